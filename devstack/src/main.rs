@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use settings::{EnvKey, Validate};
+use tokio::net::TcpListener;
+use tokio::time;
+
+use director_worker::profiles::{load_profiles, ProfileConfig};
+use director_worker::{CancellationToken, DirectorConfig, OpenMatchDirector, Worker, WorkerConfig};
+use frontend::server::FrontendConfig;
+use gameserver::server::GameServerConfig;
+use gameserver::services::StatusManager;
+use mmf::server::MmfConfig;
+use testing::{FakeAgonesSdk, FakeFleetAllocationClient, FakeOpenMatch, FLEET_LABEL};
+
+const FLEET_NAME: &str = "gameserver";
+const REGION_LABEL: &str = "region";
+
+/// Marks the fake gameserver ready and shut down, like the Agones SDK does.
+#[derive(Clone)]
+struct FakeStatusManager {
+    sdk: FakeAgonesSdk,
+}
+
+impl StatusManager for FakeStatusManager {
+    fn ready(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.sdk.set_state("Ready");
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.sdk.set_state("Shutdown");
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self.sdk.state())
+    }
+}
+
+fn spawn<F, E>(name: &'static str, server: F)
+where
+    F: Future<Output = Result<(), E>> + Send + 'static,
+    E: std::fmt::Debug,
+{
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            error!("{} stopped: {:?}", name, err);
+        }
+    });
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct DevstackConfig {
+    /// Address the gameservers listen on and report to the players.
+    host: String,
+    /// Frontend, Backend and Query share one address.
+    om_address: SocketAddr,
+    mmf_address: SocketAddr,
+    frontend_address: SocketAddr,
+    gs_count: i32,
+    gs_base_port: i32,
+    /// Region label of the gameservers. They have none when empty.
+    gs_region: String,
+    num_matching_members: usize,
+    /// Profiles file. The default profile is used when empty.
+    match_profiles_path: String,
+}
+
+impl Default for DevstackConfig {
+    fn default() -> Self {
+        DevstackConfig {
+            host: "127.0.0.1".to_string(),
+            om_address: ([127, 0, 0, 1], 50504).into(),
+            mmf_address: ([127, 0, 0, 1], 50502).into(),
+            frontend_address: ([127, 0, 0, 1], 10001).into(),
+            gs_count: 5,
+            gs_base_port: 7000,
+            gs_region: String::new(),
+            num_matching_members: 2,
+            match_profiles_path: String::new(),
+        }
+    }
+}
+
+const ENV_KEYS: &[EnvKey] = &[
+    ("HOST", "host"),
+    ("OM_ADDRESS", "om_address"),
+    ("MMF_ADDRESS", "mmf_address"),
+    ("FRONTEND_ADDRESS", "frontend_address"),
+    ("GS_COUNT", "gs_count"),
+    ("GS_BASE_PORT", "gs_base_port"),
+    ("GS_REGION", "gs_region"),
+    ("NUM_MATCHING_MEMBERS", "num_matching_members"),
+    ("MATCH_PROFILES_PATH", "match_profiles_path"),
+];
+
+impl Validate for DevstackConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.gs_count <= 0 {
+            return Err(format!("gs_count must be positive: {}", self.gs_count));
+        }
+        if self.gs_base_port <= 0 || self.gs_base_port + self.gs_count - 1 > 65535 {
+            return Err(format!(
+                "gameserver ports must be in 1-65535: {}-{}",
+                self.gs_base_port,
+                self.gs_base_port + self.gs_count - 1
+            ));
+        }
+        if self.num_matching_members == 0 {
+            return Err("num_matching_members must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Runs the frontend, MMF, director and gameservers in one process, with in-memory Open Match
+/// and Agones.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    telemetry::init("devstack")?;
+
+    let config: DevstackConfig = settings::load(ENV_KEYS)?;
+    let host = config.host;
+    let om_address = config.om_address;
+    let mmf_address = config.mmf_address;
+    let frontend_address = config.frontend_address;
+    let gs_count = config.gs_count;
+    let gs_base_port = config.gs_base_port;
+    let mut profiles = if config.match_profiles_path.is_empty() {
+        vec![ProfileConfig::default_profile()]
+    } else {
+        load_profiles(&config.match_profiles_path)?
+    };
+
+    // Frontend, Backend and Query share one address.
+    spawn("open match", FakeOpenMatch::new().serve(om_address));
+    time::delay_for(Duration::from_millis(500)).await;
+
+    let mut mmf_config = MmfConfig {
+        address: mmf_address,
+        om_query_address: om_address.to_string(),
+        ..Default::default()
+    };
+    mmf_config.default_params.team_size = config.num_matching_members;
+    let mmf = mmf::server::ServerBuilder::new(mmf_config)
+        .build()
+        .await
+        .map_err(|err| anyhow::anyhow!("cannot start mmf: {}", err))?;
+    spawn("mmf", mmf);
+
+    let frontend_config = FrontendConfig {
+        address: frontend_address,
+        om_frontend_address: om_address.to_string(),
+        ..Default::default()
+    };
+    let frontend = frontend::server::ServerBuilder::new(frontend_config)
+        .build()
+        .await
+        .map_err(|err| anyhow::anyhow!("cannot start frontend: {}", err))?;
+    spawn("frontend", frontend);
+
+    // the director authorizes its reservations with a token only the devstack knows
+    let token_path = std::env::temp_dir().join(format!("devstack-director-token-{}", gs_base_port));
+    std::fs::write(&token_path, format!("{:x}", rand::random::<u128>()))?;
+    let token_path = token_path.to_string_lossy().to_string();
+    let gs_config = GameServerConfig {
+        director_token_path: token_path.clone(),
+        ..Default::default()
+    };
+
+    let mut gameservers = Vec::new();
+    for i in 0..gs_count {
+        let port = gs_base_port + i;
+        let mut labels = HashMap::new();
+        labels.insert(FLEET_LABEL.to_string(), FLEET_NAME.to_string());
+        if !config.gs_region.is_empty() {
+            labels.insert(REGION_LABEL.to_string(), config.gs_region.clone());
+        }
+        let sdk = FakeAgonesSdk::new(&format!("gameserver-{}", i), &host, port, labels);
+        sdk.set_state("Ready");
+        let status_manager = FakeStatusManager { sdk: sdk.clone() };
+        // bound here so that the director can reach the gameserver as soon as it is allocated
+        let listener = TcpListener::bind(format!("{}:{}", host, port)).await?;
+        let gameserver = gameserver::server::ServerBuilder::new(gs_config.clone(), status_manager)
+            .listener(listener)
+            .build()
+            .await
+            .map_err(|err| anyhow::anyhow!("cannot start gameserver: {}", err))?;
+        spawn("gameserver", gameserver);
+        gameservers.push(sdk);
+    }
+
+    // every profile runs on the local MMF
+    for profile in profiles.iter_mut() {
+        profile.match_function.host = Some(mmf_address.ip().to_string());
+        profile.match_function.port = Some(mmf_address.port() as i32);
+    }
+    let alloc_client = FakeFleetAllocationClient::new(gameservers, REGION_LABEL.to_string());
+    let director = OpenMatchDirector::new(
+        alloc_client,
+        om_address.to_string(),
+        profiles,
+        "default".to_string(),
+        DirectorConfig {
+            gameserver_token_path: token_path,
+            ..Default::default()
+        },
+    )
+    .await?;
+    let mut worker = Worker::new(director, WorkerConfig::default())?;
+
+    let cancel = CancellationToken::new();
+    let signal_cancel = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            signal_cancel.cancel();
+        }
+    });
+    info!(
+        "devstack is running. frontend: {}, gameservers: {}:{}-{}",
+        frontend_address,
+        host,
+        gs_base_port,
+        gs_base_port + gs_count - 1
+    );
+    worker.run(cancel).await;
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time::delay_for;
+
+use frontend::server::FrontendConfig;
+use frontend::service::mm;
+use mmf::server::MmfConfig;
+use mmf::service::om as mmf_om;
+use testing::{om, FakeOpenMatch};
+
+fn free_address() -> SocketAddr {
+    StdTcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Returns the value of the sample of `name` with all of `labels`, or 0 if there is none.
+fn sample(name: &str, labels: &[&str]) -> f64 {
+    let metrics = String::from_utf8(telemetry::metrics::gather().unwrap()).unwrap();
+    metrics
+        .lines()
+        .filter(|line| line.starts_with(name))
+        .filter(|line| labels.iter().all(|label| line.contains(label)))
+        .filter_map(|line| line.rsplit(' ').next())
+        .map(|value| value.parse().unwrap())
+        .next()
+        .unwrap_or(0.0)
+}
+
+#[tokio::test]
+async fn runs_count_the_tickets_of_their_pools_and_the_leftover_tickets() {
+    let om_address = free_address();
+    tokio::spawn(FakeOpenMatch::new().serve(om_address));
+    delay_for(Duration::from_millis(100)).await;
+    let mut frontend = om::frontend_service_client::FrontendServiceClient::connect(format!(
+        "http://{}",
+        om_address
+    ))
+    .await
+    .unwrap();
+    for _ in 0..5 {
+        frontend
+            .create_ticket(tonic::Request::new(om::CreateTicketRequest {
+                ticket: Some(om::Ticket::default()),
+            }))
+            .await
+            .unwrap();
+    }
+
+    let mmf_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mmf_address = mmf_listener.local_addr().unwrap();
+    let mmf = mmf::server::ServerBuilder::new(MmfConfig {
+        om_query_address: om_address.to_string(),
+        ..Default::default()
+    })
+    .listener(mmf_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(mmf);
+
+    let mut client = mmf_om::match_function_client::MatchFunctionClient::connect(format!(
+        "http://{}",
+        mmf_address
+    ))
+    .await
+    .unwrap();
+    let mut stream = client
+        .run(tonic::Request::new(mmf_om::RunRequest {
+            profile: Some(mmf_om::MatchProfile {
+                name: "counted".to_string(),
+                pools: vec![
+                    mmf_om::Pool {
+                        name: "all".to_string(),
+                        ..Default::default()
+                    },
+                    mmf_om::Pool {
+                        name: "again".to_string(),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut proposals = 0;
+    while stream.message().await.unwrap().is_some() {
+        proposals += 1;
+    }
+
+    // pairs by default, and the tickets in both pools are only matched once
+    assert_eq!(proposals, 2);
+    let pool = |pool| {
+        sample(
+            "mmf_pool_tickets",
+            &["profile=\"counted\"", &format!("pool=\"{}\"", pool)],
+        )
+    };
+    assert_eq!(pool("all"), 5.0);
+    assert_eq!(pool("again"), 5.0);
+    assert_eq!(
+        sample("mmf_leftover_tickets", &["profile=\"counted\""]),
+        1.0
+    );
+}
+
+#[tokio::test]
+async fn players_who_leave_cancel_their_tickets() {
+    let om = FakeOpenMatch::new();
+    let om_address = free_address();
+    tokio::spawn(om.clone().serve(om_address));
+    delay_for(Duration::from_millis(100)).await;
+
+    let frontend_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let frontend_address = frontend_listener.local_addr().unwrap();
+    let frontend = frontend::server::ServerBuilder::new(FrontendConfig {
+        om_frontend_address: om_address.to_string(),
+        ..Default::default()
+    })
+    .listener(frontend_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(frontend);
+
+    let cancelled = || {
+        sample(
+            "frontend_tickets_finished_total",
+            &["outcome=\"cancelled\""],
+        )
+    };
+    let before = cancelled();
+    let mut client =
+        mm::frontend_client::FrontendClient::connect(format!("http://{}", frontend_address))
+            .await
+            .unwrap();
+    let stream = client
+        .create_match(tonic::Request::new(mm::CreateMatchRequest {
+            player_id: "leaver".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    delay_for(Duration::from_millis(100)).await;
+    assert_eq!(om.tickets().len(), 1);
+
+    // nobody assigns the ticket, and the player gives up
+    drop(stream);
+    drop(client);
+    delay_for(Duration::from_millis(200)).await;
+    assert!(om.tickets().is_empty());
+    assert_eq!(cancelled(), before + 1.0);
+}
+
+#[tokio::test]
+async fn tickets_that_are_not_assigned_in_time_time_out() {
+    let om = FakeOpenMatch::new();
+    let om_address = free_address();
+    tokio::spawn(om.clone().serve(om_address));
+    delay_for(Duration::from_millis(100)).await;
+
+    let frontend_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let frontend_address = frontend_listener.local_addr().unwrap();
+    let frontend = frontend::server::ServerBuilder::new(FrontendConfig {
+        om_frontend_address: om_address.to_string(),
+        ticket_timeout_ms: 200,
+        ..Default::default()
+    })
+    .listener(frontend_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(frontend);
+
+    let timed_out = || {
+        sample(
+            "frontend_tickets_finished_total",
+            &["outcome=\"timed_out\""],
+        )
+    };
+    let before = timed_out();
+    let mut client =
+        mm::frontend_client::FrontendClient::connect(format!("http://{}", frontend_address))
+            .await
+            .unwrap();
+    let mut stream = client
+        .create_match(tonic::Request::new(mm::CreateMatchRequest {
+            player_id: "waiter".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    delay_for(Duration::from_millis(100)).await;
+    assert_eq!(om.tickets().len(), 1);
+
+    // nobody assigns the ticket
+    let err = stream.message().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+    delay_for(Duration::from_millis(100)).await;
+    assert!(om.tickets().is_empty());
+    assert_eq!(timed_out(), before + 1.0);
+}
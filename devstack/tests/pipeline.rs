@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time::{delay_for, timeout};
+
+use director_worker::profiles::ProfileConfig;
+use director_worker::{CancellationToken, DirectorConfig, OpenMatchDirector, Worker, WorkerConfig};
+use frontend::server::FrontendConfig;
+use frontend::service::mm;
+use gameserver::admin::pb as admin;
+use gameserver::admin::pb::admin_client::AdminClient;
+use gameserver::server::GameServerConfig;
+use gameserver::services::pb as game;
+use gameserver::services::pb::game_client::GameClient;
+use gameserver::services::StatusManager;
+use grpc_health::reflection::pb::server_reflection_request::MessageRequest;
+use grpc_health::reflection::pb::server_reflection_response::MessageResponse;
+use grpc_health::ServingStatus;
+use mmf::server::MmfConfig;
+use testing::{FakeAgonesSdk, FakeFleetAllocationClient, FakeOpenMatch};
+
+#[derive(Clone)]
+struct FakeStatusManager {
+    sdk: FakeAgonesSdk,
+}
+
+impl StatusManager for FakeStatusManager {
+    fn ready(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.sdk.set_state("Ready");
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.sdk.set_state("Shutdown");
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self.sdk.state())
+    }
+}
+
+fn free_address() -> SocketAddr {
+    StdTcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn create_match(frontend_address: SocketAddr, player_id: &str) -> String {
+    let mut client =
+        mm::frontend_client::FrontendClient::connect(format!("http://{}", frontend_address))
+            .await
+            .unwrap();
+    let mut stream = client
+        .create_match(tonic::Request::new(mm::CreateMatchRequest {
+            player_id: player_id.to_string(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let res = stream.message().await.unwrap().unwrap();
+    res.game_server.unwrap().address
+}
+
+#[tokio::test]
+async fn players_are_matched_onto_a_gameserver() {
+    let om_address = free_address();
+    tokio::spawn(FakeOpenMatch::new().serve(om_address));
+    delay_for(Duration::from_millis(100)).await;
+
+    let mmf_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mmf_address = mmf_listener.local_addr().unwrap();
+    let mmf = mmf::server::ServerBuilder::new(MmfConfig {
+        om_query_address: om_address.to_string(),
+        ..Default::default()
+    })
+    .listener(mmf_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(mmf);
+
+    let frontend_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let frontend_address = frontend_listener.local_addr().unwrap();
+    let frontend = frontend::server::ServerBuilder::new(FrontendConfig {
+        om_frontend_address: om_address.to_string(),
+        ..Default::default()
+    })
+    .listener(frontend_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(frontend);
+
+    let gs_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let gs_port = gs_listener.local_addr().unwrap().port();
+    let token_path = token_file("director-token", gs_port);
+    let sdk = FakeAgonesSdk::new("gameserver", "127.0.0.1", gs_port as i32, HashMap::new());
+    sdk.set_state("Ready");
+    let gameserver = gameserver::server::ServerBuilder::new(
+        GameServerConfig {
+            director_token_path: token_path.clone(),
+            ..Default::default()
+        },
+        FakeStatusManager { sdk: sdk.clone() },
+    )
+    .listener(gs_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(gameserver);
+
+    let mut profile = ProfileConfig::default_profile();
+    profile.match_function.host = Some(mmf_address.ip().to_string());
+    profile.match_function.port = Some(mmf_address.port() as i32);
+    let director = OpenMatchDirector::new(
+        FakeFleetAllocationClient::new(vec![sdk.clone()], "region".to_string()),
+        om_address.to_string(),
+        vec![profile],
+        "default".to_string(),
+        DirectorConfig {
+            gameserver_token_path: token_path.clone(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut worker = Worker::new(
+        director,
+        WorkerConfig {
+            interval: Duration::from_millis(100),
+            jitter: Duration::from_millis(0),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let cancel = CancellationToken::new();
+    let worker_cancel = cancel.clone();
+    tokio::spawn(async move { worker.run(worker_cancel).await });
+
+    let (a, b) = timeout(
+        Duration::from_secs(10),
+        futures::future::join(
+            create_match(frontend_address, "player-a"),
+            create_match(frontend_address, "player-b"),
+        ),
+    )
+    .await
+    .unwrap();
+    cancel.cancel();
+
+    assert_eq!(a, b);
+    assert!(a.ends_with(&format!("127.0.0.1:{}", gs_port)));
+    assert_eq!(sdk.state(), "Allocated");
+    std::fs::remove_file(&token_path).unwrap();
+}
+
+fn token_file(name: &str, port: u16) -> String {
+    let path = std::env::temp_dir().join(format!("{}-{}", name, port));
+    std::fs::write(&path, "secret\n").unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+async fn start_gameserver(state: &str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let sdk = FakeAgonesSdk::new(
+        "gameserver",
+        "127.0.0.1",
+        address.port() as i32,
+        HashMap::new(),
+    );
+    sdk.set_state(state);
+    let gameserver = gameserver::server::ServerBuilder::new(
+        GameServerConfig::default(),
+        FakeStatusManager { sdk },
+    )
+    .listener(listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(gameserver);
+    address
+}
+
+async fn health(address: SocketAddr, service: &str) -> ServingStatus {
+    let mut client =
+        grpc_health::pb::health_client::HealthClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+    let res = client
+        .check(grpc_health::pb::HealthCheckRequest {
+            service: service.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    ServingStatus::from_i32(res.status).unwrap()
+}
+
+#[tokio::test]
+async fn gameservers_report_their_readiness() {
+    let ready = start_gameserver("Ready").await;
+    let shutdown = start_gameserver("Shutdown").await;
+    delay_for(Duration::from_millis(100)).await;
+
+    assert_eq!(health(ready, "").await, ServingStatus::Serving);
+    assert_eq!(health(ready, "game.Game").await, ServingStatus::Serving);
+    assert_eq!(
+        health(shutdown, "game.Game").await,
+        ServingStatus::NotServing
+    );
+
+    let mut client =
+        grpc_health::reflection::pb::server_reflection_client::ServerReflectionClient::connect(
+            format!("http://{}", ready),
+        )
+        .await
+        .unwrap();
+    let request = grpc_health::reflection::pb::ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let res = client
+        .server_reflection_info(futures::stream::iter(vec![request]))
+        .await
+        .unwrap()
+        .into_inner()
+        .message()
+        .await
+        .unwrap()
+        .unwrap();
+    match res.message_response {
+        Some(MessageResponse::ListServicesResponse(list)) => {
+            assert!(list.service.iter().any(|s| s.name == "game.Game"));
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+}
+
+async fn join(
+    client: &mut GameClient<tonic::transport::Channel>,
+    match_id: &str,
+    player_id: &str,
+) -> Result<tonic::Streaming<game::Message>, tonic::Status> {
+    join_with(client, match_id, player_id, futures::stream::pending()).await
+}
+
+/// Joins with `messages` as the messages of the player, who leaves when it ends.
+async fn join_with<S>(
+    client: &mut GameClient<tonic::transport::Channel>,
+    match_id: &str,
+    player_id: &str,
+    messages: S,
+) -> Result<tonic::Streaming<game::Message>, tonic::Status>
+where
+    S: futures::Stream<Item = game::Message> + Send + Sync + 'static,
+{
+    let mut request = tonic::Request::new(messages);
+    request
+        .metadata_mut()
+        .insert("match_id", match_id.parse().unwrap());
+    request
+        .metadata_mut()
+        .insert("player_id", player_id.parse().unwrap());
+    client.join(request).await.map(|res| res.into_inner())
+}
+
+fn authorized<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer secret".parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn operators_act_on_running_matches() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let admin_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_address = admin_listener.local_addr().unwrap();
+    let token_path = token_file("admin-token", admin_address.port());
+    let sdk = FakeAgonesSdk::new(
+        "gameserver",
+        "127.0.0.1",
+        address.port() as i32,
+        HashMap::new(),
+    );
+    sdk.set_state("Allocated");
+    let gameserver = gameserver::server::ServerBuilder::new(
+        GameServerConfig {
+            admin_token_path: token_path.clone(),
+            ..Default::default()
+        },
+        FakeStatusManager { sdk: sdk.clone() },
+    )
+    .listener(listener)
+    .admin_listener(admin_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(gameserver);
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = GameClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    let mut a = join(&mut client, "match-1", "player-a").await.unwrap();
+    let mut b = join(&mut client, "match-1", "player-b").await.unwrap();
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut admin = AdminClient::connect(format!("http://{}", admin_address))
+        .await
+        .unwrap();
+    let err = admin
+        .list_matches(admin::ListMatchesRequest {})
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+    let res = admin
+        .list_matches(authorized(admin::ListMatchesRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(res.matches.len(), 1);
+    let mut players: Vec<_> = res.matches[0].players.iter().map(|p| &p.id[..]).collect();
+    players.sort();
+    assert_eq!(players, vec!["player-a", "player-b"]);
+
+    admin
+        .broadcast(authorized(admin::BroadcastRequest {
+            match_id: String::new(),
+            body: b"maintenance".to_vec(),
+        }))
+        .await
+        .unwrap();
+    for stream in &mut [&mut a, &mut b] {
+        let message = stream.message().await.unwrap().unwrap();
+        assert_eq!(message.body, b"maintenance".to_vec());
+        assert!(message.system);
+    }
+
+    admin
+        .kick_player(authorized(admin::KickPlayerRequest {
+            match_id: "match-1".to_string(),
+            player_id: "player-b".to_string(),
+            reason: "cheating".to_string(),
+        }))
+        .await
+        .unwrap();
+    let err = b.message().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Aborted);
+    assert!(err.message().contains("cheating"));
+    let err = join(&mut client, "match-1", "player-b").await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+    admin
+        .set_draining(authorized(admin::SetDrainingRequest { draining: true }))
+        .await
+        .unwrap();
+    let err = join(&mut client, "match-2", "player-c").await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+
+    admin
+        .end_match(authorized(admin::EndMatchRequest {
+            match_id: "match-1".to_string(),
+            reason: "server update".to_string(),
+        }))
+        .await
+        .unwrap();
+    let err = a.message().await.unwrap_err();
+    assert!(err.message().contains("server update"));
+    delay_for(Duration::from_millis(100)).await;
+
+    let res = admin
+        .list_matches(authorized(admin::ListMatchesRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(res.matches.is_empty());
+    assert!(res.draining);
+    // the last match of a draining gameserver is over
+    assert_eq!(sdk.state(), "Shutdown");
+    std::fs::remove_file(&token_path).unwrap();
+}
+
+#[tokio::test]
+async fn idle_gameservers_keep_running_while_draining() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let admin_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_address = admin_listener.local_addr().unwrap();
+    let token_path = token_file("admin-token", admin_address.port());
+    let sdk = FakeAgonesSdk::new(
+        "gameserver",
+        "127.0.0.1",
+        address.port() as i32,
+        HashMap::new(),
+    );
+    sdk.set_state("Ready");
+    let gameserver = gameserver::server::ServerBuilder::new(
+        GameServerConfig {
+            admin_token_path: token_path.clone(),
+            ..Default::default()
+        },
+        FakeStatusManager { sdk: sdk.clone() },
+    )
+    .listener(listener)
+    .admin_listener(admin_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(gameserver);
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut admin = AdminClient::connect(format!("http://{}", admin_address))
+        .await
+        .unwrap();
+    admin
+        .set_draining(authorized(admin::SetDrainingRequest { draining: true }))
+        .await
+        .unwrap();
+    delay_for(Duration::from_millis(100)).await;
+    assert_eq!(sdk.state(), "Ready");
+    let mut client = GameClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    let err = join(&mut client, "match-1", "player-a").await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+
+    admin
+        .set_draining(authorized(admin::SetDrainingRequest { draining: false }))
+        .await
+        .unwrap();
+    join(&mut client, "match-1", "player-a").await.unwrap();
+    std::fs::remove_file(&token_path).unwrap();
+}
+
+async fn open_slots(client: &mut GameClient<tonic::transport::Channel>) -> i32 {
+    client
+        .get_server_info(game::GetServerInfoRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .backfills
+        .iter()
+        .map(|backfill| backfill.open_slots)
+        .sum()
+}
+
+#[tokio::test]
+async fn seats_are_freed_when_players_leave_or_never_join() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let token_path = token_file("director-token", address.port());
+    let sdk = FakeAgonesSdk::new(
+        "gameserver",
+        "127.0.0.1",
+        address.port() as i32,
+        HashMap::new(),
+    );
+    sdk.set_state("Allocated");
+    let gameserver = gameserver::server::ServerBuilder::new(
+        GameServerConfig {
+            director_token_path: token_path.clone(),
+            reservation_ttl_ms: 500,
+            ..Default::default()
+        },
+        FakeStatusManager { sdk },
+    )
+    .listener(listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(gameserver);
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = GameClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    client
+        .reserve_match(authorized(game::ReserveMatchRequest {
+            match_id: "match-1".to_string(),
+            match_profile: "default".to_string(),
+            teams: vec![game::Team {
+                player_ids: vec![
+                    "player-a".to_string(),
+                    "player-b".to_string(),
+                    "player-c".to_string(),
+                ],
+            }],
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+    // the reserved match counts before anybody joins
+    let info = client
+        .get_server_info(game::GetServerInfoRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.number_of_matches, 1);
+    let (leave, messages) = tokio::sync::mpsc::channel::<game::Message>(1);
+    let _a = join_with(&mut client, "match-1", "player-a", messages)
+        .await
+        .unwrap();
+    let _b = join(&mut client, "match-1", "player-b").await.unwrap();
+    delay_for(Duration::from_millis(100)).await;
+    // player-c holds their seat until the reservation expires
+    assert_eq!(open_slots(&mut client).await, 0);
+
+    drop(leave);
+    delay_for(Duration::from_millis(150)).await;
+    assert_eq!(open_slots(&mut client).await, 1);
+
+    delay_for(Duration::from_millis(1000)).await;
+    assert_eq!(open_slots(&mut client).await, 2);
+    std::fs::remove_file(&token_path).unwrap();
+}
+
+#[tokio::test]
+async fn only_the_director_reserves_matches() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let admin_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_address = admin_listener.local_addr().unwrap();
+    let token_path = token_file("director-token", address.port());
+    let sdk = FakeAgonesSdk::new(
+        "gameserver",
+        "127.0.0.1",
+        address.port() as i32,
+        HashMap::new(),
+    );
+    sdk.set_state("Allocated");
+    let gameserver = gameserver::server::ServerBuilder::new(
+        GameServerConfig {
+            director_token_path: token_path.clone(),
+            admin_token_path: token_path.clone(),
+            reservation_ttl_ms: 200,
+            ..Default::default()
+        },
+        FakeStatusManager { sdk },
+    )
+    .listener(listener)
+    .admin_listener(admin_listener)
+    .build()
+    .await
+    .unwrap();
+    tokio::spawn(gameserver);
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = GameClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    let reserve = |match_id: &str| game::ReserveMatchRequest {
+        match_id: match_id.to_string(),
+        match_profile: "default".to_string(),
+        teams: vec![game::Team {
+            player_ids: vec!["player-a".to_string(), "player-b".to_string()],
+        }],
+        ..Default::default()
+    };
+    let err = client.reserve_match(reserve("match-1")).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+    let err = client
+        .backfill_match(game::BackfillMatchRequest {
+            match_id: "match-1".to_string(),
+            player_ids: vec!["player-c".to_string()],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+    client
+        .reserve_match(authorized(reserve("match-1")))
+        .await
+        .unwrap();
+    client
+        .reserve_match(authorized(reserve("match-2")))
+        .await
+        .unwrap();
+    let _a = join(&mut client, "match-1", "player-a").await.unwrap();
+    // nobody joined the second match in time, so it runs without its reservation
+    delay_for(Duration::from_millis(300)).await;
+    let _b = join(&mut client, "match-2", "player-a").await.unwrap();
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut admin = AdminClient::connect(format!("http://{}", admin_address))
+        .await
+        .unwrap();
+    let mut matches: Vec<_> = admin
+        .list_matches(authorized(admin::ListMatchesRequest {}))
+        .await
+        .unwrap()
+        .into_inner()
+        .matches
+        .into_iter()
+        .map(|m| (m.match_id, m.match_profile))
+        .collect();
+    matches.sort();
+    assert_eq!(
+        matches,
+        vec![
+            ("match-1".to_string(), "default".to_string()),
+            ("match-2".to_string(), String::new()),
+        ]
+    );
+    std::fs::remove_file(&token_path).unwrap();
+}
@@ -0,0 +1,90 @@
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::time::delay_for;
+
+use mmf::server::MmfConfig;
+use mmf::service::om as mmf_om;
+use testing::{om, FakeOpenMatch};
+
+fn free_address() -> SocketAddr {
+    StdTcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn proposals(mmf_address: SocketAddr) -> usize {
+    let mut client = mmf_om::match_function_client::MatchFunctionClient::connect(format!(
+        "http://{}",
+        mmf_address
+    ))
+    .await
+    .unwrap();
+    let mut stream = client
+        .run(tonic::Request::new(mmf_om::RunRequest {
+            profile: Some(mmf_om::MatchProfile {
+                name: "default".to_string(),
+                pools: vec![mmf_om::Pool {
+                    name: "all".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut proposals = 0;
+    while stream.message().await.unwrap().is_some() {
+        proposals += 1;
+    }
+    proposals
+}
+
+#[tokio::test]
+async fn reloaded_match_params_are_used() {
+    let om_address = free_address();
+    tokio::spawn(FakeOpenMatch::new().serve(om_address));
+    delay_for(Duration::from_millis(100)).await;
+    let mut frontend = om::frontend_service_client::FrontendServiceClient::connect(format!(
+        "http://{}",
+        om_address
+    ))
+    .await
+    .unwrap();
+    for _ in 0..4 {
+        frontend
+            .create_ticket(tonic::Request::new(om::CreateTicketRequest {
+                ticket: Some(om::Ticket::default()),
+            }))
+            .await
+            .unwrap();
+    }
+
+    let config = MmfConfig {
+        om_query_address: om_address.to_string(),
+        ..Default::default()
+    };
+    let (tx, rx) = watch::channel(config.clone());
+    let mmf_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mmf_address = mmf_listener.local_addr().unwrap();
+    let mmf = mmf::server::ServerBuilder::new(config.clone())
+        .config_updates(rx)
+        .listener(mmf_listener)
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(mmf);
+
+    // pairs by default
+    assert_eq!(proposals(mmf_address).await, 2);
+
+    let mut reloaded = config;
+    reloaded.default_params.team_size = 4;
+    tx.broadcast(reloaded).unwrap();
+    delay_for(Duration::from_millis(100)).await;
+    assert_eq!(proposals(mmf_address).await, 1);
+}
@@ -1,14 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::marker::{Send, Sync};
-use std::time::Duration;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use futures::StreamExt;
 use http::header::HeaderValue;
-use log::debug;
+use prost::Message;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Mutex};
 use tokio::time;
+use tracing::{debug, error, field, info, info_span, warn};
+use tracing_futures::Instrument;
 
-use gameserver_client::GameServerClient;
+use gameserver_client::{GameServerClient, GameServerClientImpl};
+use grpc_tls::{TlsConfig, TokenFile};
 
 use kube::{
     api::{PostParams, RawApi},
@@ -20,6 +29,110 @@ pub mod om {
     tonic::include_proto!("openmatch");
 }
 
+pub mod metrics;
+pub mod profiles;
+
+use profiles::ProfileConfig;
+
+// Match extensions set by the MMF with the player ids of each team and the region.
+const TEAMS_EXTENSION: &str = "teams";
+const REGION_EXTENSION: &str = "region";
+// Set by the MMF on matches that fill the open seats of a running match.
+const BACKFILL_EXTENSION: &str = "backfill";
+// Profile extension listing the running matches that need players.
+const BACKFILLS_EXTENSION: &str = "backfills";
+// Ticket search fields set by the frontend. A party ticket is rated by the mean of its members.
+const SKILL_RATING_ARG: &str = "skill_rating";
+const PARTY_SIZE_ARG: &str = "party_size";
+
+/// Mean skill rating of the players of the tickets.
+fn mean_skill_rating(tickets: &[om::Ticket]) -> f64 {
+    let (players, total) = tickets
+        .iter()
+        .filter_map(|ticket| ticket.search_fields.as_ref())
+        .fold((0.0, 0.0), |(players, total), fields| {
+            let size = fields
+                .double_args
+                .get(PARTY_SIZE_ARG)
+                .cloned()
+                .unwrap_or(1.0)
+                .max(1.0);
+            let rating = fields
+                .double_args
+                .get(SKILL_RATING_ARG)
+                .cloned()
+                .unwrap_or(0.0);
+            (players + size, total + rating * size)
+        });
+    if players > 0.0 {
+        total / players
+    } else {
+        0.0
+    }
+}
+
+fn decode_teams(any: &prost_types::Any) -> anyhow::Result<Vec<Vec<String>>> {
+    let list = prost_types::ListValue::decode(any.value.as_slice())?;
+    let teams = list
+        .values
+        .into_iter()
+        .map(|team| match team.kind {
+            Some(prost_types::value::Kind::ListValue(players)) => om_ext::strings(players),
+            _ => Vec::new(),
+        })
+        .collect();
+    Ok(teams)
+}
+
+fn backfills_extension(backfills: &[&Backfill]) -> anyhow::Result<prost_types::Any> {
+    let values = backfills
+        .iter()
+        .map(|backfill| {
+            let mut fields = BTreeMap::new();
+            fields.insert(
+                "match_id".to_string(),
+                om_ext::string_value(backfill.match_id.clone()),
+            );
+            fields.insert(
+                "open_slots".to_string(),
+                prost_types::Value {
+                    kind: Some(prost_types::value::Kind::NumberValue(
+                        backfill.open_slots as f64,
+                    )),
+                },
+            );
+            fields.insert(
+                "region".to_string(),
+                om_ext::string_value(backfill.region.clone()),
+            );
+            fields.insert(
+                "skill_rating".to_string(),
+                prost_types::Value {
+                    kind: Some(prost_types::value::Kind::NumberValue(backfill.skill_rating)),
+                },
+            );
+            prost_types::Value {
+                kind: Some(prost_types::value::Kind::StructValue(prost_types::Struct {
+                    fields,
+                })),
+            }
+        })
+        .collect();
+    Ok(om_ext::list_any(values)?)
+}
+
+/// Returns the `host:port` of an allocated gameserver.
+fn status_address(status: Status) -> anyhow::Result<String> {
+    let host = status.address.ok_or(anyhow::anyhow!("host is empty"))?;
+    let port = status
+        .ports
+        .ok_or(anyhow::anyhow!("port is empty"))?
+        .first()
+        .ok_or(anyhow::anyhow!("port is empty"))?
+        .port;
+    Ok(format!("{}:{}", host, port))
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AllocateResponse {
@@ -36,6 +149,16 @@ pub struct Status {
     address: Option<String>,
 }
 
+impl Status {
+    pub fn new(state: String, address: String, ports: Vec<Port>) -> Self {
+        Status {
+            state,
+            ports: Some(ports),
+            address: Some(address),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Port {
@@ -43,6 +166,12 @@ pub struct Port {
     port: i32,
 }
 
+impl Port {
+    pub fn new(name: String, port: i32) -> Self {
+        Port { name, port }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AllocateRequest {
@@ -55,65 +184,264 @@ pub struct AllocateRequest {
 #[serde(rename_all = "camelCase")]
 pub struct Spec {
     required: Required,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    preferred: Vec<Required>,
+    scheduling: SchedulingStrategy,
+    #[serde(default)]
+    metadata: MetaPatch,
 }
 
+/// A label selector.
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Required {
     match_labels: HashMap<String, String>,
 }
 
+/// Labels and annotations added to the allocated gameserver.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetaPatch {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    labels: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SchedulingStrategy {
+    Packed,
+    Distributed,
+}
+
+impl FromStr for SchedulingStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Packed" => Ok(SchedulingStrategy::Packed),
+            "Distributed" => Ok(SchedulingStrategy::Distributed),
+            _ => Err(anyhow::anyhow!("invalid scheduling strategy: {}", s)),
+        }
+    }
+}
+
+/// Annotation holding the id of the match running on an allocated gameserver.
+pub const MATCH_ID_ANNOTATION: &str = "matchmaker/match-id";
+
+/// Which gameservers `AgonesGameServerAllocationClient` allocates and how it marks them.
+#[derive(Clone, Debug)]
+pub struct AllocationPolicy {
+    /// Fleet to allocate from, unless the profile of the match names another one.
+    pub fleet_name: String,
+    /// Labels the gameserver must have besides the fleet and region.
+    pub selectors: HashMap<String, String>,
+    /// Label selectors tried in order before the required ones.
+    pub preferred_selectors: Vec<HashMap<String, String>>,
+    pub scheduling: SchedulingStrategy,
+    /// Label holding the region of the gameserver.
+    pub region_label: String,
+    /// Labels and annotations put on the allocated gameserver.
+    pub labels: HashMap<String, String>,
+    pub annotations: HashMap<String, String>,
+}
+
+impl Default for AllocationPolicy {
+    fn default() -> Self {
+        AllocationPolicy {
+            fleet_name: "gameserver".to_string(),
+            selectors: HashMap::new(),
+            preferred_selectors: Vec::new(),
+            scheduling: SchedulingStrategy::Packed,
+            region_label: "region".to_string(),
+            labels: HashMap::new(),
+            annotations: HashMap::new(),
+        }
+    }
+}
+
+/// Parses labels written as `key1=value1,key2=value2`.
+pub fn parse_labels(s: &str) -> anyhow::Result<HashMap<String, String>> {
+    s.split(',')
+        .map(|label| label.trim())
+        .filter(|label| !label.is_empty())
+        .map(|label| {
+            let mut kv = label.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if !key.is_empty() => {
+                    Ok((key.to_string(), value.to_string()))
+                }
+                _ => Err(anyhow::anyhow!("invalid label: {}", label)),
+            }
+        })
+        .collect()
+}
+
+/// What the match needs from the gameserver it is allocated to.
+#[derive(Clone, Debug, Default)]
+pub struct AllocationParams {
+    pub match_id: String,
+    /// Region the MMF picked for the players, if any.
+    pub region: Option<String>,
+    /// Fleet of the profile of the match, if it overrides the default one.
+    pub fleet: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AllocationError {
+    /// No gameserver can take the match right now, e.g. the fleet or gameserver is full.
+    NoCapacity(String),
+    /// The allocation API failed or returned something unexpected.
+    Api(anyhow::Error),
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocationError::NoCapacity(reason) => write!(f, "no capacity: {}", reason),
+            AllocationError::Api(err) => write!(f, "allocation api error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+/// A running match on an allocated gameserver that needs `open_slots` more players.
+#[derive(Clone, Debug)]
+pub struct Backfill {
+    pub match_id: String,
+    pub match_profile: String,
+    pub open_slots: i32,
+    /// `host:port` of the gameserver.
+    pub address: String,
+    /// Region of the match, empty for any region.
+    pub region: String,
+    /// Mean skill rating of the players the match was made with.
+    pub skill_rating: f64,
+}
+
 #[async_trait]
 pub trait GameServerAllocationClient {
-    async fn allocate(&mut self) -> anyhow::Result<Status>;
+    async fn allocate(&self, params: &AllocationParams) -> Result<Status, AllocationError>;
+
+    /// Running matches that need more players. Only clients that keep track of the
+    /// gameservers they allocated can report them.
+    async fn backfills(&self) -> anyhow::Result<Vec<Backfill>> {
+        Ok(vec![])
+    }
 }
 
 pub struct AgonesGameServerAllocationClient {
     k8s_api_client: APIClient,
     k8s_namespace: String,
+    policy: watch::Receiver<AllocationPolicy>,
 }
 
 impl AgonesGameServerAllocationClient {
-    pub fn new(k8s_namespace: String) -> anyhow::Result<Self> {
+    pub fn new(k8s_namespace: String, policy: AllocationPolicy) -> anyhow::Result<Self> {
         let config = config::incluster_config()?;
         let client = APIClient::new(config);
         Ok(AgonesGameServerAllocationClient {
             k8s_api_client: client,
             k8s_namespace: k8s_namespace,
+            policy: watch::channel(policy).1,
         })
     }
-}
 
-#[async_trait]
-impl GameServerAllocationClient for AgonesGameServerAllocationClient {
-    async fn allocate(&mut self) -> anyhow::Result<Status> {
-        let pp = PostParams::default();
-        let custom_resource = RawApi::customResource("gameserverallocations")
-            .version("v1")
-            .group("allocation.agones.dev")
-            .within(&self.k8s_namespace);
+    /// Allocates through the API server at `base_path` instead of the one of the cluster the
+    /// director runs in, e.g. `http://127.0.0.1:8001` of `kubectl proxy`.
+    pub fn with_api_server(
+        base_path: String,
+        k8s_namespace: String,
+        policy: AllocationPolicy,
+    ) -> Self {
+        let config = config::Configuration::new(base_path, reqwest::Client::new());
+        AgonesGameServerAllocationClient {
+            k8s_api_client: APIClient::new(config),
+            k8s_namespace: k8s_namespace,
+            policy: watch::channel(policy).1,
+        }
+    }
+
+    /// Allocates with the latest policy sent on `policy` from now on.
+    pub fn with_policy_updates(mut self, policy: watch::Receiver<AllocationPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn allocate_request(&self, params: &AllocationParams) -> AllocateRequest {
+        let policy = self.policy.borrow();
+        // the fleet and region also bound the preferred gameservers
         let mut labels = HashMap::new();
-        labels.insert("agones.dev/fleet".to_string(), "gameserver".to_string());
+        labels.insert(
+            "agones.dev/fleet".to_string(),
+            params.fleet.clone().unwrap_or(policy.fleet_name.clone()),
+        );
+        if let Some(region) = &params.region {
+            labels.insert(policy.region_label.clone(), region.clone());
+        }
+        let preferred = policy
+            .preferred_selectors
+            .iter()
+            .map(|selector| {
+                let mut match_labels = selector.clone();
+                match_labels.extend(labels.clone());
+                Required {
+                    match_labels: match_labels,
+                }
+            })
+            .collect();
+        let mut match_labels = policy.selectors.clone();
+        match_labels.extend(labels);
 
-        let req = AllocateRequest {
+        let mut annotations = policy.annotations.clone();
+        annotations.insert(MATCH_ID_ANNOTATION.to_string(), params.match_id.clone());
+
+        AllocateRequest {
             api_version: "allocation.agones.dev/v1".to_string(),
             kind: "GameServerAllocation".to_string(),
             spec: Spec {
                 required: Required {
-                    match_labels: labels,
+                    match_labels: match_labels,
+                },
+                preferred: preferred,
+                scheduling: policy.scheduling,
+                metadata: MetaPatch {
+                    labels: policy.labels.clone(),
+                    annotations: annotations,
                 },
             },
-        };
-        let mut request = custom_resource.create(&pp, serde_json::to_vec(&req)?)?;
+        }
+    }
+}
+
+#[async_trait]
+impl GameServerAllocationClient for AgonesGameServerAllocationClient {
+    async fn allocate(&self, params: &AllocationParams) -> Result<Status, AllocationError> {
+        let pp = PostParams::default();
+        let custom_resource = RawApi::customResource("gameserverallocations")
+            .version("v1")
+            .group("allocation.agones.dev")
+            .within(&self.k8s_namespace);
+        let req = self.allocate_request(params);
+        let body = serde_json::to_vec(&req).map_err(|err| AllocationError::Api(err.into()))?;
+        let mut request = custom_resource
+            .create(&pp, body)
+            .map_err(|err| AllocationError::Api(anyhow::anyhow!("{:?}", err)))?;
         request
             .headers_mut()
             .insert("Content-Type", HeaderValue::from_static("application/json"));
+        telemetry::inject(request.headers_mut());
 
         let res = self
             .k8s_api_client
             .request::<AllocateResponse>(request)
             .await
-            .map_err(|err| anyhow::anyhow!("{:?}", err))?;
+            .map_err(|err| AllocationError::Api(anyhow::anyhow!("{:?}", err)))?;
+        // UnAllocated when no gameserver is ready, Contention when they were all taken
+        if &*res.status.state != "Allocated" {
+            return Err(AllocationError::NoCapacity(res.status.state));
+        }
         Ok(res.status)
     }
 }
@@ -125,6 +453,7 @@ where
     agones_sdk: agones::Sdk,
     gameserver_client: T,
     max_allocate: i32,
+    allocate_lock: Mutex<()>,
 }
 
 impl<T> AgonesSDKSelfAllocationClient<T>
@@ -136,6 +465,7 @@ where
             agones_sdk: sdk,
             gameserver_client: gameserver_client,
             max_allocate: max_allocate,
+            allocate_lock: Mutex::new(()),
         }
     }
 }
@@ -145,36 +475,45 @@ impl<T> GameServerAllocationClient for AgonesSDKSelfAllocationClient<T>
 where
     T: GameServerClient + Sync + Send,
 {
-    async fn allocate(&mut self) -> anyhow::Result<Status> {
+    // This gameserver runs in one region, so the region of the match is not checked.
+    async fn allocate(&self, _params: &AllocationParams) -> Result<Status, AllocationError> {
+        // the capacity check and the allocation must not interleave. The gameserver counts
+        // the matches reserved on it, so matches nobody joined yet take their slot
+        let _guard = self.allocate_lock.lock().await;
         let num_matches = self
             .gameserver_client
             .get_number_of_matches()
             .await
-            .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+            .map_err(|err| AllocationError::Api(anyhow::anyhow!(err.to_string())))?;
         if self.max_allocate <= num_matches {
-            return Err(anyhow::anyhow!("gamesever is full"));
+            return Err(AllocationError::NoCapacity("gamesever is full".to_string()));
         }
 
         let gs = self
             .agones_sdk
             .get_gameserver()
-            .map_err(|err| anyhow::anyhow!("{:?}", err))?;
-        let mut status = gs.status.ok_or(anyhow::anyhow!("empty status"))?;
+            .map_err(|err| AllocationError::Api(anyhow::anyhow!("{:?}", err)))?;
+        let mut status = gs
+            .status
+            .ok_or(AllocationError::Api(anyhow::anyhow!("empty status")))?;
         if &status.state != "Allocated" {
             self.agones_sdk
                 .allocate()
-                .map_err(|err| anyhow::anyhow!("{:?}", err))?;
+                .map_err(|err| AllocationError::Api(anyhow::anyhow!("{:?}", err)))?;
             status = self
                 .agones_sdk
                 .get_gameserver()
-                .map_err(|err| anyhow::anyhow!("{:?}", err))?
+                .map_err(|err| AllocationError::Api(anyhow::anyhow!("{:?}", err)))?
                 .status
-                .ok_or(anyhow::anyhow!("empty status"))?;
+                .ok_or(AllocationError::Api(anyhow::anyhow!("empty status")))?;
+            if &status.state != "Allocated" {
+                return Err(AllocationError::NoCapacity(status.state));
+            }
         }
         let port = status
             .ports
             .first()
-            .ok_or(anyhow::anyhow!("port is empty"))?;
+            .ok_or(AllocationError::Api(anyhow::anyhow!("port is empty")))?;
         let port = Port {
             name: port.name.clone(),
             port: port.port,
@@ -185,11 +524,217 @@ where
             address: Some(status.address),
         })
     }
+
+    async fn backfills(&self) -> anyhow::Result<Vec<Backfill>> {
+        let backfills = self
+            .gameserver_client
+            .get_backfills()
+            .await
+            .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+        if backfills.is_empty() {
+            return Ok(vec![]);
+        }
+        let status = self
+            .agones_sdk
+            .get_gameserver()
+            .map_err(|err| anyhow::anyhow!("{:?}", err))?
+            .status
+            .ok_or(anyhow::anyhow!("empty status"))?;
+        let port = status
+            .ports
+            .first()
+            .ok_or(anyhow::anyhow!("port is empty"))?;
+        let address = format!("{}:{}", status.address, port.port);
+        Ok(backfills
+            .into_iter()
+            .map(|b| Backfill {
+                match_id: b.match_id,
+                match_profile: b.match_profile,
+                open_slots: b.open_slots,
+                address: address.clone(),
+                region: b.region,
+                skill_rating: b.skill_rating,
+            })
+            .collect())
+    }
+}
+
+/// Tells a `Worker` to stop. Clones share the same state.
+#[derive(Clone)]
+pub struct CancellationToken {
+    tx: Arc<watch::Sender<bool>>,
+    rx: watch::Receiver<bool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        let (tx, rx) = watch::channel(false);
+        CancellationToken {
+            tx: Arc::new(tx),
+            rx: rx,
+        }
+    }
+
+    pub fn cancel(&self) {
+        // the token holds a receiver, so this can't fail
+        let _ = self.tx.broadcast(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once the token is cancelled.
+    pub async fn cancelled(&self) {
+        let mut rx = self.rx.clone();
+        while let Some(cancelled) = rx.recv().await {
+            if cancelled {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What happened to the matches of one director cycle.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CycleSummary {
+    pub fetched: usize,
+    pub assigned: usize,
+    pub failed: usize,
+}
+
+impl fmt::Display for CycleSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fetched: {}, assigned: {}, failed: {}",
+            self.fetched, self.assigned, self.failed
+        )
+    }
 }
 
 #[async_trait]
 pub trait Director {
-    async fn assign(&mut self) -> anyhow::Result<()>;
+    /// Runs one cycle. Once `cancel` is cancelled, the matches being placed are finished and
+    /// the tickets of the remaining matches are released.
+    async fn assign(&mut self, cancel: &CancellationToken) -> anyhow::Result<CycleSummary>;
+}
+
+/// How often and how fast the director retries to allocate a gameserver for a match.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts per match, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Wait before retrying when no gameserver has capacity. A gameserver only frees up once
+    /// the fleet scales or a match ends, so this doesn't grow with the API error backoff.
+    /// Zero doesn't retry, and the tickets go back to the pool for the next cycle.
+    pub no_capacity_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+            no_capacity_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Time to wait after the `attempt`-th failed attempt, counted from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let backoff = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+    }
+
+    /// Time to wait after the `attempt`-th attempt failed with `err`, or `None` if it isn't
+    /// retried.
+    pub fn retry_after(&self, attempt: u32, err: &AllocationError) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        match err {
+            AllocationError::Api(_) => Some(self.backoff(attempt)),
+            AllocationError::NoCapacity(_) if self.no_capacity_backoff > Duration::from_secs(0) => {
+                Some(self.no_capacity_backoff)
+            }
+            AllocationError::NoCapacity(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DirectorConfig {
+    pub retry_policy: RetryPolicy,
+    /// Matches allocated and reserved at the same time.
+    pub max_concurrent_assignments: usize,
+    /// Matches assigned by one `AssignTickets` call.
+    pub assign_batch_size: usize,
+    /// mTLS to the Open Match backend.
+    pub om_tls: TlsConfig,
+    /// mTLS to the gameservers matches are reserved on.
+    pub gameserver_tls: TlsConfig,
+    /// Token the gameservers require to reserve and backfill matches.
+    pub gameserver_token_path: String,
+}
+
+impl Default for DirectorConfig {
+    fn default() -> Self {
+        DirectorConfig {
+            retry_policy: RetryPolicy::default(),
+            max_concurrent_assignments: 16,
+            assign_batch_size: 32,
+            om_tls: TlsConfig::default(),
+            gameserver_tls: TlsConfig::default(),
+            gameserver_token_path: String::new(),
+        }
+    }
+}
+
+type BackendClient =
+    om::backend_service_client::BackendServiceClient<telemetry::Traced<tonic::transport::Channel>>;
+
+/// The profiles the director fetches matches for.
+#[derive(Clone, Debug, Default)]
+pub struct MatchProfiles {
+    fetch_matches_requests: Vec<om::FetchMatchesRequest>,
+    // fleet of each profile that overrides the default one
+    fleets: HashMap<String, String>,
+}
+
+impl MatchProfiles {
+    pub fn new(profiles: &[ProfileConfig], mmf_namespace: &str) -> anyhow::Result<Self> {
+        let fetch_matches_requests = profiles
+            .iter()
+            .map(|profile| profile.fetch_matches_request(mmf_namespace))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let fleets = profiles
+            .iter()
+            .filter_map(|profile| {
+                profile
+                    .fleet
+                    .clone()
+                    .map(|fleet| (profile.name.clone(), fleet))
+            })
+            .collect();
+        Ok(MatchProfiles {
+            fetch_matches_requests: fetch_matches_requests,
+            fleets: fleets,
+        })
+    }
 }
 
 pub struct OpenMatchDirector<T>
@@ -197,8 +742,10 @@ where
     T: GameServerAllocationClient,
 {
     gs_alloc_client: T,
-    om_backend_client: om::backend_service_client::BackendServiceClient<tonic::transport::Channel>,
-    k8s_namespace: String,
+    om_backend_client: BackendClient,
+    profiles: watch::Receiver<MatchProfiles>,
+    config: DirectorConfig,
+    gameserver_token: Option<TokenFile>,
 }
 
 impl<T> OpenMatchDirector<T>
@@ -208,17 +755,269 @@ where
     pub async fn new(
         gs_alloc_client: T,
         om_backend_address: String,
-        k8s_namespace: String,
+        profiles: Vec<ProfileConfig>,
+        mmf_namespace: String,
+        config: DirectorConfig,
     ) -> anyhow::Result<Self> {
-        let om_backend_url = format!("http://{}", om_backend_address);
+        let profiles = MatchProfiles::new(&profiles, &mmf_namespace)?;
+        let channel = grpc_tls::connect(&om_backend_address, &config.om_tls).await?;
         let client =
-            om::backend_service_client::BackendServiceClient::connect(om_backend_url).await?;
+            om::backend_service_client::BackendServiceClient::new(telemetry::Traced::new(channel));
+        let gameserver_token = if config.gameserver_token_path.is_empty() {
+            None
+        } else {
+            Some(TokenFile::new(&config.gameserver_token_path))
+        };
         Ok(OpenMatchDirector {
             gs_alloc_client: gs_alloc_client,
             om_backend_client: client,
-            k8s_namespace: k8s_namespace,
+            profiles: watch::channel(profiles).1,
+            config: config,
+            gameserver_token: gameserver_token,
         })
     }
+
+    /// Fetches the latest profiles sent on `profiles` from the next cycle on. The cycle in
+    /// flight keeps its profiles.
+    pub fn with_profile_updates(mut self, profiles: watch::Receiver<MatchProfiles>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+}
+
+/// A match that could not be placed on a gameserver.
+struct Unplaced {
+    match_id: String,
+    ticket_ids: Vec<String>,
+    err: anyhow::Error,
+}
+
+/// Places the matches of one cycle on gameservers. It only borrows what it needs so that
+/// several matches can be placed at once.
+struct MatchPlacer<'a, T> {
+    gs_alloc_client: &'a T,
+    fleets: &'a HashMap<String, String>,
+    retry_policy: &'a RetryPolicy,
+    gameserver_tls: &'a TlsConfig,
+    gameserver_token: Option<&'a TokenFile>,
+    backfills: &'a [Backfill],
+    cancel: &'a CancellationToken,
+}
+
+impl<'a, T> MatchPlacer<'a, T>
+where
+    T: GameServerAllocationClient + Sync + Send,
+{
+    async fn gameserver_client(&self, address: String) -> anyhow::Result<GameServerClientImpl> {
+        let client = GameServerClientImpl::with_tls(address, self.gameserver_tls)
+            .await
+            .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+        Ok(match self.gameserver_token {
+            Some(token) => client.with_token(token.clone()),
+            None => client,
+        })
+    }
+
+    /// Tells the gameserver which players will join the match and on which team.
+    async fn reserve_match(
+        &self,
+        address: String,
+        match_id: String,
+        match_profile: String,
+        teams: Vec<Vec<String>>,
+        region: String,
+        skill_rating: f64,
+    ) -> anyhow::Result<()> {
+        self.gameserver_client(address)
+            .await?
+            .reserve_match(match_id, match_profile, teams, region, skill_rating)
+            .await
+            .map_err(|err| anyhow::anyhow!(err.to_string()))
+    }
+
+    /// Tells the gameserver which players will join a running match.
+    async fn backfill_match(
+        &self,
+        address: String,
+        match_id: String,
+        player_ids: Vec<String>,
+    ) -> anyhow::Result<()> {
+        self.gameserver_client(address)
+            .await?
+            .backfill_match(match_id, player_ids)
+            .await
+            .map_err(|err| anyhow::anyhow!(err.to_string()))
+    }
+
+    async fn allocate_with_retry(
+        &self,
+        params: &AllocationParams,
+    ) -> Result<Status, AllocationError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = match self.gs_alloc_client.allocate(params).await {
+                Ok(status) => return Ok(status),
+                Err(err) => err,
+            };
+            let backoff = match self.retry_policy.retry_after(attempt, &err) {
+                Some(backoff) if !self.cancel.is_cancelled() => backoff,
+                _ => return Err(err),
+            };
+            debug!(
+                "failed to allocate gameserver for match {}, retrying in {:?}: {}",
+                params.match_id, backoff, err
+            );
+            tokio::select! {
+                _ = time::delay_for(backoff) => {}
+                _ = self.cancel.cancelled() => return Err(err),
+            }
+        }
+    }
+
+    /// Allocates or backfills a gameserver for the match and returns its assignment.
+    async fn place(&self, m: om::Match) -> Result<om::AssignmentGroup, Unplaced> {
+        let match_id = m.match_id.clone();
+        let ticket_ids: Vec<String> = m.tickets.iter().map(|t| t.id.clone()).collect();
+        if self.cancel.is_cancelled() {
+            return Err(Unplaced {
+                match_id: match_id,
+                ticket_ids: ticket_ids,
+                err: anyhow::anyhow!("director is shutting down"),
+            });
+        }
+        let span = info_span!(
+            "place_match",
+            match_id = %match_id,
+            match_profile = %m.match_profile,
+            ticket_ids = ?ticket_ids,
+            player_ids = field::Empty,
+            gameserver = field::Empty
+        );
+        self.try_place(m)
+            .instrument(span)
+            .await
+            .map_err(|err| Unplaced {
+                match_id: match_id,
+                ticket_ids: ticket_ids,
+                err: err,
+            })
+    }
+
+    async fn try_place(&self, m: om::Match) -> anyhow::Result<om::AssignmentGroup> {
+        let match_id = m.match_id.clone();
+        let skill_rating = mean_skill_rating(&m.tickets);
+        let mut ticket_ids = Vec::with_capacity(m.tickets.len());
+        let mut player_ids = Vec::new();
+        for ticket in m.tickets {
+            ticket_ids.push(ticket.id.clone());
+            player_ids.append(&mut om_ext::player_ids(&ticket.extensions));
+        }
+        let span = tracing::Span::current();
+        span.record("player_ids", &field::debug(&player_ids));
+        let mut extensions = HashMap::new();
+        extensions.insert(
+            om_ext::PLAYER_IDS_EXTENSION.to_string(),
+            om_ext::string_list_any(&player_ids)?,
+        );
+
+        let address = if m.extensions.contains_key(BACKFILL_EXTENSION) {
+            // players for the open seats of a running match
+            let backfill = self
+                .backfills
+                .iter()
+                .find(|b| b.match_id == match_id)
+                .ok_or(anyhow::anyhow!("unknown backfill: {}", match_id))?;
+            // the players can only join once the gameserver expects them
+            self.backfill_match(
+                backfill.address.clone(),
+                match_id.clone(),
+                player_ids.clone(),
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!("failed to backfill match: {}", err))?;
+            backfill.address.clone()
+        } else {
+            let params = AllocationParams {
+                match_id: match_id.clone(),
+                region: match m.extensions.get(REGION_EXTENSION) {
+                    Some(any) => Some(String::decode(any.value.as_slice())?),
+                    None => None,
+                },
+                fleet: self.fleets.get(&m.match_profile).cloned(),
+            };
+            let region = params.region.clone().unwrap_or_default();
+            let status = self.allocate_with_retry(&params).await?;
+            let address = status_address(status)?;
+            // without a reservation the gameserver would turn the players away, so the
+            // tickets are released instead
+            if let Some(teams) = m.extensions.get(TEAMS_EXTENSION) {
+                extensions.insert(TEAMS_EXTENSION.to_string(), teams.clone());
+                let teams = decode_teams(teams)
+                    .map_err(|err| anyhow::anyhow!("failed to decode teams: {}", err))?;
+                self.reserve_match(
+                    address.clone(),
+                    match_id.clone(),
+                    m.match_profile.clone(),
+                    teams,
+                    region,
+                    skill_rating,
+                )
+                .await
+                .map_err(|err| anyhow::anyhow!("failed to reserve match: {}", err))?;
+            }
+            address
+        };
+        span.record("gameserver", &field::display(&address));
+        info!("placed match");
+        Ok(om::AssignmentGroup {
+            ticket_ids: ticket_ids,
+            assignment: Some(om::Assignment {
+                connection: match_id + "," + &address,
+                extensions: extensions,
+            }),
+        })
+    }
+}
+
+/// Assigns a batch of placed matches. The tickets of a failed batch and the tickets Open
+/// Match couldn't assign are added to `unassigned`, and their matches count as failed.
+async fn assign_batch(
+    om_backend_client: &mut BackendClient,
+    assignments: Vec<om::AssignmentGroup>,
+    summary: &mut CycleSummary,
+    unassigned: &mut Vec<String>,
+) {
+    let num_matches = assignments.len();
+    let groups: Vec<Vec<String>> = assignments
+        .iter()
+        .map(|group| group.ticket_ids.clone())
+        .collect();
+    let req = om::AssignTicketsRequest {
+        assignments: assignments,
+    };
+    let span = info_span!("assign_tickets", matches = num_matches);
+    match om_backend_client.assign_tickets(req).instrument(span).await {
+        Ok(res) => {
+            let failures = res.into_inner().failures;
+            if !failures.is_empty() {
+                warn!("failed to assign tickets: {:?}", failures);
+            }
+            let failed: HashSet<String> = failures.into_iter().map(|f| f.ticket_id).collect();
+            let failed_matches = groups
+                .iter()
+                .filter(|ticket_ids| ticket_ids.iter().any(|id| failed.contains(id)))
+                .count();
+            summary.assigned += num_matches - failed_matches;
+            summary.failed += failed_matches;
+            unassigned.extend(failed);
+        }
+        Err(err) => {
+            warn!("failed to assign tickets: {:?}", err);
+            summary.failed += num_matches;
+            unassigned.extend(groups.into_iter().flatten());
+        }
+    }
 }
 
 #[async_trait]
@@ -226,73 +1025,161 @@ impl<T> Director for OpenMatchDirector<T>
 where
     T: GameServerAllocationClient + Sync + Send,
 {
-    async fn assign(&mut self) -> anyhow::Result<()> {
-        let req = om::FetchMatchesRequest {
-            config: Some(om::FunctionConfig {
-                host: format!("mmf.{}.svc.cluster.local", self.k8s_namespace),
-                port: 50502,
-                r#type: om::function_config::Type::Grpc as i32,
-            }),
-            profile: Some(om::MatchProfile {
-                name: "default".to_string(),
-                pools: vec![om::Pool {
-                    name: "default".to_string(),
-                    double_range_filters: vec![],
-                    string_equals_filters: vec![],
-                    tag_present_filters: vec![],
-                    created_before: None,
-                    created_after: None,
-                }],
-                extensions: HashMap::new(),
-            }),
+    async fn assign(&mut self, cancel: &CancellationToken) -> anyhow::Result<CycleSummary> {
+        // the MMF fills the open seats of running matches of the same profile first. No tickets
+        // are held until the matches are fetched, so a cycle cancelled before that just stops
+        let backfills = tokio::select! {
+            res = self.gs_alloc_client.backfills() => res?,
+            _ = cancel.cancelled() => return Ok(CycleSummary::default()),
         };
-        let mut stream = self
-            .om_backend_client
-            .fetch_matches(tonic::Request::new(req))
-            .await?
-            .into_inner();
-        while let Some(res) = stream.message().await? {
-            match res.r#match {
-                Some(m) => {
-                    let match_id = m.match_id.clone();
-                    let mut ticket_ids = Vec::with_capacity(m.tickets.len());
-                    for ticket in m.tickets {
-                        ticket_ids.push(ticket.id.clone());
-                    }
-                    debug!("ticket_ids: {:?}", ticket_ids);
-
-                    let status = self.gs_alloc_client.allocate().await?;
-                    if &*status.state != "Allocated" {
-                        return Err(anyhow::anyhow!(
-                            "failed to allocate game server: {}",
-                            status.state
-                        ));
-                    }
+        let profiles = self.profiles.borrow().clone();
+        let mut requests = Vec::with_capacity(profiles.fetch_matches_requests.len());
+        for req in &profiles.fetch_matches_requests {
+            let mut req = req.clone();
+            if let Some(profile) = req.profile.as_mut() {
+                let profile_backfills: Vec<&Backfill> = backfills
+                    .iter()
+                    .filter(|b| b.match_profile == profile.name)
+                    .collect();
+                if !profile_backfills.is_empty() {
+                    profile.extensions.insert(
+                        BACKFILLS_EXTENSION.to_string(),
+                        backfills_extension(&profile_backfills)?,
+                    );
+                }
+            }
+            requests.push(req);
+        }
+
+        // fetch the matches of all profiles at once
+        let fetches = requests.into_iter().map(|req| {
+            let mut client = self.om_backend_client.clone();
+            async move { client.fetch_matches(tonic::Request::new(req)).await }
+        });
+        let streams = tokio::select! {
+            res = futures::future::try_join_all(fetches) => res?,
+            _ = cancel.cancelled() => return Ok(CycleSummary::default()),
+        };
+        let streams = streams.into_iter().map(|res| res.into_inner());
+        let stream = futures::stream::select_all(streams);
 
-                    let host = status.address.ok_or(anyhow::anyhow!("host is empty"))?;
-                    let port = status
-                        .ports
-                        .ok_or(anyhow::anyhow!("port is empty"))?
-                        .first()
-                        .ok_or(anyhow::anyhow!("port is empty"))?
-                        .port
-                        .to_string();
-                    self.om_backend_client
-                        .assign_tickets(om::AssignTicketsRequest {
-                            assignments: vec![om::AssignmentGroup {
-                                ticket_ids: ticket_ids,
-                                assignment: Some(om::Assignment {
-                                    connection: match_id + "," + &host + ":" + &port,
-                                    extensions: HashMap::new(),
-                                }),
-                            }],
-                        })
-                        .await?;
+        // place matches concurrently and assign them in batches
+        let placer = MatchPlacer {
+            gs_alloc_client: &self.gs_alloc_client,
+            fleets: &profiles.fleets,
+            retry_policy: &self.config.retry_policy,
+            gameserver_tls: &self.config.gameserver_tls,
+            gameserver_token: self.gameserver_token.as_ref(),
+            backfills: &backfills,
+            cancel: cancel,
+        };
+        let placer = &placer;
+        let mut placements = stream
+            .map(|res| async move {
+                let m = res?.r#match.ok_or(anyhow::anyhow!("match not found"))?;
+                Ok::<_, anyhow::Error>(placer.place(m).await)
+            })
+            .buffer_unordered(self.config.max_concurrent_assignments.max(1));
+        let mut om_backend_client = self.om_backend_client.clone();
+        let mut summary = CycleSummary::default();
+        let mut batch = Vec::new();
+        let mut unassigned = Vec::new();
+        let mut stream_err: Option<anyhow::Error> = None;
+        while let Some(res) = placements.next().await {
+            match res {
+                Ok(Ok(group)) => {
+                    summary.fetched += 1;
+                    batch.push(group);
+                    if batch.len() >= self.config.assign_batch_size {
+                        let assignments = std::mem::take(&mut batch);
+                        assign_batch(
+                            &mut om_backend_client,
+                            assignments,
+                            &mut summary,
+                            &mut unassigned,
+                        )
+                        .await;
+                    }
+                }
+                Ok(Err(unplaced)) => {
+                    warn!(
+                        "failed to assign match {}: {:?}",
+                        unplaced.match_id, unplaced.err
+                    );
+                    summary.fetched += 1;
+                    summary.failed += 1;
+                    unassigned.extend(unplaced.ticket_ids);
+                }
+                // the matches being placed are still assigned or released
+                Err(err) => {
+                    warn!("failed to fetch matches: {:?}", err);
+                    stream_err = stream_err.or(Some(err));
                 }
-                None => return Err(anyhow::anyhow!("match not found")),
             }
         }
-        Ok(())
+        if !batch.is_empty() {
+            assign_batch(&mut om_backend_client, batch, &mut summary, &mut unassigned).await;
+        }
+
+        // put the tickets of matches that couldn't be placed back into the pool
+        if !unassigned.is_empty() {
+            let req = om::ReleaseTicketsRequest {
+                ticket_ids: unassigned,
+            };
+            if let Err(err) = om_backend_client.release_tickets(req).await {
+                warn!("failed to release tickets: {:?}", err);
+            }
+        }
+        match stream_err {
+            Some(err) => Err(err.context(format!("cycle aborted after {}", summary))),
+            None => Ok(summary),
+        }
+    }
+}
+
+/// When the worker runs director cycles.
+#[derive(Clone, Debug)]
+pub struct WorkerConfig {
+    /// Time between the end of a cycle and the start of the next one.
+    pub interval: Duration,
+    /// Up to this much is added to every wait so that directors don't run in lockstep.
+    pub jitter: Duration,
+    /// Longest wait after consecutive failed cycles. The wait doubles with every failure.
+    pub max_backoff: Duration,
+    /// A cycle that runs longer than this stops placing matches and releases the tickets
+    /// of the matches it didn't get to.
+    pub cycle_timeout: Duration,
+    /// How long a cancelled cycle gets to finish its allocations and release its tickets
+    /// before it is dropped.
+    pub cancel_grace: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            interval: Duration::from_millis(1000),
+            jitter: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(30),
+            cycle_timeout: Duration::from_secs(30),
+            cancel_grace: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// Time to wait before the next cycle after `failures` consecutive failed cycles.
+    fn delay(&self, failures: u32) -> Duration {
+        let delay = if failures == 0 {
+            self.interval
+        } else {
+            let factor = 2u32.saturating_pow(failures.min(16) - 1);
+            (self.interval * factor).min(self.max_backoff)
+        };
+        let jitter_ms = self.jitter.as_millis() as u64;
+        if jitter_ms == 0 {
+            return delay;
+        }
+        delay + Duration::from_millis(rand::thread_rng().gen_range(0, jitter_ms + 1))
     }
 }
 
@@ -301,23 +1188,85 @@ where
     T: Director,
 {
     director: T,
+    config: WorkerConfig,
 }
 
 impl<T> Worker<T>
 where
     T: Director,
 {
-    pub fn new(director: T) -> anyhow::Result<Self> {
-        Ok(Worker { director: director })
+    pub fn new(director: T, config: WorkerConfig) -> anyhow::Result<Self> {
+        Ok(Worker {
+            director: director,
+            config: config,
+        })
     }
 
-    pub async fn run(&mut self) {
-        let mut interval = time::interval(Duration::from_millis(2000));
-        loop {
-            if let Err(err) = self.director.assign().await {
-                debug!("{:?}", err);
-                interval.tick().await;
+    /// Runs director cycles until `cancel` is cancelled. The cycle in flight gets up to
+    /// `cancel_grace` to finish before returning.
+    pub async fn run(&mut self, cancel: CancellationToken) {
+        let mut failures = 0;
+        while !cancel.is_cancelled() {
+            let started = Instant::now();
+            // the cycle is cancelled rather than dropped at its deadline, so that it still
+            // releases the tickets of the matches it didn't place
+            let cycle_cancel = CancellationToken::new();
+            let cycle = self
+                .director
+                .assign(&cycle_cancel)
+                .instrument(info_span!("cycle"));
+            futures::pin_mut!(cycle);
+            let mut timed_out = false;
+            let res = tokio::select! {
+                res = &mut cycle => res,
+                _ = time::delay_for(self.config.cycle_timeout) => {
+                    timed_out = true;
+                    cycle_cancel.cancel();
+                    match time::timeout(self.config.cancel_grace, &mut cycle).await {
+                        Ok(res) => res,
+                        Err(_) => Err(anyhow::anyhow!(
+                            "cycle timed out and didn't finish within {:?} of being cancelled",
+                            self.config.cancel_grace
+                        )),
+                    }
+                }
+                _ = cancel.cancelled() => {
+                    cycle_cancel.cancel();
+                    match time::timeout(self.config.cancel_grace, &mut cycle).await {
+                        Ok(res) => res,
+                        Err(_) => Err(anyhow::anyhow!(
+                            "cycle didn't finish within {:?} of the shutdown",
+                            self.config.cancel_grace
+                        )),
+                    }
+                }
+            };
+            match res {
+                Ok(summary) if !timed_out => {
+                    failures = 0;
+                    let elapsed = started.elapsed();
+                    info!("cycle finished in {:?}. {}", elapsed, summary);
+                    metrics::record_cycle("ok", &summary, elapsed);
+                }
+                Ok(summary) => {
+                    failures += 1;
+                    error!(
+                        "cycle timed out after {:?} ({} in a row). {}",
+                        self.config.cycle_timeout, failures, summary
+                    );
+                    metrics::record_cycle("timeout", &summary, started.elapsed());
+                }
+                Err(err) => {
+                    failures += 1;
+                    error!("cycle failed ({} in a row): {:?}", failures, err);
+                    metrics::record_failed_cycle(started.elapsed());
+                }
+            }
+            tokio::select! {
+                _ = time::delay_for(self.config.delay(failures)) => {}
+                _ = cancel.cancelled() => {}
             }
         }
+        info!("worker stopped");
     }
 }
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{register_histogram, register_int_counter_vec, Histogram, IntCounterVec};
+
+use super::CycleSummary;
+
+lazy_static! {
+    static ref CYCLES: IntCounterVec = register_int_counter_vec!(
+        "director_cycles_total",
+        "Number of director cycles by result.",
+        &["result"]
+    )
+    .unwrap();
+    static ref MATCHES: IntCounterVec = register_int_counter_vec!(
+        "director_matches_total",
+        "Number of matches handled by the director by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref CYCLE_DURATION: Histogram = register_histogram!(
+        "director_cycle_duration_seconds",
+        "Duration of director cycles in seconds."
+    )
+    .unwrap();
+}
+
+/// Records a cycle that finished, `"ok"` or `"timeout"`, and the matches it handled.
+pub fn record_cycle(result: &str, summary: &CycleSummary, duration: Duration) {
+    CYCLES.with_label_values(&[result]).inc();
+    MATCHES
+        .with_label_values(&["fetched"])
+        .inc_by(summary.fetched as i64);
+    MATCHES
+        .with_label_values(&["assigned"])
+        .inc_by(summary.assigned as i64);
+    MATCHES
+        .with_label_values(&["failed"])
+        .inc_by(summary.failed as i64);
+    CYCLE_DURATION.observe(duration.as_secs_f64());
+}
+
+/// Records a cycle that returned an error.
+pub fn record_failed_cycle(duration: Duration) {
+    CYCLES.with_label_values(&["error"]).inc();
+    CYCLE_DURATION.observe(duration.as_secs_f64());
+}
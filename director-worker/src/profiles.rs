@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use super::om;
+
+// Search fields set by the frontend.
+const GAME_MODE_ARG: &str = "game_mode";
+const REGION_ARG: &str = "region";
+const QUEUE_ARG: &str = "queue";
+/// Profile extension holding the parameters the MMF should use for the profile.
+pub const MMF_PARAMS_EXTENSION: &str = "mmf_params";
+
+const DEFAULT_MMF_PORT: i32 = 50502;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DoubleRangeFilter {
+    pub double_arg: String,
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StringEqualsFilter {
+    pub string_arg: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PoolConfig {
+    pub name: String,
+    #[serde(default)]
+    pub double_range_filters: Vec<DoubleRangeFilter>,
+    #[serde(default)]
+    pub string_equals_filters: Vec<StringEqualsFilter>,
+    #[serde(default)]
+    pub tag_present_filters: Vec<String>,
+}
+
+/// Which MMF runs the profile and with which parameters.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MatchFunctionConfig {
+    /// Defaults to the `mmf` service in the MMF namespace.
+    pub host: Option<String>,
+    pub port: Option<i32>,
+    /// Match function to run in the MMF, e.g. "basic" or "skill".
+    pub name: Option<String>,
+    /// Extra numeric parameters of the match function, e.g. `skill_window`.
+    #[serde(default)]
+    pub params: BTreeMap<String, f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub game_mode: Option<String>,
+    pub region: Option<String>,
+    pub queue: Option<String>,
+    /// Pools of the profile. The game mode, region and queue filters are added to every
+    /// pool, and a single pool is used when none is given.
+    #[serde(default)]
+    pub pools: Vec<PoolConfig>,
+    pub team_count: Option<usize>,
+    pub team_size: Option<usize>,
+    #[serde(default)]
+    pub match_function: MatchFunctionConfig,
+    /// Agones fleet the matches of the profile are allocated from, instead of the default.
+    pub fleet: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfilesConfig {
+    pub profiles: Vec<ProfileConfig>,
+}
+
+impl ProfileConfig {
+    /// The profile used when no config file is given: one unfiltered pool.
+    pub fn default_profile() -> Self {
+        ProfileConfig {
+            name: "default".to_string(),
+            game_mode: None,
+            region: None,
+            queue: None,
+            pools: vec![],
+            team_count: None,
+            team_size: None,
+            match_function: MatchFunctionConfig::default(),
+            fleet: None,
+        }
+    }
+
+    fn pools(&self) -> Vec<om::Pool> {
+        let mut implicit_filters = Vec::new();
+        for (arg, value) in &[
+            (GAME_MODE_ARG, &self.game_mode),
+            (REGION_ARG, &self.region),
+            (QUEUE_ARG, &self.queue),
+        ] {
+            if let Some(value) = value {
+                implicit_filters.push(om::StringEqualsFilter {
+                    string_arg: arg.to_string(),
+                    value: value.clone(),
+                });
+            }
+        }
+
+        let pools = if self.pools.is_empty() {
+            vec![PoolConfig {
+                name: self.name.clone(),
+                double_range_filters: vec![],
+                string_equals_filters: vec![],
+                tag_present_filters: vec![],
+            }]
+        } else {
+            self.pools.clone()
+        };
+        pools
+            .into_iter()
+            .map(|pool| om::Pool {
+                name: pool.name,
+                double_range_filters: pool
+                    .double_range_filters
+                    .into_iter()
+                    .map(|f| om::DoubleRangeFilter {
+                        double_arg: f.double_arg,
+                        min: f.min,
+                        max: f.max,
+                    })
+                    .collect(),
+                string_equals_filters: pool
+                    .string_equals_filters
+                    .into_iter()
+                    .map(|f| om::StringEqualsFilter {
+                        string_arg: f.string_arg,
+                        value: f.value,
+                    })
+                    .chain(implicit_filters.iter().cloned())
+                    .collect(),
+                tag_present_filters: pool
+                    .tag_present_filters
+                    .into_iter()
+                    .map(|tag| om::TagPresentFilter { tag })
+                    .collect(),
+                created_before: None,
+                created_after: None,
+            })
+            .collect()
+    }
+
+    /// Encodes the MMF parameters of the profile as a `google.protobuf.Struct`.
+    fn mmf_params(&self) -> anyhow::Result<prost_types::Any> {
+        let mut fields = BTreeMap::new();
+        if let Some(name) = &self.match_function.name {
+            fields.insert(
+                "match_function".to_string(),
+                prost_types::Value {
+                    kind: Some(prost_types::value::Kind::StringValue(name.clone())),
+                },
+            );
+        }
+        let numbers = self
+            .match_function
+            .params
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .chain(
+                self.team_count
+                    .map(|n| ("team_count".to_string(), n as f64)),
+            )
+            .chain(self.team_size.map(|n| ("team_size".to_string(), n as f64)));
+        for (key, value) in numbers {
+            fields.insert(
+                key,
+                prost_types::Value {
+                    kind: Some(prost_types::value::Kind::NumberValue(value)),
+                },
+            );
+        }
+        let mut buf = Vec::new();
+        prost_types::Struct { fields }.encode(&mut buf)?;
+        Ok(prost_types::Any {
+            type_url: "type.googleapis.com/google.protobuf.Struct".to_string(),
+            value: buf,
+        })
+    }
+
+    /// Builds the `FetchMatches` request of the profile.
+    pub fn fetch_matches_request(
+        &self,
+        mmf_namespace: &str,
+    ) -> anyhow::Result<om::FetchMatchesRequest> {
+        let mut extensions = HashMap::new();
+        extensions.insert(MMF_PARAMS_EXTENSION.to_string(), self.mmf_params()?);
+        Ok(om::FetchMatchesRequest {
+            config: Some(om::FunctionConfig {
+                host: self
+                    .match_function
+                    .host
+                    .clone()
+                    .unwrap_or(format!("mmf.{}.svc.cluster.local", mmf_namespace)),
+                port: self.match_function.port.unwrap_or(DEFAULT_MMF_PORT),
+                r#type: om::function_config::Type::Grpc as i32,
+            }),
+            profile: Some(om::MatchProfile {
+                name: self.name.clone(),
+                pools: self.pools(),
+                extensions,
+            }),
+        })
+    }
+}
+
+/// Loads the match profiles from a YAML file.
+pub fn load_profiles<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<ProfileConfig>> {
+    let file = std::fs::File::open(path.as_ref())
+        .map_err(|err| anyhow::anyhow!("cannot open {:?}: {}", path.as_ref(), err))?;
+    let config: ProfilesConfig = serde_yaml::from_reader(file)
+        .map_err(|err| anyhow::anyhow!("cannot parse {:?}: {}", path.as_ref(), err))?;
+    if config.profiles.is_empty() {
+        return Err(anyhow::anyhow!("no profile in {:?}", path.as_ref()));
+    }
+    Ok(config.profiles)
+}
@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use prost::Message;
+
+use director_worker::om;
+use director_worker::profiles::{load_profiles, ProfileConfig, MMF_PARAMS_EXTENSION};
+
+fn profiles_file(name: &str, yaml: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.yml", name, std::process::id()));
+    std::fs::write(&path, yaml).unwrap();
+    path
+}
+
+fn mmf_params(request: &om::FetchMatchesRequest) -> prost_types::Struct {
+    let any = &request.profile.as_ref().unwrap().extensions[MMF_PARAMS_EXTENSION];
+    prost_types::Struct::decode(any.value.as_slice()).unwrap()
+}
+
+fn number(params: &prost_types::Struct, key: &str) -> Option<f64> {
+    match params.fields.get(key).and_then(|v| v.kind.clone()) {
+        Some(prost_types::value::Kind::NumberValue(n)) => Some(n),
+        _ => None,
+    }
+}
+
+#[test]
+fn profiles_are_loaded_with_their_filters_and_params() {
+    let path = profiles_file(
+        "profiles-valid",
+        r#"
+profiles:
+- name: ranked-asia
+  game_mode: ranked
+  region: asia
+  team_count: 2
+  team_size: 3
+  pools:
+  - name: beginners
+    double_range_filters:
+    - double_arg: skill_rating
+      min: 0
+      max: 1000
+  - name: experts
+    tag_present_filters: [veteran]
+  match_function:
+    name: skill
+    params:
+      skill_window: 50
+- name: casual
+  match_function:
+    host: mmf.local
+    port: 7000
+"#,
+    );
+    let profiles = load_profiles(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(profiles.len(), 2);
+
+    let request = profiles[0].fetch_matches_request("matchmaker").unwrap();
+    let config = request.config.as_ref().unwrap();
+    assert_eq!(config.host, "mmf.matchmaker.svc.cluster.local");
+    assert_eq!(config.port, 50502);
+    let profile = request.profile.as_ref().unwrap();
+    assert_eq!(profile.name, "ranked-asia");
+    let pools: Vec<&str> = profile.pools.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(pools, vec!["beginners", "experts"]);
+    // the game mode and region filter every pool
+    for pool in &profile.pools {
+        let filters: Vec<(&str, &str)> = pool
+            .string_equals_filters
+            .iter()
+            .map(|f| (f.string_arg.as_str(), f.value.as_str()))
+            .collect();
+        assert_eq!(filters, vec![("game_mode", "ranked"), ("region", "asia")]);
+    }
+    assert_eq!(profile.pools[0].double_range_filters[0].max, 1000.0);
+    assert_eq!(profile.pools[1].tag_present_filters[0].tag, "veteran");
+
+    let params = mmf_params(&request);
+    match params.fields["match_function"].kind.clone() {
+        Some(prost_types::value::Kind::StringValue(name)) => assert_eq!(name, "skill"),
+        other => panic!("unexpected match_function: {:?}", other),
+    }
+    assert_eq!(number(&params, "team_count"), Some(2.0));
+    assert_eq!(number(&params, "team_size"), Some(3.0));
+    assert_eq!(number(&params, "skill_window"), Some(50.0));
+}
+
+#[test]
+fn unset_fields_are_left_to_the_mmf() {
+    let path = profiles_file(
+        "profiles-partial",
+        r#"
+profiles:
+- name: casual
+  team_size: 4
+  match_function:
+    host: mmf.local
+    port: 7000
+"#,
+    );
+    let profiles = load_profiles(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let request = profiles[0].fetch_matches_request("default").unwrap();
+    let config = request.config.as_ref().unwrap();
+    assert_eq!((config.host.as_str(), config.port), ("mmf.local", 7000));
+    // a profile without pools has one unfiltered pool named after it
+    let pools = &request.profile.as_ref().unwrap().pools;
+    assert_eq!(pools.len(), 1);
+    assert_eq!(pools[0].name, "casual");
+    assert!(pools[0].string_equals_filters.is_empty());
+
+    let params = mmf_params(&request);
+    let keys: Vec<&str> = params.fields.keys().map(|k| k.as_str()).collect();
+    assert_eq!(keys, vec!["team_size"]);
+
+    let params = mmf_params(
+        &ProfileConfig::default_profile()
+            .fetch_matches_request("default")
+            .unwrap(),
+    );
+    assert!(params.fields.is_empty());
+}
+
+#[test]
+fn invalid_profiles_are_refused() {
+    assert!(load_profiles(std::env::temp_dir().join("no-such-profiles.yml")).is_err());
+
+    for (name, yaml) in &[
+        ("profiles-empty", "profiles: []\n"),
+        ("profiles-unnamed", "profiles:\n- game_mode: ranked\n"),
+        (
+            "profiles-bad-size",
+            "profiles:\n- name: ranked\n  team_size: three\n",
+        ),
+        ("profiles-malformed", "profiles: [\n"),
+    ] {
+        let path = profiles_file(name, yaml);
+        let result = load_profiles(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err(), "{} was loaded", name);
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use grpc_tls::TlsConfig;
+use serde::{Deserialize, Serialize};
+use settings::{EnvKey, Validate};
+
+use director_worker::profiles::{load_profiles, ProfileConfig};
+use director_worker::{AllocationPolicy, DirectorConfig, MatchProfiles, RetryPolicy, WorkerConfig};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AllocationMode {
+    /// Allocates from a fleet through the Kubernetes API.
+    Outside,
+    /// Allocates the gameserver the director runs next to, through the Agones SDK.
+    #[serde(rename = "self")]
+    SelfAllocation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AllocationConfig {
+    pub fleet_name: String,
+    pub selectors: HashMap<String, String>,
+    pub preferred_selectors: Vec<HashMap<String, String>>,
+    /// Packed or Distributed
+    pub scheduling: String,
+    pub region_label: String,
+    pub labels: HashMap<String, String>,
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Wait before retrying a full fleet. Zero leaves the match to the next cycle.
+    pub no_capacity_backoff_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkerTiming {
+    pub fetch_interval_ms: u64,
+    pub fetch_jitter_ms: u64,
+    pub max_backoff_ms: u64,
+    pub cycle_timeout_ms: u64,
+    pub cancel_grace_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    pub om_backend_address: String,
+    pub gameserver_namespace: String,
+    pub mmf_namespace: String,
+    /// Profiles file. The default profile is used when empty.
+    pub match_profiles_path: String,
+    pub metrics_address: SocketAddr,
+    pub allocation_mode: AllocationMode,
+    /// Gameservers allocated at most in self allocation mode.
+    pub max_allocate: i32,
+    /// Gameserver the director runs next to in self allocation mode.
+    pub gameserver_address: String,
+    pub allocation: AllocationConfig,
+    pub retry: RetryConfig,
+    pub max_concurrent_assignments: usize,
+    pub assign_batch_size: usize,
+    pub worker: WorkerTiming,
+    /// mTLS to the Open Match backend.
+    pub om_tls: TlsConfig,
+    /// mTLS to the gameservers.
+    pub gameserver_tls: TlsConfig,
+    /// Token file sent with the reservations and backfills, e.g. a mounted Secret.
+    pub gameserver_token_path: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let policy = AllocationPolicy::default();
+        let retry_policy = RetryPolicy::default();
+        let director_config = DirectorConfig::default();
+        let worker_config = WorkerConfig::default();
+        Config {
+            om_backend_address: "om-backend.open-match.svc.cluster.local:50505".to_string(),
+            gameserver_namespace: "default".to_string(),
+            mmf_namespace: "default".to_string(),
+            match_profiles_path: String::new(),
+            metrics_address: ([0, 0, 0, 0], 9090).into(),
+            allocation_mode: AllocationMode::Outside,
+            max_allocate: 10,
+            gameserver_address: "localhost:10000".to_string(),
+            allocation: AllocationConfig {
+                fleet_name: policy.fleet_name,
+                selectors: policy.selectors,
+                preferred_selectors: policy.preferred_selectors,
+                scheduling: "Packed".to_string(),
+                region_label: policy.region_label,
+                labels: policy.labels,
+                annotations: policy.annotations,
+            },
+            retry: RetryConfig {
+                max_attempts: retry_policy.max_attempts,
+                initial_backoff_ms: retry_policy.initial_backoff.as_millis() as u64,
+                max_backoff_ms: retry_policy.max_backoff.as_millis() as u64,
+                no_capacity_backoff_ms: retry_policy.no_capacity_backoff.as_millis() as u64,
+            },
+            max_concurrent_assignments: director_config.max_concurrent_assignments,
+            assign_batch_size: director_config.assign_batch_size,
+            worker: WorkerTiming {
+                fetch_interval_ms: worker_config.interval.as_millis() as u64,
+                fetch_jitter_ms: worker_config.jitter.as_millis() as u64,
+                max_backoff_ms: worker_config.max_backoff.as_millis() as u64,
+                cycle_timeout_ms: worker_config.cycle_timeout.as_millis() as u64,
+                cancel_grace_ms: worker_config.cancel_grace.as_millis() as u64,
+            },
+            om_tls: director_config.om_tls,
+            gameserver_tls: director_config.gameserver_tls,
+            gameserver_token_path: director_config.gameserver_token_path,
+        }
+    }
+}
+
+/// Environment variables overriding the config file.
+pub const ENV_KEYS: &[EnvKey] = &[
+    ("OM_BACKEND_ADDRESS", "om_backend_address"),
+    ("GAMESERVER_NAMESPACE", "gameserver_namespace"),
+    ("MMF_NAMESPACE", "mmf_namespace"),
+    ("MATCH_PROFILES_PATH", "match_profiles_path"),
+    ("METRICS_ADDRESS", "metrics_address"),
+    ("GS_ALLOCATION_MODE", "allocation_mode"), // outside or self
+    ("GS_MAX_ALLOCATE", "max_allocate"),
+    ("GS_ADDRESS", "gameserver_address"),
+    ("GS_FLEET_NAME", "allocation.fleet_name"),
+    ("GS_SELECTORS", "allocation.selectors"),
+    // selectors separated by ";", e.g. "zone=a;zone=b"
+    ("GS_PREFERRED_SELECTORS", "allocation.preferred_selectors"),
+    ("GS_SCHEDULING", "allocation.scheduling"),
+    ("GS_REGION_LABEL", "allocation.region_label"),
+    ("GS_LABELS", "allocation.labels"),
+    ("GS_ANNOTATIONS", "allocation.annotations"),
+    ("ALLOCATION_MAX_ATTEMPTS", "retry.max_attempts"),
+    ("ALLOCATION_INITIAL_BACKOFF_MS", "retry.initial_backoff_ms"),
+    ("ALLOCATION_MAX_BACKOFF_MS", "retry.max_backoff_ms"),
+    (
+        "ALLOCATION_NO_CAPACITY_BACKOFF_MS",
+        "retry.no_capacity_backoff_ms",
+    ),
+    ("MAX_CONCURRENT_ASSIGNMENTS", "max_concurrent_assignments"),
+    ("ASSIGN_BATCH_SIZE", "assign_batch_size"),
+    ("FETCH_INTERVAL_MS", "worker.fetch_interval_ms"),
+    ("FETCH_JITTER_MS", "worker.fetch_jitter_ms"),
+    ("MAX_BACKOFF_MS", "worker.max_backoff_ms"),
+    ("CYCLE_TIMEOUT_MS", "worker.cycle_timeout_ms"),
+    ("CANCEL_GRACE_MS", "worker.cancel_grace_ms"),
+    ("OM_TLS_CERT_PATH", "om_tls.cert_path"),
+    ("OM_TLS_KEY_PATH", "om_tls.key_path"),
+    ("OM_TLS_CA_PATH", "om_tls.ca_path"),
+    ("OM_TLS_DOMAIN_NAME", "om_tls.domain_name"),
+    ("GS_TLS_CERT_PATH", "gameserver_tls.cert_path"),
+    ("GS_TLS_KEY_PATH", "gameserver_tls.key_path"),
+    ("GS_TLS_CA_PATH", "gameserver_tls.ca_path"),
+    ("GS_TLS_DOMAIN_NAME", "gameserver_tls.domain_name"),
+    ("GS_TOKEN_PATH", "gameserver_token_path"),
+];
+
+/// Keys applied without a restart: the profiles, and the allocation policy when the director
+/// allocates from outside the fleet.
+pub const RELOADABLE_KEYS: &[&str] = &[
+    "match_profiles_path",
+    "mmf_namespace",
+    "profiles",
+    "allocation",
+];
+
+/// Keys applied without a restart in self allocation mode, which has no allocation policy.
+pub const SELF_ALLOCATION_RELOADABLE_KEYS: &[&str] =
+    &["match_profiles_path", "mmf_namespace", "profiles"];
+
+impl AllocationMode {
+    /// Keys applied without a restart in this mode.
+    pub fn reloadable_keys(self) -> &'static [&'static str] {
+        match self {
+            AllocationMode::Outside => RELOADABLE_KEYS,
+            AllocationMode::SelfAllocation => SELF_ALLOCATION_RELOADABLE_KEYS,
+        }
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), String> {
+        if self.om_backend_address.is_empty() {
+            return Err("om_backend_address must not be empty".to_string());
+        }
+        if self.allocation_mode == AllocationMode::SelfAllocation && self.max_allocate <= 0 {
+            return Err(format!(
+                "max_allocate must be positive: {}",
+                self.max_allocate
+            ));
+        }
+        self.allocation
+            .scheduling
+            .parse::<director_worker::SchedulingStrategy>()
+            .map_err(|err| err.to_string())?;
+        if self.retry.max_attempts == 0 {
+            return Err("retry.max_attempts must be positive".to_string());
+        }
+        if self.retry.max_backoff_ms < self.retry.initial_backoff_ms {
+            return Err(format!(
+                "retry.max_backoff_ms must not be less than retry.initial_backoff_ms: {} < {}",
+                self.retry.max_backoff_ms, self.retry.initial_backoff_ms
+            ));
+        }
+        if self.max_concurrent_assignments == 0 || self.assign_batch_size == 0 {
+            return Err(
+                "max_concurrent_assignments and assign_batch_size must be positive".to_string(),
+            );
+        }
+        if self.worker.cycle_timeout_ms == 0 {
+            return Err("worker.cycle_timeout_ms must be positive".to_string());
+        }
+        self.om_tls
+            .validate()
+            .map_err(|err| format!("om_tls: {}", err))?;
+        self.gameserver_tls
+            .validate()
+            .map_err(|err| format!("gameserver_tls: {}", err))?;
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Loads the config from the config file, the environment and the command line.
+    pub fn load() -> Result<Self, settings::Error> {
+        settings::load(ENV_KEYS)
+    }
+
+    pub fn profiles(&self) -> anyhow::Result<Vec<ProfileConfig>> {
+        if self.match_profiles_path.is_empty() {
+            return Ok(vec![ProfileConfig::default_profile()]);
+        }
+        load_profiles(&self.match_profiles_path)
+    }
+
+    pub fn allocation_policy(&self) -> anyhow::Result<AllocationPolicy> {
+        let allocation = self.allocation.clone();
+        Ok(AllocationPolicy {
+            fleet_name: allocation.fleet_name,
+            selectors: allocation.selectors,
+            preferred_selectors: allocation.preferred_selectors,
+            scheduling: allocation.scheduling.parse()?,
+            region_label: allocation.region_label,
+            labels: allocation.labels,
+            annotations: allocation.annotations,
+        })
+    }
+
+    pub fn director_config(&self) -> DirectorConfig {
+        DirectorConfig {
+            retry_policy: RetryPolicy {
+                max_attempts: self.retry.max_attempts,
+                initial_backoff: Duration::from_millis(self.retry.initial_backoff_ms),
+                max_backoff: Duration::from_millis(self.retry.max_backoff_ms),
+                no_capacity_backoff: Duration::from_millis(self.retry.no_capacity_backoff_ms),
+                ..Default::default()
+            },
+            max_concurrent_assignments: self.max_concurrent_assignments,
+            assign_batch_size: self.assign_batch_size,
+            om_tls: self.om_tls.clone(),
+            gameserver_tls: self.gameserver_tls.clone(),
+            gameserver_token_path: self.gameserver_token_path.clone(),
+        }
+    }
+
+    pub fn worker_config(&self) -> WorkerConfig {
+        WorkerConfig {
+            interval: Duration::from_millis(self.worker.fetch_interval_ms),
+            jitter: Duration::from_millis(self.worker.fetch_jitter_ms),
+            max_backoff: Duration::from_millis(self.worker.max_backoff_ms),
+            cycle_timeout: Duration::from_millis(self.worker.cycle_timeout_ms),
+            cancel_grace: Duration::from_millis(self.worker.cancel_grace_ms),
+        }
+    }
+}
+
+/// The config with the profiles and allocation policy it resolves to. It is reloaded as a
+/// whole, so that a bad profiles file keeps the last good config too.
+#[derive(Clone, Debug, Serialize)]
+pub struct Matchmaking {
+    #[serde(flatten)]
+    pub config: Config,
+    pub profiles: Vec<ProfileConfig>,
+    #[serde(skip)]
+    pub match_profiles: MatchProfiles,
+    #[serde(skip)]
+    pub allocation_policy: AllocationPolicy,
+}
+
+impl Matchmaking {
+    pub fn new(config: Config) -> Result<Self, settings::Error> {
+        let profiles = config.profiles().map_err(|err| format!("{:#}", err))?;
+        let match_profiles = MatchProfiles::new(&profiles, &config.mmf_namespace)
+            .map_err(|err| format!("invalid profiles: {:#}", err))?;
+        let allocation_policy = config
+            .allocation_policy()
+            .map_err(|err| format!("{:#}", err))?;
+        Ok(Matchmaking {
+            config,
+            profiles,
+            match_profiles,
+            allocation_policy,
+        })
+    }
+
+    /// Resolves the config and reads the profiles file again.
+    pub fn reload() -> Result<Self, settings::Error> {
+        Matchmaking::new(settings::reload(ENV_KEYS)?)
+    }
+}
@@ -1,7 +1,8 @@
+mod config;
 mod worker;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    telemetry::init("director")?;
     worker::run_worker().await
 }
@@ -1,40 +1,94 @@
-use std::env;
-
 use director_worker::{
-    AgonesGameServerAllocationClient, AgonesSDKSelfAllocationClient, OpenMatchDirector, Worker,
+    AgonesGameServerAllocationClient, AgonesSDKSelfAllocationClient, CancellationToken,
+    OpenMatchDirector, Worker,
 };
 use gameserver_client::GameServerClientImpl;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+use super::config::{AllocationMode, Config, Matchmaking};
+
+/// Resolves on SIGTERM, which Kubernetes sends before killing the pod, or on Ctrl-C.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        res = tokio::signal::ctrl_c() => res?,
+    }
+    Ok(())
+}
 
 pub async fn run_worker() -> anyhow::Result<()> {
-    let om_backend_address = env::var("OM_BACKEND_ADDRESS")
-        .unwrap_or("om-backend.open-match.svc.cluster.local:50505".to_string());
-    let gameserver_namespace = env::var("GAMESERVER_NAMESPACE").unwrap_or("default".to_string());
-    let mmf_namespace = env::var("MMF_NAMESPACE").unwrap_or("default".to_string());
-    let mode = env::var("GS_ALLOCATION_MODE").unwrap_or("outside".to_string()); // outside or self
-    match mode.as_str() {
-        "outside" => {
-            let alloc_client = AgonesGameServerAllocationClient::new(gameserver_namespace)?;
-            let director =
-                OpenMatchDirector::new(alloc_client, om_backend_address, mmf_namespace).await?;
-            let mut worker = Worker::new(director)?;
-            worker.run().await;
+    let matchmaking = Matchmaking::new(Config::load()?)?;
+    let updates = settings::watch_with(
+        matchmaking.clone(),
+        matchmaking.config.allocation_mode.reloadable_keys(),
+        settings::RELOAD_INTERVAL,
+        Matchmaking::reload,
+    );
+    let profile_updates =
+        settings::map(updates.clone(), |m: &Matchmaking| m.match_profiles.clone());
+    let config = matchmaking.config;
+    let profiles = matchmaking.profiles;
+    let metrics_address = config.metrics_address;
+    tokio::spawn(async move {
+        if let Err(err) = telemetry::metrics::serve(metrics_address).await {
+            error!("metrics server error: {:?}", err);
         }
-        "self" => {
-            let sdk = agones::Sdk::new().expect("could not connect to the sidecar");
-            let max_allocate = env::var("GS_MAX_ALLOCATE").unwrap_or("10".to_string());
-            let gs_address = env::var("GS_ADDRESS").unwrap_or("localhost:10000".to_string());
-            let gs_client = GameServerClientImpl::new(gs_address)
-                .await
-                .map_err(|err| anyhow::anyhow!(err.to_string()))?;
-            let alloc_client =
-                AgonesSDKSelfAllocationClient::new(sdk, gs_client, max_allocate.parse()?);
-            let director =
-                OpenMatchDirector::new(alloc_client, om_backend_address, mmf_namespace).await?;
-            let mut worker = Worker::new(director)?;
-            worker.run().await;
+    });
+    let cancel = CancellationToken::new();
+    let signal_cancel = cancel.clone();
+    tokio::spawn(async move {
+        if let Err(err) = shutdown_signal().await {
+            error!("failed to listen for shutdown signals: {:?}", err);
+            return;
         }
-        _ => {
-            panic!("invalid mode");
+        info!("shutting down after the current cycle");
+        signal_cancel.cancel();
+    });
+    match config.allocation_mode {
+        AllocationMode::Outside => {
+            let policy_updates =
+                settings::map(updates, |m: &Matchmaking| m.allocation_policy.clone());
+            let alloc_client = AgonesGameServerAllocationClient::new(
+                config.gameserver_namespace.clone(),
+                matchmaking.allocation_policy,
+            )?
+            .with_policy_updates(policy_updates);
+            let director = OpenMatchDirector::new(
+                alloc_client,
+                config.om_backend_address.clone(),
+                profiles,
+                config.mmf_namespace.clone(),
+                config.director_config(),
+            )
+            .await?
+            .with_profile_updates(profile_updates);
+            let mut worker = Worker::new(director, config.worker_config())?;
+            worker.run(cancel).await;
+        }
+        AllocationMode::SelfAllocation => {
+            let sdk = agones::Sdk::new()
+                .map_err(|err| anyhow::anyhow!("could not connect to the sidecar: {:?}", err))?;
+            let gs_client = GameServerClientImpl::with_tls(
+                config.gameserver_address.clone(),
+                &config.gameserver_tls,
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+            let alloc_client =
+                AgonesSDKSelfAllocationClient::new(sdk, gs_client, config.max_allocate);
+            let director = OpenMatchDirector::new(
+                alloc_client,
+                config.om_backend_address.clone(),
+                profiles,
+                config.mmf_namespace.clone(),
+                config.director_config(),
+            )
+            .await?
+            .with_profile_updates(profile_updates);
+            let mut worker = Worker::new(director, config.worker_config())?;
+            worker.run(cancel).await;
         }
     };
     Ok(())
@@ -1,6 +1,7 @@
 use std::env;
 use std::time::Duration;
 
+use grpc_tls::TlsConfig;
 use tokio::time;
 use tonic::metadata::MetadataValue;
 use tonic::transport::Channel;
@@ -20,6 +21,7 @@ async fn run_message_stream(
             let elapsed = time.duration_since(start);
             let message = game::Message {
                 body: "aaa".as_bytes().to_vec(),
+                system: false,
             };
             yield message;
         };
@@ -37,8 +39,13 @@ async fn run_message_stream(
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let address = env::var("SERVER_ADDR").unwrap();
-    let address = format!("http://{}", address);
-    let channel = Channel::from_shared(address)?.connect().await?;
+    // TLS_CA_PATH connects over TLS
+    let tls = TlsConfig {
+        ca_path: env::var("TLS_CA_PATH").unwrap_or_default(),
+        domain_name: env::var("TLS_DOMAIN_NAME").unwrap_or_default(),
+        ..Default::default()
+    };
+    let channel = grpc_tls::connect(&address, &tls).await?;
     let metadata = MetadataValue::from_str("12345")?;
     let mut client = game::game_client::GameClient::with_interceptor(
         channel,
@@ -1,6 +1,7 @@
 use std::env;
 use std::time::Duration;
 
+use grpc_tls::TlsConfig;
 use tokio::time;
 use tonic::metadata::MetadataValue;
 use tonic::transport::Channel;
@@ -25,6 +26,7 @@ async fn run_message_stream(
             let elapsed = time.duration_since(start);
             let message = game::Message {
                 body: "aaa".as_bytes().to_vec(),
+                system: false,
             };
             yield message;
         };
@@ -41,17 +43,33 @@ async fn run_message_stream(
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mm_address = format!("http://{}", env::var("MM_SERVER_ADDR").unwrap());
+    let mm_address = env::var("MM_SERVER_ADDR").unwrap();
+    // TLS_CA_PATH connects over TLS. Gameservers are reached by their IP, so their
+    // certificates are checked against GS_TLS_DOMAIN_NAME.
+    let ca_path = env::var("TLS_CA_PATH").unwrap_or_default();
+    let mm_tls = TlsConfig {
+        ca_path: ca_path.clone(),
+        domain_name: env::var("MM_TLS_DOMAIN_NAME").unwrap_or_default(),
+        ..Default::default()
+    };
+    let gs_tls = TlsConfig {
+        ca_path,
+        domain_name: env::var("GS_TLS_DOMAIN_NAME").unwrap_or_default(),
+        ..Default::default()
+    };
     for _ in 0..10 {
         let mm_address = mm_address.clone();
+        let mm_tls = mm_tls.clone();
+        let gs_tls = gs_tls.clone();
         tokio::spawn(async move {
-            let mut mm_client = mm::frontend_client::FrontendClient::connect(mm_address)
-                .await
-                .unwrap();
+            let channel = grpc_tls::connect(&mm_address, &mm_tls).await.unwrap();
+            let mut mm_client = mm::frontend_client::FrontendClient::new(channel);
             let player_id = Uuid::new_v4().to_string();
             let mut stream = mm_client
                 .create_match(mm::CreateMatchRequest {
                     player_id: player_id.clone(),
+                    skill_rating: rand::random::<f64>() * 3000.0,
+                    ..Default::default()
                 })
                 .await
                 .unwrap()
@@ -69,14 +87,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     panic!("unexpected response");
                 }
                 match_id = address[0].to_string();
-                gs_address = format!("http://{}", address[1]);
+                gs_address = address[1].to_string();
                 break;
             }
-            let channel = Channel::from_shared(gs_address)
-                .unwrap()
-                .connect()
-                .await
-                .unwrap();
+            let channel = grpc_tls::connect(&gs_address, &gs_tls).await.unwrap();
             let player_id = MetadataValue::from_str(&player_id).unwrap();
             let match_id = MetadataValue::from_str(&match_id).unwrap();
             let mut client = game::game_client::GameClient::with_interceptor(
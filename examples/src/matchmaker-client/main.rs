@@ -1,5 +1,7 @@
 use std::env;
 
+use grpc_tls::TlsConfig;
+
 pub mod mm {
     tonic::include_proto!("matchmaker");
 }
@@ -7,12 +9,19 @@ pub mod mm {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let address = env::var("SERVER_ADDR").unwrap();
-    let address = format!("http://{}", address);
+    // TLS_CA_PATH connects over TLS
+    let tls = TlsConfig {
+        ca_path: env::var("TLS_CA_PATH").unwrap_or_default(),
+        domain_name: env::var("TLS_DOMAIN_NAME").unwrap_or_default(),
+        ..Default::default()
+    };
     for _ in 0..10 {
-        let mut client = mm::frontend_client::FrontendClient::connect(address.clone()).await?;
+        let channel = grpc_tls::connect(&address, &tls).await?;
+        let mut client = mm::frontend_client::FrontendClient::new(channel);
         let mut stream = client
             .create_match(mm::CreateMatchRequest {
                 player_id: "123".to_string(),
+                ..Default::default()
             })
             .await?
             .into_inner();
@@ -1,8 +1,15 @@
+use grpc_health::build::descriptor_set;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure().build_server(true).compile(
         &["../deps/open-match/api/frontend.proto"],
         &["../deps/open-match", "../deps/open-match/third_party"],
     )?;
     tonic_build::compile_protos("../proto/game_frontend.proto")?;
+    descriptor_set(
+        &["../proto/game_frontend.proto"],
+        &["../proto"],
+        "frontend_descriptor.bin",
+    )?;
     Ok(())
 }
@@ -0,0 +1,5 @@
+pub mod metrics;
+pub mod parties;
+pub mod server;
+pub mod service;
+pub mod tickets;
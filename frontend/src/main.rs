@@ -1,8 +1,8 @@
-mod service;
+use frontend::server;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    service::run_server().await?;
+    telemetry::init("frontend")?;
+    server::run_server().await?;
     Ok(())
 }
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Histogram, IntCounter, IntCounterVec, IntGauge,
+};
+
+lazy_static! {
+    static ref TICKETS_CREATED: IntCounter = register_int_counter!(
+        "frontend_tickets_created_total",
+        "Number of tickets created in Open Match."
+    )
+    .unwrap();
+    static ref TICKETS_FINISHED: IntCounterVec = register_int_counter_vec!(
+        "frontend_tickets_finished_total",
+        "Number of tickets that stopped waiting for a match by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref TIME_TO_ASSIGNMENT: Histogram = register_histogram!(
+        "frontend_time_to_assignment_seconds",
+        "Time from the match request to the assignment of its ticket in seconds.",
+        prometheus::exponential_buckets(0.5, 2.0, 10).unwrap()
+    )
+    .unwrap();
+    static ref WATCH_ASSIGNMENTS_STREAMS: IntGauge = register_int_gauge!(
+        "frontend_watch_assignments_streams",
+        "Number of open WatchAssignments streams."
+    )
+    .unwrap();
+}
+
+/// Why a ticket stopped waiting for a match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Assigned,
+    /// The ticket was deleted or replaced, or the player left.
+    Cancelled,
+    TimedOut,
+    Failed,
+}
+
+impl Outcome {
+    /// The outcome of a WatchAssignments stream that ended with `status`.
+    pub fn from_status(status: &tonic::Status) -> Self {
+        match status.code() {
+            tonic::Code::Cancelled | tonic::Code::NotFound => Outcome::Cancelled,
+            tonic::Code::DeadlineExceeded => Outcome::TimedOut,
+            _ => Outcome::Failed,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Assigned => "assigned",
+            Outcome::Cancelled => "cancelled",
+            Outcome::TimedOut => "timed_out",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+pub fn record_ticket_created() {
+    TICKETS_CREATED.inc();
+}
+
+/// Records the end of the wait of a ticket that was requested `elapsed` ago.
+pub fn record_ticket_finished(outcome: Outcome, elapsed: Duration) {
+    TICKETS_FINISHED.with_label_values(&[outcome.label()]).inc();
+    if outcome == Outcome::Assigned {
+        TIME_TO_ASSIGNMENT.observe(elapsed.as_secs_f64());
+    }
+}
+
+pub fn watch_started() {
+    WATCH_ASSIGNMENTS_STREAMS.inc();
+}
+
+pub fn watch_finished() {
+    WATCH_ASSIGNMENTS_STREAMS.dec();
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+use uuid::Uuid;
+
+/// Outcome of a party's ticket: the assigned connection or the reason it failed.
+pub type PartyMatchResult = Result<String, String>;
+
+#[derive(Debug)]
+pub enum PartyError {
+    NotFound,
+    NotMember,
+    NotLeader,
+    AlreadyInParty,
+    Full,
+    Queued,
+    Internal(String),
+}
+
+impl From<PartyError> for tonic::Status {
+    fn from(err: PartyError) -> Self {
+        match err {
+            PartyError::NotFound => tonic::Status::new(tonic::Code::NotFound, "party not found"),
+            PartyError::NotMember => tonic::Status::new(
+                tonic::Code::PermissionDenied,
+                "player is not a member of the party",
+            ),
+            PartyError::NotLeader => tonic::Status::new(
+                tonic::Code::PermissionDenied,
+                "only the party leader can do this",
+            ),
+            PartyError::AlreadyInParty => {
+                tonic::Status::new(tonic::Code::AlreadyExists, "player is already in a party")
+            }
+            PartyError::Full => tonic::Status::new(tonic::Code::ResourceExhausted, "party is full"),
+            PartyError::Queued => tonic::Status::new(
+                tonic::Code::FailedPrecondition,
+                "party is queued for a match",
+            ),
+            PartyError::Internal(err) => tonic::Status::new(tonic::Code::Internal, err),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub player_id: String,
+    pub skill_rating: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PartySnapshot {
+    pub party_id: String,
+    pub leader_id: String,
+    pub members: Vec<Member>,
+}
+
+struct Party {
+    leader_id: String,
+    members: Vec<Member>,
+    queued: bool,
+    // last change of the members or the queue
+    updated: Instant,
+    result_tx: watch::Sender<Option<PartyMatchResult>>,
+    result_rx: watch::Receiver<Option<PartyMatchResult>>,
+}
+
+impl Party {
+    fn snapshot(&self, party_id: &str) -> PartySnapshot {
+        PartySnapshot {
+            party_id: party_id.to_string(),
+            leader_id: self.leader_id.clone(),
+            members: self.members.clone(),
+        }
+    }
+
+    fn is_member(&self, player_id: &str) -> bool {
+        self.members.iter().any(|m| m.player_id == player_id)
+    }
+}
+
+struct State {
+    parties: HashMap<String, Party>,
+    // player_id -> party_id
+    memberships: HashMap<String, String>,
+}
+
+impl State {
+    /// Disbands the parties that weren't queued or changed for `idle_timeout`, since players
+    /// who disconnect never leave them.
+    fn remove_idle(&mut self, idle_timeout: Duration) {
+        let parties = &mut self.parties;
+        let memberships = &mut self.memberships;
+        parties.retain(|_, party| {
+            let idle = !party.queued && party.updated.elapsed() >= idle_timeout;
+            if idle {
+                for member in &party.members {
+                    memberships.remove(&member.player_id);
+                }
+            }
+            !idle
+        });
+    }
+}
+
+/// In-memory registry of parties, shared by all requests of one frontend.
+pub struct PartyRegistry {
+    max_party_size: usize,
+    idle_timeout: Duration,
+    state: Mutex<State>,
+}
+
+impl PartyRegistry {
+    pub fn new(max_party_size: usize, idle_timeout: Duration) -> Self {
+        PartyRegistry {
+            max_party_size,
+            idle_timeout,
+            state: Mutex::new(State {
+                parties: HashMap::new(),
+                memberships: HashMap::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<State>, PartyError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|err| PartyError::Internal(err.to_string()))?;
+        state.remove_idle(self.idle_timeout);
+        Ok(state)
+    }
+
+    pub fn create(&self, player_id: &str, skill_rating: f64) -> Result<PartySnapshot, PartyError> {
+        let mut state = self.lock()?;
+        if state.memberships.contains_key(player_id) {
+            return Err(PartyError::AlreadyInParty);
+        }
+        let party_id = Uuid::new_v4().to_string();
+        let (result_tx, result_rx) = watch::channel(None);
+        let party = Party {
+            leader_id: player_id.to_string(),
+            members: vec![Member {
+                player_id: player_id.to_string(),
+                skill_rating,
+            }],
+            queued: false,
+            updated: Instant::now(),
+            result_tx,
+            result_rx,
+        };
+        let snapshot = party.snapshot(&party_id);
+        state.parties.insert(party_id.clone(), party);
+        state
+            .memberships
+            .insert(player_id.to_string(), party_id.clone());
+        Ok(snapshot)
+    }
+
+    pub fn join(
+        &self,
+        party_id: &str,
+        player_id: &str,
+        skill_rating: f64,
+    ) -> Result<PartySnapshot, PartyError> {
+        let mut state = self.lock()?;
+        if state.memberships.contains_key(player_id) {
+            return Err(PartyError::AlreadyInParty);
+        }
+        let max_party_size = self.max_party_size;
+        let party = state
+            .parties
+            .get_mut(party_id)
+            .ok_or(PartyError::NotFound)?;
+        if party.queued {
+            return Err(PartyError::Queued);
+        }
+        if party.members.len() >= max_party_size {
+            return Err(PartyError::Full);
+        }
+        party.members.push(Member {
+            player_id: player_id.to_string(),
+            skill_rating,
+        });
+        party.updated = Instant::now();
+        let snapshot = party.snapshot(party_id);
+        state
+            .memberships
+            .insert(player_id.to_string(), party_id.to_string());
+        Ok(snapshot)
+    }
+
+    /// Removes the player from the party. The next member becomes leader when the leader
+    /// leaves, and the party is disbanded when nobody is left.
+    pub fn leave(&self, party_id: &str, player_id: &str) -> Result<PartySnapshot, PartyError> {
+        let mut state = self.lock()?;
+        let party = state
+            .parties
+            .get_mut(party_id)
+            .ok_or(PartyError::NotFound)?;
+        if !party.is_member(player_id) {
+            return Err(PartyError::NotMember);
+        }
+        if party.queued {
+            return Err(PartyError::Queued);
+        }
+        party.members.retain(|m| m.player_id != player_id);
+        party.updated = Instant::now();
+        if party.leader_id == player_id {
+            if let Some(next) = party.members.first() {
+                party.leader_id = next.player_id.clone();
+            }
+        }
+        let snapshot = party.snapshot(party_id);
+        if snapshot.members.is_empty() {
+            state.parties.remove(party_id);
+        }
+        state.memberships.remove(player_id);
+        Ok(snapshot)
+    }
+
+    /// Marks the party as queued. Only the leader can queue the party.
+    pub fn start_queue(
+        &self,
+        party_id: &str,
+        player_id: &str,
+    ) -> Result<PartySnapshot, PartyError> {
+        let mut state = self.lock()?;
+        let party = state
+            .parties
+            .get_mut(party_id)
+            .ok_or(PartyError::NotFound)?;
+        if party.leader_id != player_id {
+            return Err(PartyError::NotLeader);
+        }
+        if party.queued {
+            return Err(PartyError::Queued);
+        }
+        party.queued = true;
+        party.updated = Instant::now();
+        // Drop the result of the previous queue so that new watchers don't see it.
+        if let Err(err) = party.result_tx.broadcast(None) {
+            return Err(PartyError::Internal(format!("{:?}", err)));
+        }
+        Ok(party.snapshot(party_id))
+    }
+
+    /// Marks the party as no longer queued and notifies the members watching it. The result is
+    /// kept for members who watch later, until the party is queued again.
+    pub fn finish_queue(&self, party_id: &str, result: PartyMatchResult) {
+        if let Ok(mut state) = self.lock() {
+            if let Some(party) = state.parties.get_mut(party_id) {
+                party.queued = false;
+                party.updated = Instant::now();
+                // Broadcasting only fails when no member is watching.
+                let _ = party.result_tx.broadcast(Some(result));
+            }
+        }
+    }
+
+    /// Returns a receiver for the result of the party's last, current or next queue.
+    pub fn subscribe(
+        &self,
+        party_id: &str,
+        player_id: &str,
+    ) -> Result<watch::Receiver<Option<PartyMatchResult>>, PartyError> {
+        let state = self.lock()?;
+        let party = state.parties.get(party_id).ok_or(PartyError::NotFound)?;
+        if !party.is_member(player_id) {
+            return Err(PartyError::NotMember);
+        }
+        Ok(party.result_rx.clone())
+    }
+}
@@ -0,0 +1,195 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use grpc_health::HealthReporter;
+use grpc_tls::{incoming, TlsConfig};
+use serde::{Deserialize, Serialize};
+use settings::{EnvKey, Validate};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use super::metrics;
+use super::parties::PartyRegistry;
+use super::service::{mm, GameFrontend};
+use super::tickets::{DuplicateTicketPolicy, InMemoryActiveTicketStore};
+
+const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/frontend_descriptor.bin"));
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrontendConfig {
+    pub address: SocketAddr,
+    pub om_frontend_address: String,
+    pub duplicate_ticket_policy: DuplicateTicketPolicy,
+    pub max_party_size: usize,
+    /// Parties that aren't queued or changed for this long are disbanded.
+    pub party_idle_timeout_ms: u64,
+    /// Tickets that aren't assigned for this long are deleted. Zero keeps them until they are.
+    pub ticket_timeout_ms: u64,
+    /// TLS of the player-facing server.
+    pub tls: TlsConfig,
+    /// Plaintext address serving only the health service, for Kubernetes gRPC probes, which
+    /// can't speak TLS, when `tls` is set.
+    pub health_address: Option<SocketAddr>,
+    /// mTLS to Open Match.
+    pub om_tls: TlsConfig,
+    pub metrics_address: SocketAddr,
+}
+
+impl Default for FrontendConfig {
+    fn default() -> Self {
+        FrontendConfig {
+            address: ([0, 0, 0, 0], 10001).into(),
+            om_frontend_address: "om-frontend.open-match.svc.cluster.local:50504".to_string(),
+            duplicate_ticket_policy: DuplicateTicketPolicy::Reject,
+            max_party_size: 4,
+            party_idle_timeout_ms: 30 * 60 * 1000,
+            ticket_timeout_ms: 0,
+            tls: TlsConfig::default(),
+            health_address: None,
+            om_tls: TlsConfig::default(),
+            metrics_address: ([0, 0, 0, 0], 9090).into(),
+        }
+    }
+}
+
+/// Environment variables overriding the config file.
+pub const ENV_KEYS: &[EnvKey] = &[
+    ("ADDRESS", "address"),
+    ("OM_FRONTEND_ADDRESS", "om_frontend_address"),
+    ("DUPLICATE_TICKET_POLICY", "duplicate_ticket_policy"), // reject or replace
+    ("MAX_PARTY_SIZE", "max_party_size"),
+    ("PARTY_IDLE_TIMEOUT_MS", "party_idle_timeout_ms"),
+    ("TICKET_TIMEOUT_MS", "ticket_timeout_ms"),
+    ("TLS_CERT_PATH", "tls.cert_path"),
+    ("TLS_KEY_PATH", "tls.key_path"),
+    ("TLS_CA_PATH", "tls.ca_path"),
+    ("HEALTH_ADDRESS", "health_address"),
+    ("OM_TLS_CERT_PATH", "om_tls.cert_path"),
+    ("OM_TLS_KEY_PATH", "om_tls.key_path"),
+    ("OM_TLS_CA_PATH", "om_tls.ca_path"),
+    ("OM_TLS_DOMAIN_NAME", "om_tls.domain_name"),
+    ("METRICS_ADDRESS", "metrics_address"),
+];
+
+impl Validate for FrontendConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.om_frontend_address.is_empty() {
+            return Err("om_frontend_address must not be empty".to_string());
+        }
+        if self.max_party_size == 0 {
+            return Err("max_party_size must be positive".to_string());
+        }
+        if self.party_idle_timeout_ms == 0 {
+            return Err("party_idle_timeout_ms must be positive".to_string());
+        }
+        if self.health_address == Some(self.address) {
+            return Err(format!(
+                "health_address must differ from address: {}",
+                self.address
+            ));
+        }
+        self.tls.validate().map_err(|err| format!("tls: {}", err))?;
+        self.om_tls
+            .validate()
+            .map_err(|err| format!("om_tls: {}", err))?;
+        Ok(())
+    }
+}
+
+impl FrontendConfig {
+    /// Loads the config from the config file, the environment and the command line.
+    pub fn load() -> Result<Self, settings::Error> {
+        settings::load(ENV_KEYS)
+    }
+}
+
+/// Builds the game frontend server.
+pub struct ServerBuilder {
+    config: FrontendConfig,
+    listener: Option<TcpListener>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
+
+impl ServerBuilder {
+    pub fn new(config: FrontendConfig) -> Self {
+        ServerBuilder {
+            config,
+            listener: None,
+            shutdown: None,
+        }
+    }
+
+    /// Serves on `listener` instead of binding the configured address.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Stops accepting connections once `signal` resolves.
+    pub fn shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Connects to Open Match and binds the listener. The returned future runs the server.
+    /// The server is reported serving while Open Match answers.
+    pub async fn build(
+        self,
+    ) -> Result<BoxFuture<'static, Result<(), tonic::transport::Error>>, Box<dyn std::error::Error>>
+    {
+        let mut gf = GameFrontend::new(
+            self.config.om_frontend_address,
+            &self.config.om_tls,
+            InMemoryActiveTicketStore::new(),
+            self.config.duplicate_ticket_policy,
+            PartyRegistry::new(
+                self.config.max_party_size,
+                Duration::from_millis(self.config.party_idle_timeout_ms),
+            ),
+        )
+        .await?;
+        if self.config.ticket_timeout_ms > 0 {
+            gf = gf.with_ticket_timeout(Duration::from_millis(self.config.ticket_timeout_ms));
+        }
+        let health = HealthReporter::new();
+        health.poll(
+            &[grpc_health::SERVER, "matchmaker.Frontend"],
+            grpc_health::PROBE_INTERVAL,
+            gf.health_check(),
+        );
+        let reflection = grpc_health::reflection::service(&[FILE_DESCRIPTOR_SET])?;
+        let listener = match self.listener {
+            Some(listener) => listener,
+            None => TcpListener::bind(self.config.address).await?,
+        };
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(tls) = grpc_tls::server_tls(&self.config.tls)? {
+            builder.tls_config(&tls);
+        }
+        let server = builder
+            .add_service(mm::frontend_server::FrontendServer::new(gf))
+            .add_service(health.service())
+            .add_service(reflection)
+            .serve_with_incoming(incoming(listener, self.shutdown));
+        Ok(health.with_plaintext_health(server, self.config.health_address))
+    }
+}
+
+pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
+    info!("start game frontend server");
+    let config = FrontendConfig::load()?;
+    let metrics_address = config.metrics_address;
+    tokio::spawn(async move {
+        if let Err(err) = telemetry::metrics::serve(metrics_address).await {
+            error!("metrics server error: {:?}", err);
+        }
+    });
+    ServerBuilder::new(config).build().await?.await?;
+    Ok(())
+}
@@ -1,9 +1,22 @@
 use std::collections::HashMap;
-use std::env;
-use std::time::SystemTime;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
-use log::{debug, error, info};
-use tokio::sync::mpsc;
+use futures::future::BoxFuture;
+use futures::Stream;
+use grpc_tls::TlsConfig;
+use om_ext::{string_any, string_list_any, PLAYER_IDS_EXTENSION, PLAYER_ID_EXTENSION};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use tracing::{debug, error, field, info, info_span};
+use tracing_futures::Instrument;
+use uuid::Uuid;
+
+use super::metrics::{self, Outcome};
+use super::parties::{PartyRegistry, PartySnapshot};
+use super::tickets::{ActiveTicketStore, DuplicateTicketPolicy};
 
 pub mod mm {
     tonic::include_proto!("matchmaker");
@@ -13,62 +26,445 @@ pub mod om {
     tonic::include_proto!("openmatch");
 }
 
-pub struct GameFrontend {
-    om_frontend_service_client:
-        om::frontend_service_client::FrontendServiceClient<tonic::transport::channel::Channel>,
+// Keys of the Open Match search fields and extensions filled from CreateMatchRequest.
+// The match function and the director profiles filter on these names.
+pub const SKILL_RATING_ARG: &str = "skill_rating";
+pub const LATENCY_ARG_PREFIX: &str = "latency.";
+pub const REGION_ARG: &str = "region";
+pub const GAME_MODE_ARG: &str = "game_mode";
+pub const QUEUE_ARG: &str = "queue";
+pub const PARTY_ID_ARG: &str = "party_id";
+pub const PARTY_SIZE_ARG: &str = "party_size";
+
+fn search_fields(req: &mm::CreateMatchRequest) -> om::SearchFields {
+    let mut double_args = HashMap::new();
+    double_args.insert(SKILL_RATING_ARG.to_string(), req.skill_rating);
+    for (region, latency) in &req.latencies {
+        double_args.insert(format!("{}{}", LATENCY_ARG_PREFIX, region), *latency);
+    }
+
+    let mut string_args = HashMap::new();
+    if !req.region.is_empty() {
+        string_args.insert(REGION_ARG.to_string(), req.region.clone());
+    }
+    if !req.game_mode.is_empty() {
+        string_args.insert(GAME_MODE_ARG.to_string(), req.game_mode.clone());
+    }
+    if !req.queue.is_empty() {
+        string_args.insert(QUEUE_ARG.to_string(), req.queue.clone());
+    }
+    if !req.party_id.is_empty() {
+        string_args.insert(PARTY_ID_ARG.to_string(), req.party_id.clone());
+    }
+
+    om::SearchFields {
+        double_args,
+        string_args,
+        tags: req.tags.clone(),
+    }
+}
+
+fn extensions(
+    req: &mm::CreateMatchRequest,
+) -> Result<HashMap<String, prost_types::Any>, prost::EncodeError> {
+    let mut extensions = HashMap::new();
+    extensions.insert(PLAYER_ID_EXTENSION.to_string(), string_any(&req.player_id)?);
+    Ok(extensions)
+}
+
+/// Builds the ticket of a whole party. The party is rated by the mean rating of its members.
+fn party_ticket(
+    req: &mm::CreateMatchRequest,
+    party: &PartySnapshot,
+) -> Result<om::Ticket, prost::EncodeError> {
+    let player_ids: Vec<String> = party.members.iter().map(|m| m.player_id.clone()).collect();
+    let skill_rating = party.members.iter().map(|m| m.skill_rating).sum::<f64>()
+        / party.members.len().max(1) as f64;
+
+    let mut search_fields = search_fields(req);
+    search_fields
+        .double_args
+        .insert(SKILL_RATING_ARG.to_string(), skill_rating);
+    search_fields
+        .double_args
+        .insert(PARTY_SIZE_ARG.to_string(), player_ids.len() as f64);
+
+    let mut extensions = extensions(req)?;
+    extensions.insert(
+        PLAYER_IDS_EXTENSION.to_string(),
+        string_list_any(&player_ids)?,
+    );
+
+    Ok(om::Ticket {
+        id: "".to_string(), // auto gen by open match
+        assignment: None,
+        search_fields: Some(search_fields),
+        extensions,
+        create_time: None,
+    })
+}
+
+fn party_pb(party: PartySnapshot) -> mm::Party {
+    mm::Party {
+        party_id: party.party_id,
+        leader_id: party.leader_id,
+        members: party
+            .members
+            .into_iter()
+            .map(|m| mm::PartyMember {
+                player_id: m.player_id,
+                skill_rating: m.skill_rating,
+            })
+            .collect(),
+    }
+}
+
+async fn delete_ticket(
+    client: &mut om::frontend_service_client::FrontendServiceClient<
+        telemetry::Traced<tonic::transport::channel::Channel>,
+    >,
+    ticket_id: &str,
+) {
+    if let Err(err) = client
+        .delete_ticket(tonic::Request::new(om::DeleteTicketRequest {
+            ticket_id: ticket_id.to_string(),
+        }))
+        .await
+    {
+        error!("failed to delete ticket: {:?}", err);
+    }
 }
 
-impl GameFrontend {
-    async fn new(om_frontend_address: String) -> Result<Self, tonic::transport::Error> {
-        let client = om::frontend_service_client::FrontendServiceClient::connect(format!(
-            "http://{}",
-            om_frontend_address
-        ))
-        .await?;
+/// The response stream of CreateMatch. Dropping it, which tonic does when the player leaves,
+/// drops `_left` and so tells the watch of the ticket.
+pub struct MatchStream {
+    rx: mpsc::Receiver<Result<mm::CreateMatchResponse, tonic::Status>>,
+    _left: oneshot::Sender<()>,
+}
+
+impl Stream for MatchStream {
+    type Item = Result<mm::CreateMatchResponse, tonic::Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+async fn remove_active_tickets<S>(active_tickets: &S, player_ids: &[String], ticket_id: &str)
+where
+    S: ActiveTicketStore,
+{
+    for player_id in player_ids {
+        if let Err(err) = active_tickets.remove(player_id, ticket_id).await {
+            error!("failed to remove active ticket: {:?}", err);
+        }
+    }
+}
+
+pub struct GameFrontend<S>
+where
+    S: ActiveTicketStore,
+{
+    om_frontend_service_client: om::frontend_service_client::FrontendServiceClient<
+        telemetry::Traced<tonic::transport::channel::Channel>,
+    >,
+    active_tickets: Arc<S>,
+    duplicate_ticket_policy: DuplicateTicketPolicy,
+    parties: Arc<PartyRegistry>,
+    ticket_timeout: Option<Duration>,
+}
+
+impl<S> GameFrontend<S>
+where
+    S: ActiveTicketStore,
+{
+    pub async fn new(
+        om_frontend_address: String,
+        om_tls: &TlsConfig,
+        active_tickets: S,
+        duplicate_ticket_policy: DuplicateTicketPolicy,
+        parties: PartyRegistry,
+    ) -> Result<Self, grpc_tls::Error> {
+        let channel = grpc_tls::connect(&om_frontend_address, om_tls).await?;
+        let client = om::frontend_service_client::FrontendServiceClient::new(
+            telemetry::Traced::new(channel),
+        );
         Ok(GameFrontend {
             om_frontend_service_client: client,
+            active_tickets: Arc::new(active_tickets),
+            duplicate_ticket_policy,
+            parties: Arc::new(parties),
+            ticket_timeout: None,
         })
     }
+
+    /// Deletes the tickets that aren't assigned within `timeout` and tells their players.
+    pub fn with_ticket_timeout(mut self, timeout: Duration) -> Self {
+        self.ticket_timeout = Some(timeout);
+        self
+    }
+
+    /// Checks whether Open Match answers. The ticket does not exist, so any answer will do.
+    pub fn health_check(&self) -> impl FnMut() -> BoxFuture<'static, bool> + Send + 'static {
+        let client = self.om_frontend_service_client.clone();
+        move || {
+            let mut client = client.clone();
+            Box::pin(async move {
+                let res = client
+                    .get_ticket(om::GetTicketRequest {
+                        ticket_id: String::new(),
+                    })
+                    .await;
+                grpc_health::is_reachable(&res)
+            })
+        }
+    }
+
+    /// Reserves the players with a placeholder ticket before their ticket is created, so a
+    /// rejected request leaves no ticket in Open Match. Only the `Reject` policy reserves.
+    async fn reserve_players(
+        &self,
+        player_ids: &[String],
+    ) -> Result<Option<String>, tonic::Status> {
+        if self.duplicate_ticket_policy != DuplicateTicketPolicy::Reject {
+            return Ok(None);
+        }
+        let placeholder = format!("pending-{}", Uuid::new_v4());
+        let mut reserved = Vec::with_capacity(player_ids.len());
+        for player_id in player_ids {
+            let res = match self
+                .active_tickets
+                .insert_if_absent(player_id, &placeholder)
+                .await
+            {
+                Ok(None) => Ok(()),
+                Ok(Some(active)) => Err(tonic::Status::new(
+                    tonic::Code::AlreadyExists,
+                    format!(
+                        "player {} already has an active ticket: {}",
+                        player_id, active
+                    ),
+                )),
+                Err(err) => Err(tonic::Status::new(
+                    tonic::Code::Unavailable,
+                    err.to_string(),
+                )),
+            };
+            if let Err(err) = res {
+                remove_active_tickets(&*self.active_tickets, &reserved, &placeholder).await;
+                return Err(err);
+            }
+            reserved.push(player_id.clone());
+        }
+        Ok(Some(placeholder))
+    }
+
+    /// Registers `ticket_id` as the active ticket of every player, replacing their placeholder
+    /// or, with the `Replace` policy, their previous ticket. The new ticket is deleted again
+    /// when it cannot be registered.
+    async fn activate_ticket(
+        &self,
+        player_ids: &[String],
+        ticket_id: &str,
+        placeholder: Option<&str>,
+    ) -> Result<(), tonic::Status> {
+        let mut client = self.om_frontend_service_client.clone();
+        let mut activated = Vec::with_capacity(player_ids.len());
+        let mut result = Ok(());
+        for player_id in player_ids {
+            let res = match self.active_tickets.replace(player_id, ticket_id).await {
+                Ok(Some(replaced)) => {
+                    if Some(replaced.as_str()) != placeholder && replaced != ticket_id {
+                        debug!("replace ticket: {} -> {}", replaced, ticket_id);
+                        delete_ticket(&mut client, &replaced).await;
+                    }
+                    Ok(())
+                }
+                Ok(None) => Ok(()),
+                Err(err) => Err(tonic::Status::new(
+                    tonic::Code::Unavailable,
+                    err.to_string(),
+                )),
+            };
+            if let Err(err) = res {
+                result = Err(err);
+                break;
+            }
+            activated.push(player_id.clone());
+        }
+        if result.is_err() {
+            remove_active_tickets(&*self.active_tickets, &activated, ticket_id).await;
+            if let Some(placeholder) = placeholder {
+                remove_active_tickets(&*self.active_tickets, player_ids, placeholder).await;
+            }
+            delete_ticket(&mut client, ticket_id).await;
+        }
+        result
+    }
+
+    /// Reserves `player_ids`, creates the ticket, registers it for them and returns the
+    /// assignment stream.
+    async fn queue(
+        &self,
+        ticket: om::Ticket,
+        player_ids: &[String],
+    ) -> Result<(om::Ticket, tonic::Streaming<om::WatchAssignmentsResponse>), tonic::Status> {
+        let placeholder = self.reserve_players(player_ids).await?;
+        let mut client = self.om_frontend_service_client.clone();
+        let create_ticket_res = client
+            .create_ticket(tonic::Request::new(om::CreateTicketRequest {
+                ticket: Some(ticket),
+            }))
+            .await;
+        let ticket = match create_ticket_res {
+            Ok(res) => res.into_inner(),
+            Err(err) => {
+                if let Some(placeholder) = &placeholder {
+                    remove_active_tickets(&*self.active_tickets, player_ids, placeholder).await;
+                }
+                return Err(err);
+            }
+        };
+        metrics::record_ticket_created();
+        tracing::Span::current().record("ticket_id", &ticket.id.as_str());
+        debug!("created ticket: {:?}", ticket);
+        self.activate_ticket(player_ids, &ticket.id, placeholder.as_deref())
+            .await?;
+
+        match client
+            .watch_assignments(tonic::Request::new(om::WatchAssignmentsRequest {
+                ticket_id: ticket.id.clone(),
+            }))
+            .await
+        {
+            Ok(res) => Ok((ticket, res.into_inner())),
+            Err(err) => {
+                delete_ticket(&mut client, &ticket.id).await;
+                remove_active_tickets(&*self.active_tickets, player_ids, &ticket.id).await;
+                Err(err)
+            }
+        }
+    }
 }
 
 #[tonic::async_trait]
-impl mm::frontend_server::Frontend for GameFrontend {
-    type CreateMatchStream = mpsc::Receiver<Result<mm::CreateMatchResponse, tonic::Status>>;
+impl<S> mm::frontend_server::Frontend for GameFrontend<S>
+where
+    S: ActiveTicketStore + Send + Sync + 'static,
+{
+    type CreateMatchStream = MatchStream;
     async fn create_match(
         &self,
         request: tonic::Request<mm::CreateMatchRequest>,
     ) -> Result<tonic::Response<Self::CreateMatchStream>, tonic::Status> {
+        let requested = Instant::now();
         let (mut tx, rx) = mpsc::channel(1);
         let mut client = self.om_frontend_service_client.clone();
-        let player_id = request.into_inner().player_id;
-        debug!("requested: {}", player_id);
+        let traceparent = telemetry::traceparent(request.metadata()).to_string();
+        let req = request.into_inner();
+        let span = info_span!(
+            "create_match",
+            traceparent = %traceparent,
+            player_id = %req.player_id,
+            party_id = %req.party_id,
+            ticket_id = field::Empty,
+            match_id = field::Empty,
+            gameserver = field::Empty,
+        );
+        span.in_scope(|| debug!("requested: {:?}", req));
+        if req.player_id.is_empty() {
+            return Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "please specify player_id",
+            ));
+        }
 
-        let create_ticket_req = om::CreateTicketRequest {
-            ticket: Some(om::Ticket {
+        let (ticket, player_ids) = if req.party_id.is_empty() {
+            let extensions = extensions(&req)
+                .map_err(|err| tonic::Status::new(tonic::Code::Internal, err.to_string()))?;
+            let ticket = om::Ticket {
                 id: "".to_string(), // auto gen by open match
                 assignment: None,
-                search_fields: Some(om::SearchFields {
-                    double_args: HashMap::new(),
-                    string_args: HashMap::new(),
-                    tags: vec![],
-                }),
-                extensions: std::collections::HashMap::new(),
+                search_fields: Some(search_fields(&req)),
+                extensions,
                 create_time: None,
-            }),
+            };
+            (ticket, vec![req.player_id.clone()])
+        } else {
+            let party = self.parties.start_queue(&req.party_id, &req.player_id)?;
+            let player_ids = party.members.iter().map(|m| m.player_id.clone()).collect();
+            match party_ticket(&req, &party) {
+                Ok(ticket) => (ticket, player_ids),
+                Err(err) => {
+                    self.parties
+                        .finish_queue(&req.party_id, Err(err.to_string()));
+                    return Err(tonic::Status::new(tonic::Code::Internal, err.to_string()));
+                }
+            }
         };
-        let create_ticket_res = client
-            .create_ticket(tonic::Request::new(create_ticket_req))
-            .await?;
-        let ticket = create_ticket_res.into_inner();
-        debug!("created ticket: {:?}", ticket);
-        let watch_assignments_res = client
-            .watch_assignments(tonic::Request::new(om::WatchAssignmentsRequest {
-                ticket_id: ticket.id.clone(),
-            }))
-            .await?;
-        let mut inbound = watch_assignments_res.into_inner();
-        tokio::spawn(async move {
-            while let Ok(assignment_res) = inbound.message().await {
+
+        let (ticket, mut inbound) = match self
+            .queue(ticket, &player_ids)
+            .instrument(span.clone())
+            .await
+        {
+            Ok(queued) => queued,
+            Err(err) => {
+                if !req.party_id.is_empty() {
+                    self.parties
+                        .finish_queue(&req.party_id, Err(err.message().to_string()));
+                }
+                return Err(err);
+            }
+        };
+        let active_tickets = self.active_tickets.clone();
+        let parties = self.parties.clone();
+        let party_id = req.party_id;
+        let (left_tx, mut left_rx) = oneshot::channel::<()>();
+        let ticket_timeout = self.ticket_timeout;
+        let watch_assignment = async move {
+            let deadline = async move {
+                match ticket_timeout {
+                    Some(timeout) => time::delay_for(timeout).await,
+                    None => futures::future::pending().await,
+                }
+            };
+            futures::pin_mut!(deadline);
+            metrics::watch_started();
+            let mut result = Err("failed to assign match request".to_string());
+            let mut outcome = Outcome::Failed;
+            loop {
+                let assignment_res = tokio::select! {
+                    res = inbound.message() => match res {
+                        Ok(res) => res,
+                        Err(status) => {
+                            outcome = Outcome::from_status(&status);
+                            break;
+                        }
+                    },
+                    _ = &mut left_rx => {
+                        info!("player left");
+                        outcome = Outcome::Cancelled;
+                        delete_ticket(&mut client, &ticket.id).await;
+                        break;
+                    }
+                    _ = &mut deadline => {
+                        info!("ticket timed out");
+                        outcome = Outcome::TimedOut;
+                        result = Err("no match was found in time".to_string());
+                        delete_ticket(&mut client, &ticket.id).await;
+                        if let Err(err) = tx
+                            .send(Err(tonic::Status::new(
+                                tonic::Code::DeadlineExceeded,
+                                "no match was found in time",
+                            )))
+                            .await
+                        {
+                            error!("failed to send: {:?}", err);
+                        }
+                        break;
+                    }
+                };
                 let assignment = match assignment_res {
                     Some(res) => match res.assignment {
                         Some(assignment) => assignment,
@@ -101,6 +497,14 @@ impl mm::frontend_server::Frontend for GameFrontend {
                     }
                 };
                 let connection = assignment.connection;
+                // the director assigns "<match_id>,<gameserver address>"
+                let mut parts = connection.splitn(2, ',');
+                let span = tracing::Span::current();
+                span.record("match_id", &parts.next().unwrap_or_default());
+                span.record("gameserver", &parts.next().unwrap_or_default());
+                info!("assigned");
+                result = Ok(connection.clone());
+                outcome = Outcome::Assigned;
                 let res = mm::CreateMatchResponse {
                     game_server: Some(mm::GameServer {
                         address: connection,
@@ -109,13 +513,85 @@ impl mm::frontend_server::Frontend for GameFrontend {
                 if let Err(err) = tx.send(Ok(res)).await {
                     error!("failed to send: {:?}", err);
                 }
-                if let Err(err) = client
-                    .delete_ticket(tonic::Request::new(om::DeleteTicketRequest {
-                        ticket_id: ticket.id.clone(),
-                    }))
-                    .await
-                {
-                    error!("failed to delete ticket: {:?}", err);
+                delete_ticket(&mut client, &ticket.id).await;
+                break;
+            }
+            metrics::watch_finished();
+            metrics::record_ticket_finished(outcome, requested.elapsed());
+            remove_active_tickets(&*active_tickets, &player_ids, &ticket.id).await;
+            if !party_id.is_empty() {
+                parties.finish_queue(&party_id, result);
+            }
+        };
+        tokio::spawn(watch_assignment.instrument(span));
+        Ok(tonic::Response::new(MatchStream { rx, _left: left_tx }))
+    }
+
+    async fn create_party(
+        &self,
+        request: tonic::Request<mm::CreatePartyRequest>,
+    ) -> Result<tonic::Response<mm::Party>, tonic::Status> {
+        let req = request.into_inner();
+        if req.player_id.is_empty() {
+            return Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "please specify player_id",
+            ));
+        }
+        let party = self.parties.create(&req.player_id, req.skill_rating)?;
+        debug!("created party: {:?}", party);
+        Ok(tonic::Response::new(party_pb(party)))
+    }
+
+    async fn join_party(
+        &self,
+        request: tonic::Request<mm::JoinPartyRequest>,
+    ) -> Result<tonic::Response<mm::Party>, tonic::Status> {
+        let req = request.into_inner();
+        if req.player_id.is_empty() {
+            return Err(tonic::Status::new(
+                tonic::Code::InvalidArgument,
+                "please specify player_id",
+            ));
+        }
+        let party = self
+            .parties
+            .join(&req.party_id, &req.player_id, req.skill_rating)?;
+        debug!("joined party: {:?}", party);
+        Ok(tonic::Response::new(party_pb(party)))
+    }
+
+    async fn leave_party(
+        &self,
+        request: tonic::Request<mm::LeavePartyRequest>,
+    ) -> Result<tonic::Response<mm::Party>, tonic::Status> {
+        let req = request.into_inner();
+        let party = self.parties.leave(&req.party_id, &req.player_id)?;
+        debug!("left party: {:?}", party);
+        Ok(tonic::Response::new(party_pb(party)))
+    }
+
+    type WatchPartyMatchStream = mpsc::Receiver<Result<mm::CreateMatchResponse, tonic::Status>>;
+    async fn watch_party_match(
+        &self,
+        request: tonic::Request<mm::WatchPartyMatchRequest>,
+    ) -> Result<tonic::Response<Self::WatchPartyMatchStream>, tonic::Status> {
+        let (mut tx, rx) = mpsc::channel(1);
+        let req = request.into_inner();
+        let mut result_rx = self.parties.subscribe(&req.party_id, &req.player_id)?;
+        tokio::spawn(async move {
+            while let Some(result) = result_rx.recv().await {
+                let res = match result {
+                    Some(Ok(connection)) => Ok(mm::CreateMatchResponse {
+                        game_server: Some(mm::GameServer {
+                            address: connection,
+                        }),
+                    }),
+                    Some(Err(err)) => Err(tonic::Status::new(tonic::Code::Unavailable, err)),
+                    None => continue,
+                };
+                if let Err(err) = tx.send(res).await {
+                    error!("failed to send: {:?}", err);
                 }
                 return;
             }
@@ -123,22 +599,3 @@ impl mm::frontend_server::Frontend for GameFrontend {
         Ok(tonic::Response::new(rx))
     }
 }
-
-pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
-    info!("start game frontend server");
-
-    let om_frontend_address = env::var("OM_FRONTEND_ADDRESS")
-        .unwrap_or("om-frontend.open-match.svc.cluster.local:50504".to_string());
-    let address = env::var("ADDRESS")
-        .unwrap_or("0.0.0.0:10001".to_string())
-        .parse()
-        .expect("cannot parse ADDRESS");
-
-    let gf = GameFrontend::new(om_frontend_address).await?;
-    let svc = mm::frontend_server::FrontendServer::new(gf);
-    tonic::transport::Server::builder()
-        .add_service(svc)
-        .serve(address)
-        .await?;
-    Ok(())
-}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// What to do when a player who already has an active ticket asks for another match.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateTicketPolicy {
+    /// Refuse the new request with `AlreadyExists`.
+    Reject,
+    /// Delete the old ticket and queue the new one.
+    Replace,
+}
+
+impl std::str::FromStr for DuplicateTicketPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(DuplicateTicketPolicy::Reject),
+            "replace" => Ok(DuplicateTicketPolicy::Replace),
+            _ => Err(format!("invalid duplicate ticket policy: {}", s)),
+        }
+    }
+}
+
+/// Keeps track of the Open Match ticket each player is currently queued with.
+///
+/// Implementations must make every method atomic so that several frontend replicas
+/// can share one store.
+#[async_trait]
+pub trait ActiveTicketStore {
+    /// Records `ticket_id` as the active ticket of `player_id` unless the player already
+    /// has one. Returns the ticket that is already active, if any.
+    async fn insert_if_absent(&self, player_id: &str, ticket_id: &str) -> Result<Option<String>>;
+
+    /// Records `ticket_id` as the active ticket of `player_id` and returns the replaced one.
+    async fn replace(&self, player_id: &str, ticket_id: &str) -> Result<Option<String>>;
+
+    /// Forgets the active ticket of `player_id` if it is still `ticket_id`.
+    async fn remove(&self, player_id: &str, ticket_id: &str) -> Result<()>;
+}
+
+pub struct InMemoryActiveTicketStore {
+    tickets: Mutex<HashMap<String, String>>,
+}
+
+impl InMemoryActiveTicketStore {
+    pub fn new() -> Self {
+        InMemoryActiveTicketStore {
+            tickets: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ActiveTicketStore for InMemoryActiveTicketStore {
+    async fn insert_if_absent(&self, player_id: &str, ticket_id: &str) -> Result<Option<String>> {
+        let mut tickets = self.tickets.lock().map_err(|err| err.to_string())?;
+        if let Some(active) = tickets.get(player_id) {
+            return Ok(Some(active.clone()));
+        }
+        tickets.insert(player_id.to_string(), ticket_id.to_string());
+        Ok(None)
+    }
+
+    async fn replace(&self, player_id: &str, ticket_id: &str) -> Result<Option<String>> {
+        let mut tickets = self.tickets.lock().map_err(|err| err.to_string())?;
+        Ok(tickets.insert(player_id.to_string(), ticket_id.to_string()))
+    }
+
+    async fn remove(&self, player_id: &str, ticket_id: &str) -> Result<()> {
+        let mut tickets = self.tickets.lock().map_err(|err| err.to_string())?;
+        if tickets.get(player_id).map(|active| active == ticket_id) == Some(true) {
+            tickets.remove(player_id);
+        }
+        Ok(())
+    }
+}
+
+/// Test double that records every call and can simulate an unavailable backend. Clones share
+/// their state, so a test can keep one while the frontend owns another.
+#[derive(Clone)]
+pub struct MockActiveTicketStore {
+    inner: Arc<InMemoryActiveTicketStore>,
+    calls: Arc<Mutex<Vec<String>>>,
+    unavailable: Arc<AtomicBool>,
+}
+
+impl MockActiveTicketStore {
+    pub fn new() -> Self {
+        MockActiveTicketStore {
+            inner: Arc::new(InMemoryActiveTicketStore::new()),
+            calls: Arc::new(Mutex::new(Vec::new())),
+            unavailable: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Makes every following call fail (or succeed again).
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::SeqCst);
+    }
+
+    /// Returns the calls made so far, e.g. `["insert_if_absent(p1, t1)"]`.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    fn record(&self, call: String) -> Result<()> {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(call);
+        }
+        if self.unavailable.load(Ordering::SeqCst) {
+            return Err("active ticket store is unavailable".into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ActiveTicketStore for MockActiveTicketStore {
+    async fn insert_if_absent(&self, player_id: &str, ticket_id: &str) -> Result<Option<String>> {
+        self.record(format!("insert_if_absent({}, {})", player_id, ticket_id))?;
+        self.inner.insert_if_absent(player_id, ticket_id).await
+    }
+
+    async fn replace(&self, player_id: &str, ticket_id: &str) -> Result<Option<String>> {
+        self.record(format!("replace({}, {})", player_id, ticket_id))?;
+        self.inner.replace(player_id, ticket_id).await
+    }
+
+    async fn remove(&self, player_id: &str, ticket_id: &str) -> Result<()> {
+        self.record(format!("remove({}, {})", player_id, ticket_id))?;
+        self.inner.remove(player_id, ticket_id).await
+    }
+}
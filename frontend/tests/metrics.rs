@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use frontend::metrics::{self, Outcome};
+
+/// Returns the value of the sample of `name` with all of `labels`, or 0 if there is none.
+fn sample(name: &str, labels: &[&str]) -> f64 {
+    let metrics = String::from_utf8(telemetry::metrics::gather().unwrap()).unwrap();
+    metrics
+        .lines()
+        .filter(|line| line.starts_with(name))
+        .filter(|line| labels.iter().all(|label| line.contains(label)))
+        .filter_map(|line| line.rsplit(' ').next())
+        .map(|value| value.parse().unwrap())
+        .next()
+        .unwrap_or(0.0)
+}
+
+#[test]
+fn outcomes_follow_the_status_of_the_stream() {
+    let outcome = |code| Outcome::from_status(&tonic::Status::new(code, ""));
+    assert_eq!(outcome(tonic::Code::Cancelled), Outcome::Cancelled);
+    assert_eq!(outcome(tonic::Code::NotFound), Outcome::Cancelled);
+    assert_eq!(outcome(tonic::Code::DeadlineExceeded), Outcome::TimedOut);
+    assert_eq!(outcome(tonic::Code::Unavailable), Outcome::Failed);
+}
+
+#[test]
+fn finished_tickets_are_counted_by_outcome() {
+    let finished = |outcome| {
+        sample(
+            "frontend_tickets_finished_total",
+            &[&format!("outcome=\"{}\"", outcome)],
+        )
+    };
+    let assigned = finished("assigned");
+    let timed_out = finished("timed_out");
+    let waits = sample("frontend_time_to_assignment_seconds_count", &[]);
+
+    metrics::record_ticket_finished(Outcome::Assigned, Duration::from_secs(3));
+    metrics::record_ticket_finished(Outcome::TimedOut, Duration::from_secs(60));
+
+    assert_eq!(finished("assigned"), assigned + 1.0);
+    assert_eq!(finished("timed_out"), timed_out + 1.0);
+    // only assigned tickets have a time to assignment
+    assert_eq!(
+        sample("frontend_time_to_assignment_seconds_count", &[]),
+        waits + 1.0
+    );
+}
+
+#[test]
+fn open_streams_are_counted() {
+    let streams = sample("frontend_watch_assignments_streams", &[]);
+    metrics::watch_started();
+    metrics::watch_started();
+    metrics::watch_finished();
+    assert_eq!(
+        sample("frontend_watch_assignments_streams", &[]),
+        streams + 1.0
+    );
+    metrics::watch_finished();
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use tokio::time::delay_for;
+
+use frontend::parties::{PartyError, PartyRegistry};
+
+const CONNECTION: &str = "match-1,127.0.0.1:7000";
+
+fn registry() -> PartyRegistry {
+    PartyRegistry::new(2, Duration::from_secs(60))
+}
+
+#[tokio::test]
+async fn parties_are_queued_by_their_leader() {
+    let parties = registry();
+    let party = parties.create("player-a", 10.0).unwrap();
+    let party_id = party.party_id;
+    assert_eq!(party.leader_id, "player-a");
+
+    parties.join(&party_id, "player-b", 20.0).unwrap();
+    match parties.join(&party_id, "player-c", 30.0) {
+        Err(PartyError::Full) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match parties.create("player-b", 20.0) {
+        Err(PartyError::AlreadyInParty) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match parties.start_queue(&party_id, "player-b") {
+        Err(PartyError::NotLeader) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let queued = parties.start_queue(&party_id, "player-a").unwrap();
+    assert_eq!(queued.members.len(), 2);
+    match parties.leave(&party_id, "player-b") {
+        Err(PartyError::Queued) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let mut rx = parties.subscribe(&party_id, "player-b").unwrap();
+    parties.finish_queue(&party_id, Ok(CONNECTION.to_string()));
+    loop {
+        if let Some(result) = rx.recv().await.unwrap() {
+            assert_eq!(result, Ok(CONNECTION.to_string()));
+            break;
+        }
+    }
+
+    // the next member leads when the leader leaves, and the last one disbands the party
+    let party = parties.leave(&party_id, "player-a").unwrap();
+    assert_eq!(party.leader_id, "player-b");
+    parties.leave(&party_id, "player-b").unwrap();
+    match parties.subscribe(&party_id, "player-b") {
+        Err(PartyError::NotFound) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    parties.create("player-a", 10.0).unwrap();
+}
+
+#[tokio::test]
+async fn late_members_get_the_result_until_the_next_queue() {
+    let parties = registry();
+    let party_id = parties.create("player-a", 10.0).unwrap().party_id;
+    parties.join(&party_id, "player-b", 20.0).unwrap();
+    parties.start_queue(&party_id, "player-a").unwrap();
+    let early = parties.subscribe(&party_id, "player-a").unwrap();
+    parties.finish_queue(&party_id, Ok(CONNECTION.to_string()));
+
+    let mut late = parties.subscribe(&party_id, "player-b").unwrap();
+    assert_eq!(late.recv().await.unwrap(), Some(Ok(CONNECTION.to_string())));
+    // a late member doesn't take the result from the others
+    assert_eq!(*early.borrow(), Some(Ok(CONNECTION.to_string())));
+
+    parties.start_queue(&party_id, "player-a").unwrap();
+    assert_eq!(*early.borrow(), None);
+    match parties.subscribe(&party_id, "player-c") {
+        Err(PartyError::NotMember) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn idle_parties_are_disbanded() {
+    let parties = PartyRegistry::new(2, Duration::from_millis(50));
+    let idle = parties.create("player-a", 10.0).unwrap().party_id;
+    let queued = parties.create("player-b", 20.0).unwrap().party_id;
+    parties.start_queue(&queued, "player-b").unwrap();
+    delay_for(Duration::from_millis(100)).await;
+
+    match parties.join(&idle, "player-c", 30.0) {
+        Err(PartyError::NotFound) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    // queued parties are kept while their ticket waits
+    parties.subscribe(&queued, "player-b").unwrap();
+    parties.create("player-a", 10.0).unwrap();
+}
@@ -0,0 +1,106 @@
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use grpc_tls::TlsConfig;
+use tokio::time::delay_for;
+
+use frontend::parties::PartyRegistry;
+use frontend::service::mm;
+use frontend::service::mm::frontend_server::Frontend;
+use frontend::service::GameFrontend;
+use frontend::tickets::{DuplicateTicketPolicy, MockActiveTicketStore};
+use testing::FakeOpenMatch;
+
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn start(
+    policy: DuplicateTicketPolicy,
+    store: MockActiveTicketStore,
+) -> (GameFrontend<MockActiveTicketStore>, FakeOpenMatch) {
+    let om = FakeOpenMatch::new();
+    let om_address = free_address();
+    tokio::spawn(om.clone().serve(om_address));
+    delay_for(Duration::from_millis(100)).await;
+    let frontend = GameFrontend::new(
+        om_address.to_string(),
+        &TlsConfig::default(),
+        store,
+        policy,
+        PartyRegistry::new(4, Duration::from_secs(60)),
+    )
+    .await
+    .unwrap();
+    (frontend, om)
+}
+
+fn request(player_id: &str) -> tonic::Request<mm::CreateMatchRequest> {
+    tonic::Request::new(mm::CreateMatchRequest {
+        player_id: player_id.to_string(),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn second_tickets_are_rejected() {
+    let store = MockActiveTicketStore::new();
+    let (frontend, om) = start(DuplicateTicketPolicy::Reject, store.clone()).await;
+
+    frontend.create_match(request("player-a")).await.unwrap();
+    let err = frontend
+        .create_match(request("player-a"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::AlreadyExists);
+
+    // the player is reserved before the ticket is created, so no ticket is left behind
+    assert_eq!(om.tickets().len(), 1);
+    let calls = store.calls();
+    assert_eq!(calls.len(), 3);
+    assert!(calls[0].starts_with("insert_if_absent(player-a, pending-"));
+    assert_eq!(
+        calls[1],
+        format!("replace(player-a, {})", om.tickets()[0].id)
+    );
+    assert!(calls[2].starts_with("insert_if_absent(player-a, pending-"));
+}
+
+#[tokio::test]
+async fn second_tickets_replace_the_first() {
+    let store = MockActiveTicketStore::new();
+    let (frontend, om) = start(DuplicateTicketPolicy::Replace, store.clone()).await;
+
+    frontend.create_match(request("player-a")).await.unwrap();
+    let first = om.tickets()[0].id.clone();
+    frontend.create_match(request("player-a")).await.unwrap();
+
+    let tickets = om.tickets();
+    assert_eq!(tickets.len(), 1);
+    assert_ne!(tickets[0].id, first);
+    assert!(store
+        .calls()
+        .iter()
+        .all(|call| call.starts_with("replace(player-a, ")));
+}
+
+#[tokio::test]
+async fn tickets_are_deleted_when_the_store_is_unavailable() {
+    let store = MockActiveTicketStore::new();
+    let (frontend, om) = start(DuplicateTicketPolicy::Reject, store.clone()).await;
+    store.set_unavailable(true);
+
+    let err = frontend
+        .create_match(request("player-a"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+    assert!(om.tickets().is_empty());
+
+    store.set_unavailable(false);
+    frontend.create_match(request("player-a")).await.unwrap();
+    assert_eq!(om.tickets().len(), 1);
+}
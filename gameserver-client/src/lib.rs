@@ -1,24 +1,79 @@
 use async_trait::async_trait;
+use grpc_tls::{TlsConfig, TokenFile};
 use tonic::transport::Channel;
 
 mod game {
     tonic::include_proto!("game");
 }
 
+/// A running match that needs `open_slots` more players.
+#[derive(Clone, Debug)]
+pub struct Backfill {
+    pub match_id: String,
+    pub match_profile: String,
+    pub open_slots: i32,
+    /// Region of the match, empty for any region.
+    pub region: String,
+    /// Mean skill rating of the players the match was made with.
+    pub skill_rating: f64,
+}
+
 #[async_trait]
 pub trait GameServerClient {
     async fn get_number_of_matches(&self) -> Result<i32, Box<dyn std::error::Error>>;
+    async fn get_backfills(&self) -> Result<Vec<Backfill>, Box<dyn std::error::Error>>;
+    /// Announces the teams of a match. Its region and skill rating are reported back with
+    /// its open seats.
+    async fn reserve_match(
+        &self,
+        match_id: String,
+        match_profile: String,
+        teams: Vec<Vec<String>>,
+        region: String,
+        skill_rating: f64,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+    async fn backfill_match(
+        &self,
+        match_id: String,
+        player_ids: Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>>;
 }
 
 pub struct GameServerClientImpl {
-    client: game::game_client::GameClient<Channel>,
+    client: game::game_client::GameClient<telemetry::Traced<Channel>>,
+    token: Option<TokenFile>,
 }
 
 impl GameServerClientImpl {
     pub async fn new(address: String) -> Result<Self, Box<dyn std::error::Error>> {
-        let address = format!("http://{}", address);
-        let client = game::game_client::GameClient::connect(address).await?;
-        Ok(GameServerClientImpl { client: client })
+        Self::with_tls(address, &TlsConfig::default()).await
+    }
+
+    /// Connects over TLS when the CA of `tls` is given.
+    pub async fn with_tls(
+        address: String,
+        tls: &TlsConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let channel = grpc_tls::connect(&address, tls).await?;
+        let client = game::game_client::GameClient::new(telemetry::Traced::new(channel));
+        Ok(GameServerClientImpl {
+            client: client,
+            token: None,
+        })
+    }
+
+    /// Sends `token` with the calls that only the director may make.
+    pub fn with_token(mut self, token: TokenFile) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    fn director_request<T>(&self, message: T) -> Result<tonic::Request<T>, grpc_tls::Error> {
+        let mut request = tonic::Request::new(message);
+        if let Some(token) = &self.token {
+            token.insert(request.metadata_mut())?;
+        }
+        Ok(request)
     }
 }
 
@@ -30,4 +85,61 @@ impl GameServerClient for GameServerClientImpl {
         let res = client.get_server_info(tonic::Request::new(req)).await?;
         Ok(res.into_inner().number_of_matches)
     }
+
+    async fn get_backfills(&self) -> Result<Vec<Backfill>, Box<dyn std::error::Error>> {
+        let mut client = self.client.clone();
+        let req = game::GetServerInfoRequest {};
+        let res = client.get_server_info(tonic::Request::new(req)).await?;
+        Ok(res
+            .into_inner()
+            .backfills
+            .into_iter()
+            .map(|b| Backfill {
+                match_id: b.match_id,
+                match_profile: b.match_profile,
+                open_slots: b.open_slots,
+                region: b.region,
+                skill_rating: b.skill_rating,
+            })
+            .collect())
+    }
+
+    async fn reserve_match(
+        &self,
+        match_id: String,
+        match_profile: String,
+        teams: Vec<Vec<String>>,
+        region: String,
+        skill_rating: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = self.client.clone();
+        let req = game::ReserveMatchRequest {
+            match_id: match_id,
+            teams: teams
+                .into_iter()
+                .map(|player_ids| game::Team {
+                    player_ids: player_ids,
+                })
+                .collect(),
+            match_profile: match_profile,
+            region: region,
+            skill_rating: skill_rating,
+        };
+        client.reserve_match(self.director_request(req)?).await?;
+        Ok(())
+    }
+
+    async fn backfill_match(
+        &self,
+        match_id: String,
+        player_ids: Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = self.client.clone();
+        let req = game::BackfillMatchRequest {
+            match_id: match_id,
+            player_ids: player_ids,
+        };
+        client.backfill_match(self.director_request(req)?).await?;
+        Ok(())
+    }
 }
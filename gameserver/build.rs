@@ -1,4 +1,17 @@
+use grpc_health::build::descriptor_set;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("../proto/game.proto")?;
+    tonic_build::compile_protos("../proto/game_admin.proto")?;
+    descriptor_set(
+        &["../proto/game.proto"],
+        &["../proto"],
+        "gameserver_descriptor.bin",
+    )?;
+    descriptor_set(
+        &["../proto/game_admin.proto"],
+        &["../proto"],
+        "admin_descriptor.bin",
+    )?;
     Ok(())
 }
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use grpc_tls::TokenFile;
+use tonic::Status;
+use tracing::{error, info, info_span};
+
+pub mod pb {
+    tonic::include_proto!("game.admin");
+}
+use super::entities;
+use super::services::{self, ServerState};
+
+/// Live operations of the operators on the matches of a gameserver.
+pub struct AdminService {
+    state: Arc<ServerState>,
+    // reloaded when the file changes, so a rotated token is used without a restart
+    token: TokenFile,
+}
+
+impl AdminService {
+    pub fn new(state: Arc<ServerState>, token: TokenFile) -> Self {
+        AdminService { state, token }
+    }
+}
+
+async fn send(
+    mut worker: services::WorkerSender,
+    event: entities::Event<services::pb::Message, Status>,
+) -> Result<(), Status> {
+    worker
+        .send(Ok(event))
+        .await
+        .map_err(|err| Status::new(tonic::Code::Aborted, err.to_string()))
+}
+
+fn event() -> entities::Event<services::pb::Message, Status> {
+    entities::Event {
+        join: None,
+        leave: None,
+        backfill: None,
+        message: None,
+        kick: None,
+        end: None,
+    }
+}
+
+#[tonic::async_trait]
+impl pb::admin_server::Admin for AdminService {
+    async fn list_matches(
+        &self,
+        request: tonic::Request<pb::ListMatchesRequest>,
+    ) -> Result<tonic::Response<pb::ListMatchesResponse>, Status> {
+        self.token.authorize(request.metadata())?;
+        let matches = self
+            .state
+            .matches()?
+            .into_iter()
+            .map(|m| {
+                let (match_profile, open_slots) = match m.backfill {
+                    Some(backfill) => (backfill.match_profile, backfill.open_slots as i32),
+                    None => (String::new(), 0),
+                };
+                pb::Match {
+                    match_id: m.match_id,
+                    match_profile: match_profile,
+                    players: m
+                        .players
+                        .into_iter()
+                        .map(|player| pb::Player {
+                            id: player.id,
+                            team: player.team.map(|team| team as i32).unwrap_or(-1),
+                        })
+                        .collect(),
+                    open_slots: open_slots,
+                }
+            })
+            .collect();
+        Ok(tonic::Response::new(pb::ListMatchesResponse {
+            matches: matches,
+            draining: self.state.is_draining(),
+        }))
+    }
+
+    async fn kick_player(
+        &self,
+        request: tonic::Request<pb::KickPlayerRequest>,
+    ) -> Result<tonic::Response<pb::KickPlayerResponse>, Status> {
+        self.token.authorize(request.metadata())?;
+        let req = request.into_inner();
+        let span = info_span!("kick_player", match_id = %req.match_id, player_id = %req.player_id);
+        let joined = self
+            .state
+            .matches()?
+            .into_iter()
+            .filter(|m| m.match_id == req.match_id)
+            .flat_map(|m| m.players)
+            .any(|player| player.id == req.player_id);
+        if !joined {
+            return Err(Status::new(
+                tonic::Code::NotFound,
+                format!("player is not in the match: {}", req.player_id),
+            ));
+        }
+        let worker = self.state.worker(&req.match_id)?;
+        span.in_scope(|| info!(reason = %req.reason, "kicking player"));
+        let event = entities::Event {
+            kick: Some(entities::KickEvent {
+                player_id: req.player_id,
+                reason: Status::new(
+                    tonic::Code::Aborted,
+                    format!("kicked by an operator: {}", req.reason),
+                ),
+            }),
+            ..event()
+        };
+        send(worker, event).await?;
+        Ok(tonic::Response::new(pb::KickPlayerResponse {}))
+    }
+
+    async fn end_match(
+        &self,
+        request: tonic::Request<pb::EndMatchRequest>,
+    ) -> Result<tonic::Response<pb::EndMatchResponse>, Status> {
+        self.token.authorize(request.metadata())?;
+        let req = request.into_inner();
+        let worker = self.state.worker(&req.match_id)?;
+        info_span!("end_match", match_id = %req.match_id)
+            .in_scope(|| info!(reason = %req.reason, "ending match"));
+        let event = entities::Event {
+            end: Some(entities::EndEvent {
+                reason: Status::new(
+                    tonic::Code::Aborted,
+                    format!("match ended by an operator: {}", req.reason),
+                ),
+            }),
+            ..event()
+        };
+        send(worker, event).await?;
+        Ok(tonic::Response::new(pb::EndMatchResponse {}))
+    }
+
+    async fn broadcast(
+        &self,
+        request: tonic::Request<pb::BroadcastRequest>,
+    ) -> Result<tonic::Response<pb::BroadcastResponse>, Status> {
+        self.token.authorize(request.metadata())?;
+        let req = request.into_inner();
+        let workers = if req.match_id.is_empty() {
+            self.state.workers()?
+        } else {
+            vec![self.state.worker(&req.match_id)?]
+        };
+        info!(match_id = %req.match_id, matches = workers.len(), "broadcasting message");
+        let message = services::pb::Message {
+            body: req.body,
+            system: true,
+        };
+        for worker in workers {
+            let event = entities::Event {
+                message: Some(message.clone()),
+                ..event()
+            };
+            // a match may end while the message is sent to the others
+            if let Err(err) = send(worker, event).await {
+                error!("failed to broadcast message: {:?}", err);
+            }
+        }
+        Ok(tonic::Response::new(pb::BroadcastResponse {}))
+    }
+
+    async fn set_draining(
+        &self,
+        request: tonic::Request<pb::SetDrainingRequest>,
+    ) -> Result<tonic::Response<pb::SetDrainingResponse>, Status> {
+        self.token.authorize(request.metadata())?;
+        let draining = request.into_inner().draining;
+        info!(draining = draining, "setting draining");
+        self.state.set_draining(draining)?;
+        Ok(tonic::Response::new(pb::SetDrainingResponse {}))
+    }
+}
@@ -1,9 +1,15 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use tokio::sync::mpsc;
 
 #[derive(Clone, Debug)]
 pub struct Player<M, E> {
     pub id: String,
+    pub team: Option<usize>,
     pub sender: mpsc::Sender<Result<M, E>>,
+    // stops forwarding the messages of the player once they are kicked
+    pub kick: mpsc::Sender<()>,
 }
 
 impl<M, E> Player<M, E> {
@@ -19,20 +25,89 @@ pub type MatchId = String;
 
 pub struct GameSession<M, E> {
     pub players: Vec<Player<M, E>>,
+    // player ids of each team, as reserved by the director
+    pub teams: Vec<Vec<String>>,
+    // number of seats of each team
+    pub team_sizes: Vec<usize>,
+    // reserved players who haven't joined yet, with the time they were seated
+    pending: HashMap<String, Instant>,
 }
 
 impl<M, E> GameSession<M, E> {
     pub fn new() -> GameSession<M, E> {
+        GameSession::with_teams(Vec::new())
+    }
+
+    pub fn with_teams(teams: Vec<Vec<String>>) -> GameSession<M, E> {
+        let now = Instant::now();
         GameSession {
             players: Vec::new(),
+            team_sizes: teams.iter().map(|team| team.len()).collect(),
+            pending: teams.iter().flatten().map(|id| (id.clone(), now)).collect(),
+            teams: teams,
         }
     }
 
-    pub fn add_player(&mut self, player: Player<M, E>) {
+    pub fn team_of(&self, player_id: &str) -> Option<usize> {
+        self.teams
+            .iter()
+            .position(|team| team.iter().any(|id| id == player_id))
+    }
+
+    /// Adds the player and seats them on their reserved team, if any.
+    pub fn add_player(&mut self, mut player: Player<M, E>) {
+        player.team = self.team_of(&player.id);
+        self.pending.remove(&player.id);
         self.players.push(player);
     }
 
+    /// Seats of the reserved teams that are not taken.
+    pub fn open_slots(&self) -> usize {
+        self.teams
+            .iter()
+            .zip(self.team_sizes.iter())
+            .map(|(team, size)| size.saturating_sub(team.len()))
+            .sum()
+    }
+
+    /// Reserves seats for players added to the running match, on the teams with the most
+    /// open seats. Players beyond the open seats are not seated on a team.
+    pub fn backfill(&mut self, player_ids: Vec<String>) {
+        for id in player_ids {
+            let team = (0..self.teams.len())
+                .filter(|&i| self.teams[i].len() < self.team_sizes[i])
+                .max_by_key(|&i| self.team_sizes[i] - self.teams[i].len());
+            if let Some(team) = team {
+                self.pending.insert(id.clone(), Instant::now());
+                self.teams[team].push(id);
+            }
+        }
+    }
+
+    /// Frees the seats of the reserved players who haven't joined within `ttl`. Returns
+    /// whether any seat was freed.
+    pub fn expire_reservations(&mut self, ttl: Duration) -> bool {
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, seated)| seated.elapsed() >= ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            self.pending.remove(id);
+            for team in self.teams.iter_mut() {
+                team.retain(|player_id| player_id != id);
+            }
+        }
+        !expired.is_empty()
+    }
+
     pub fn delete_player(&mut self, id: String) {
+        // the seat of a player who left can be backfilled
+        self.pending.remove(&id);
+        for team in self.teams.iter_mut() {
+            team.retain(|player_id| player_id != &id);
+        }
         let mut index: i32 = -1;
         for (i, player) in self.players.iter().enumerate() {
             if player.id == id {
@@ -47,6 +122,16 @@ impl<M, E> GameSession<M, E> {
     pub fn num_players(&self) -> usize {
         self.players.len()
     }
+
+    pub fn player_infos(&self) -> Vec<PlayerInfo> {
+        self.players
+            .iter()
+            .map(|player| PlayerInfo {
+                id: player.id.clone(),
+                team: player.team,
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -59,9 +144,64 @@ pub struct LeaveEvent {
     pub player_id: String,
 }
 
+#[derive(Clone, Debug)]
+pub struct BackfillEvent {
+    pub player_ids: Vec<String>,
+}
+
+/// Disconnects a player, closing their stream with `reason`.
+#[derive(Clone, Debug)]
+pub struct KickEvent<E> {
+    pub player_id: String,
+    pub reason: E,
+}
+
+/// Disconnects every player, closing their streams with `reason`.
+#[derive(Clone, Debug)]
+pub struct EndEvent<E> {
+    pub reason: E,
+}
+
 #[derive(Clone, Debug)]
 pub struct Event<M, E> {
     pub join: Option<JoinEvent<M, E>>,
     pub leave: Option<LeaveEvent>,
+    pub backfill: Option<BackfillEvent>,
     pub message: Option<M>,
+    pub kick: Option<KickEvent<E>>,
+    pub end: Option<EndEvent<E>>,
+}
+
+/// A reservation made by the director before the players of a match join.
+#[derive(Clone, Debug, Default)]
+pub struct Reservation {
+    pub match_profile: String,
+    pub teams: Vec<Vec<String>>,
+    pub region: String,
+    pub skill_rating: f64,
+}
+
+/// Open seats of a running match, reported to the director for backfill.
+#[derive(Clone, Debug)]
+pub struct Backfill {
+    pub match_profile: String,
+    pub open_slots: usize,
+    pub region: String,
+    pub skill_rating: f64,
+}
+
+/// A player of a running match, as listed to the operators.
+#[derive(Clone, Debug)]
+pub struct PlayerInfo {
+    pub id: String,
+    pub team: Option<usize>,
+}
+
+/// A running match, as listed to the operators.
+#[derive(Clone, Debug)]
+pub struct MatchInfo {
+    pub match_id: MatchId,
+    // set for reserved matches
+    pub backfill: Option<Backfill>,
+    pub players: Vec<PlayerInfo>,
 }
@@ -0,0 +1,4 @@
+pub mod admin;
+pub mod entities;
+pub mod server;
+pub mod services;
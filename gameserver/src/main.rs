@@ -1,16 +1,16 @@
-use std::env;
 use std::time::Duration;
 
-use log::{debug, error, info};
 use tokio::time;
+use tracing::{debug, error, info};
 
-mod entities;
-mod services;
+use gameserver::server::{GameServerConfig, ServerBuilder};
+use gameserver::services::AgonesStatusManager;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+    telemetry::init("gameserver")?;
 
+    let config = GameServerConfig::load()?;
     info!("start gameserver");
     let sdk = agones::Sdk::new().map_err(|_| "could not connect to the sidecar")?;
 
@@ -39,7 +39,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|e| format!("could not run ready(): {:?}", e))?;
 
     // run server
-    let address = env::var("ADDRESS").unwrap_or("0.0.0.0:10000".to_string());
-    services::run_server(sdk.clone(), &address).await?;
+    info!("start server");
+    ServerBuilder::new(config, AgonesStatusManager::new(sdk.clone()))
+        .build()
+        .await?
+        .await
+        .map_err(|e| format!("could not start game server: {:?}", e))?;
     Ok(())
 }
@@ -0,0 +1,220 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use grpc_health::HealthReporter;
+use grpc_tls::{incoming, TlsConfig, TokenFile};
+use serde::{Deserialize, Serialize};
+use settings::{EnvKey, Validate};
+use tokio::net::TcpListener;
+
+use super::admin::{self, AdminService};
+use super::services::{pb, GameService, StatusManager};
+
+const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/gameserver_descriptor.bin"));
+const ADMIN_FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/admin_descriptor.bin"));
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameServerConfig {
+    pub address: SocketAddr,
+    /// TLS of the server players join, with mTLS for the director when the CA is given.
+    pub tls: TlsConfig,
+    /// Plaintext address serving only the health service, for Kubernetes gRPC probes, which
+    /// can't speak TLS, when `tls` is set.
+    pub health_address: Option<SocketAddr>,
+    /// File holding the bearer token of the director, e.g. a mounted Secret. Reservations and
+    /// backfills are refused without it.
+    pub director_token_path: String,
+    /// Time players have to join a reserved match before the reservation is dropped.
+    pub reservation_ttl_ms: u64,
+    /// Address of the admin service, which is only served when `admin_token_path` is set.
+    pub admin_address: SocketAddr,
+    /// File holding the bearer token of the operators, e.g. a mounted Secret.
+    pub admin_token_path: String,
+    /// TLS of the admin server, with mTLS for the operators when the CA is given.
+    pub admin_tls: TlsConfig,
+}
+
+impl Default for GameServerConfig {
+    fn default() -> Self {
+        GameServerConfig {
+            address: ([0, 0, 0, 0], 10000).into(),
+            tls: TlsConfig::default(),
+            health_address: None,
+            director_token_path: String::new(),
+            reservation_ttl_ms: 2 * 60 * 1000,
+            admin_address: ([0, 0, 0, 0], 10010).into(),
+            admin_token_path: String::new(),
+            admin_tls: TlsConfig::default(),
+        }
+    }
+}
+
+/// Environment variables overriding the config file.
+pub const ENV_KEYS: &[EnvKey] = &[
+    ("ADDRESS", "address"),
+    ("TLS_CERT_PATH", "tls.cert_path"),
+    ("TLS_KEY_PATH", "tls.key_path"),
+    ("TLS_CA_PATH", "tls.ca_path"),
+    ("HEALTH_ADDRESS", "health_address"),
+    ("DIRECTOR_TOKEN_PATH", "director_token_path"),
+    ("RESERVATION_TTL_MS", "reservation_ttl_ms"),
+    ("ADMIN_ADDRESS", "admin_address"),
+    ("ADMIN_TOKEN_PATH", "admin_token_path"),
+    ("ADMIN_TLS_CERT_PATH", "admin_tls.cert_path"),
+    ("ADMIN_TLS_KEY_PATH", "admin_tls.key_path"),
+    ("ADMIN_TLS_CA_PATH", "admin_tls.ca_path"),
+];
+
+impl Validate for GameServerConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.tls.validate().map_err(|err| format!("tls: {}", err))?;
+        self.admin_tls
+            .validate()
+            .map_err(|err| format!("admin_tls: {}", err))?;
+        if self.health_address == Some(self.address) {
+            return Err(format!(
+                "health_address must differ from address: {}",
+                self.address
+            ));
+        }
+        if self.reservation_ttl_ms == 0 {
+            return Err("reservation_ttl_ms must be positive".to_string());
+        }
+        if !self.admin_token_path.is_empty() && self.admin_address == self.address {
+            return Err(format!(
+                "admin_address must differ from address: {}",
+                self.address
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl GameServerConfig {
+    /// Loads the config from the config file, the environment and the command line.
+    pub fn load() -> Result<Self, settings::Error> {
+        settings::load(ENV_KEYS)
+    }
+}
+
+/// Builds the game server. The gameserver is marked ready and shut down through the
+/// status manager.
+pub struct ServerBuilder<SM>
+where
+    SM: StatusManager,
+{
+    config: GameServerConfig,
+    status_manager: SM,
+    listener: Option<TcpListener>,
+    admin_listener: Option<TcpListener>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
+
+impl<SM> ServerBuilder<SM>
+where
+    SM: StatusManager + Clone + Send + Sync + 'static,
+{
+    pub fn new(config: GameServerConfig, status_manager: SM) -> Self {
+        ServerBuilder {
+            config,
+            status_manager,
+            listener: None,
+            admin_listener: None,
+            shutdown: None,
+        }
+    }
+
+    /// Serves on `listener` instead of binding the configured address.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Serves the admin service on `listener` instead of binding the configured admin address.
+    pub fn admin_listener(mut self, listener: TcpListener) -> Self {
+        self.admin_listener = Some(listener);
+        self
+    }
+
+    /// Stops accepting connections once `signal` resolves.
+    pub fn shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Binds the listeners. The returned future runs the server, and the admin server when a
+    /// token is configured. The server is reported serving while Agones has the gameserver ready
+    /// and it is not draining.
+    pub async fn build(
+        self,
+    ) -> Result<BoxFuture<'static, Result<(), tonic::transport::Error>>, Box<dyn std::error::Error>>
+    {
+        let listener = match self.listener {
+            Some(listener) => listener,
+            None => TcpListener::bind(self.config.address).await?,
+        };
+        let mut game_service = GameService::new(self.status_manager)
+            .with_reservation_ttl(Duration::from_millis(self.config.reservation_ttl_ms));
+        if !self.config.director_token_path.is_empty() {
+            game_service =
+                game_service.with_director_token(TokenFile::new(&self.config.director_token_path));
+        }
+        let admin = if self.config.admin_token_path.is_empty() {
+            None
+        } else {
+            let admin_listener = match self.admin_listener {
+                Some(listener) => listener,
+                None => TcpListener::bind(self.config.admin_address).await?,
+            };
+            let admin_service = AdminService::new(
+                game_service.state(),
+                TokenFile::new(&self.config.admin_token_path),
+            );
+            let reflection = grpc_health::reflection::service(&[ADMIN_FILE_DESCRIPTOR_SET])?;
+            let mut builder = tonic::transport::Server::builder();
+            if let Some(tls) = grpc_tls::server_tls(&self.config.admin_tls)? {
+                builder.tls_config(&tls);
+            }
+            Some(
+                builder
+                    .add_service(admin::pb::admin_server::AdminServer::new(admin_service))
+                    .add_service(reflection)
+                    .serve_with_incoming(incoming(admin_listener, None)),
+            )
+        };
+        let health = HealthReporter::new();
+        health.poll(
+            &[grpc_health::SERVER, "game.Game"],
+            grpc_health::PROBE_INTERVAL,
+            game_service.health_check(),
+        );
+        let reflection = grpc_health::reflection::service(&[FILE_DESCRIPTOR_SET])?;
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(tls) = grpc_tls::server_tls(&self.config.tls)? {
+            builder.tls_config(&tls);
+        }
+        let server = builder
+            .add_service(pb::game_server::GameServer::new(game_service))
+            .add_service(health.service())
+            .add_service(reflection)
+            .serve_with_incoming(incoming(listener, self.shutdown));
+        let server: BoxFuture<'static, _> = match admin {
+            // the admin server stops with the game server
+            Some(admin) => Box::pin(async move {
+                tokio::select! {
+                    res = server => res,
+                    res = admin => res,
+                }
+            }),
+            None => Box::pin(server),
+        };
+        Ok(health.with_plaintext_health(server, self.config.health_address))
+    }
+}
@@ -1,12 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
+use futures::future::BoxFuture;
 use futures::StreamExt;
-use lazy_static::lazy_static;
-use log::{error, info};
+use grpc_tls::TokenFile;
 use tokio::sync::mpsc;
-use tonic::{transport::Server, Status};
+use tokio::task;
+use tokio::time;
+use tonic::Status;
+use tracing::{debug, error, info, info_span};
+use tracing_futures::Instrument;
 
 pub mod pb {
     tonic::include_proto!("game");
@@ -14,12 +20,219 @@ pub mod pb {
 use super::entities;
 use super::entities::MatchId;
 
-pub struct GameService {
-    pub agones_sdk: agones::Sdk,
+/// Agones states in which the gameserver takes players.
+const SERVING_STATES: &[&str] = &["Ready", "Reserved", "Allocated"];
+
+pub(crate) type WorkerSender =
+    mpsc::Sender<Result<entities::Event<pb::Message, tonic::Status>, tonic::Status>>;
+
+/// Matches of one gameserver, shared by its service and workers.
+#[derive(Default)]
+pub struct ServerState {
+    workers: RwLock<HashMap<MatchId, WorkerSender>>,
+    // teams of the matches whose players haven't joined yet, with the time they were reserved
+    reservations: RwLock<HashMap<MatchId, (Instant, entities::Reservation)>>,
+    // open seats of the running reserved matches
+    backfills: RwLock<HashMap<MatchId, entities::Backfill>>,
+    // players of the running matches, published by their workers
+    players: RwLock<HashMap<MatchId, Vec<entities::PlayerInfo>>>,
+    // players kicked from the running matches, who can't rejoin them
+    kicked: RwLock<HashMap<MatchId, HashSet<String>>>,
+    // set by the operators, and once the gameserver asked to be shut down
+    draining: AtomicBool,
+    shut_down: AtomicBool,
+}
+
+impl ServerState {
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Shuts the gameserver down once its last match is over.
+    fn shutdown<SM: StatusManager>(&self, status_manager: &mut SM) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.shut_down.store(true, Ordering::SeqCst);
+        if status_manager.shutdown().is_err() {
+            error!("failed to shutdown");
+        }
+    }
+
+    /// Stops or resumes taking new matches. The gameserver shuts down once the last match is
+    /// over, and can't resume afterwards.
+    pub(crate) fn set_draining(&self, draining: bool) -> Result<(), tonic::Status> {
+        if !draining && self.shut_down.load(Ordering::SeqCst) {
+            return Err(tonic::Status::new(
+                tonic::Code::FailedPrecondition,
+                "the gameserver is shutting down",
+            ));
+        }
+        self.draining.store(draining, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn is_kicked(&self, match_id: &str, player_id: &str) -> Result<bool, tonic::Status> {
+        match self.kicked.read() {
+            Ok(k) => Ok(k
+                .get(match_id)
+                .map_or(false, |players| players.contains(player_id))),
+            Err(err) => Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        }
+    }
+
+    /// The running matches with their players.
+    pub(crate) fn matches(&self) -> Result<Vec<entities::MatchInfo>, tonic::Status> {
+        let (w, b, p) = match (
+            self.workers.read(),
+            self.backfills.read(),
+            self.players.read(),
+        ) {
+            (Ok(w), Ok(b), Ok(p)) => (w, b, p),
+            _ => return Err(tonic::Status::new(tonic::Code::Aborted, "poisoned lock")),
+        };
+        Ok(w.keys()
+            .map(|match_id| entities::MatchInfo {
+                match_id: match_id.clone(),
+                backfill: b.get(match_id).cloned(),
+                players: p.get(match_id).cloned().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// The worker of a running match.
+    pub(crate) fn worker(&self, match_id: &str) -> Result<WorkerSender, tonic::Status> {
+        match self.workers.read() {
+            Ok(w) => w.get(match_id).cloned().ok_or_else(|| {
+                tonic::Status::new(
+                    tonic::Code::NotFound,
+                    format!("match is not running: {}", match_id),
+                )
+            }),
+            Err(err) => Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        }
+    }
+
+    pub(crate) fn workers(&self) -> Result<Vec<WorkerSender>, tonic::Status> {
+        match self.workers.read() {
+            Ok(w) => Ok(w.values().cloned().collect()),
+            Err(err) => Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        }
+    }
+
+    fn remove_match(&self, match_id: &str) {
+        match self.backfills.write() {
+            Ok(mut b) => {
+                b.remove(match_id);
+            }
+            Err(err) => error!("{:?}", err),
+        };
+        match self.players.write() {
+            Ok(mut p) => {
+                p.remove(match_id);
+            }
+            Err(err) => error!("{:?}", err),
+        };
+        match self.kicked.write() {
+            Ok(mut k) => {
+                k.remove(match_id);
+            }
+            Err(err) => error!("{:?}", err),
+        };
+    }
+}
+
+pub struct GameService<SM>
+where
+    SM: StatusManager,
+{
+    status_manager: SM,
+    state: Arc<ServerState>,
+    director_token: Option<TokenFile>,
+    reservation_ttl: Duration,
+}
+
+impl<SM> GameService<SM>
+where
+    SM: StatusManager,
+{
+    pub fn new(status_manager: SM) -> Self {
+        GameService {
+            status_manager,
+            state: Arc::new(ServerState::default()),
+            director_token: None,
+            reservation_ttl: Duration::from_secs(120),
+        }
+    }
+
+    /// Requires `token` for the reservations and backfills of the director. Without a token they
+    /// are refused.
+    pub fn with_director_token(mut self, token: TokenFile) -> Self {
+        self.director_token = Some(token);
+        self
+    }
+
+    /// Forgets reservations nobody joined within `ttl`.
+    pub fn with_reservation_ttl(mut self, ttl: Duration) -> Self {
+        self.reservation_ttl = ttl;
+        self
+    }
+
+    fn authorize_director(&self, metadata: &tonic::metadata::MetadataMap) -> Result<(), Status> {
+        match &self.director_token {
+            Some(token) => token.authorize(metadata),
+            None => Err(Status::new(
+                tonic::Code::PermissionDenied,
+                "no director token is configured",
+            )),
+        }
+    }
+
+    /// The matches of the gameserver, shared with its admin service.
+    pub fn state(&self) -> Arc<ServerState> {
+        self.state.clone()
+    }
+}
+
+impl<SM> GameService<SM>
+where
+    SM: StatusManager + Clone + Send + 'static,
+{
+    /// Checks whether the gameserver takes players: Agones has it ready and it is not draining.
+    pub fn health_check(&self) -> impl FnMut() -> BoxFuture<'static, bool> + Send + 'static {
+        let state = self.state.clone();
+        let status_manager = self.status_manager.clone();
+        move || {
+            let draining = state.is_draining();
+            let mut status_manager = status_manager.clone();
+            Box::pin(async move {
+                if draining {
+                    return false;
+                }
+                // the SDK call blocks, so it doesn't run on the workers of the runtime
+                let res = task::spawn_blocking(move || {
+                    status_manager.state().map_err(|err| err.to_string())
+                })
+                .await;
+                match res {
+                    Ok(Ok(gs_state)) => SERVING_STATES.contains(&gs_state.as_str()),
+                    Ok(Err(err)) => {
+                        error!("failed to get the gameserver state: {}", err);
+                        false
+                    }
+                    Err(err) => {
+                        error!("failed to get the gameserver state: {:?}", err);
+                        false
+                    }
+                }
+            })
+        }
+    }
 }
 
 #[tonic::async_trait]
-impl pb::game_server::Game for GameService {
+impl<SM> pb::game_server::Game for GameService<SM>
+where
+    SM: StatusManager + Clone + Send + Sync + 'static,
+{
     type JoinStream = mpsc::Receiver<Result<pb::Message, Status>>;
     async fn join(
         &self,
@@ -52,31 +265,90 @@ impl pb::game_server::Game for GameService {
                 })
             })?;
 
-        info!(
-            "joined player. player_id: {}, match_id: {}",
-            player_id, match_id
+        let span = info_span!(
+            "join",
+            traceparent = %telemetry::traceparent(request.metadata()),
+            player_id = %player_id,
+            match_id = %match_id
         );
+        if self.state.is_kicked(match_id, player_id)? {
+            return Err(tonic::Status::new(
+                tonic::Code::PermissionDenied,
+                "the player was kicked from the match",
+            ));
+        }
+        span.in_scope(|| info!("joined player"));
 
+        let (kick_tx, mut kick_rx) = mpsc::channel(1);
         let player = entities::Player {
             id: player_id.to_string(),
+            team: None,
             sender: tx.clone(),
+            kick: kick_tx,
         };
 
-        let mut wtx = match WORKER_CHANNEL_MAP.write() {
+        let mut wtx = match self.state.workers.write() {
             Ok(mut w) => match w.get(match_id) {
                 Some(wtx) => wtx.clone(),
                 None => {
+                    if self.state.is_draining() {
+                        return Err(tonic::Status::new(
+                            tonic::Code::Unavailable,
+                            "the gameserver is draining",
+                        ));
+                    }
                     let (tx, rx) = mpsc::channel(1);
-                    let sdk = self.agones_sdk.clone();
+                    let status_manager = self.status_manager.clone();
+                    let state = self.state.clone();
                     let _match_id = match_id.to_string();
-                    tokio::spawn(async move {
-                        let status_manager = AgonesStatusManager { agones_sdk: sdk };
-                        let game_session = entities::GameSession::new();
-                        let mut worker = Worker::new(_match_id, status_manager, game_session, rx);
+                    let reservation_ttl = self.reservation_ttl;
+                    let reservation = match self.state.reservations.write() {
+                        Ok(mut r) => r
+                            .remove(match_id)
+                            .filter(|(reserved, _)| reserved.elapsed() < reservation_ttl)
+                            .map(|(_, reservation)| reservation),
+                        Err(err) => {
+                            return Err(tonic::Status::new(tonic::Code::Aborted, err.to_string()))
+                        }
+                    };
+                    // only reserved matches know their seats and can be backfilled
+                    let reservation = match reservation {
+                        Some(reservation) => {
+                            match self.state.backfills.write() {
+                                Ok(mut b) => {
+                                    b.insert(
+                                        match_id.to_string(),
+                                        entities::Backfill {
+                                            match_profile: reservation.match_profile.clone(),
+                                            open_slots: 0,
+                                            region: reservation.region.clone(),
+                                            skill_rating: reservation.skill_rating,
+                                        },
+                                    );
+                                }
+                                Err(err) => {
+                                    return Err(tonic::Status::new(
+                                        tonic::Code::Aborted,
+                                        err.to_string(),
+                                    ))
+                                }
+                            };
+                            reservation
+                        }
+                        None => entities::Reservation::default(),
+                    };
+                    // the worker outlives the player who started it
+                    let worker_span = info_span!(parent: None, "match", match_id = %match_id);
+                    let run_worker = async move {
+                        let game_session = entities::GameSession::with_teams(reservation.teams);
+                        let mut worker =
+                            Worker::new(_match_id, status_manager, game_session, rx, state)
+                                .with_reservation_ttl(reservation_ttl);
                         if let Err(err) = worker.run().await {
                             error!("worker error: {:?}", err);
                         }
-                    });
+                    };
+                    tokio::spawn(run_worker.instrument(worker_span));
                     w.insert(match_id.to_string(), tx.clone());
                     tx
                 }
@@ -88,23 +360,48 @@ impl pb::game_server::Game for GameService {
                 player: player.clone(),
             }),
             leave: None,
+            backfill: None,
             message: None,
+            kick: None,
+            end: None,
         };
         wtx.send(Ok(event))
             .await
             .map_err(|err| tonic::Status::new(tonic::Code::Aborted, err.to_string()))?;
 
         let stream = request.into_inner();
-        tokio::spawn(async move {
+        let leaving_player = player_id.to_string();
+        let forward_messages = async move {
             futures::pin_mut!(stream);
             let mut tx = tx.clone();
-            while let Some(msg) = stream.next().await {
+            // set when the player disconnects, so that their seat is freed
+            let mut left = false;
+            loop {
+                // the worker drops the player once they are kicked or the match is over
+                let msg = tokio::select! {
+                    msg = stream.next() => match msg {
+                        Some(msg) => msg,
+                        None => {
+                            left = true;
+                            break;
+                        }
+                    },
+                    _ = kick_rx.recv() => {
+                        info!("stopped forwarding the messages of the player");
+                        break;
+                    }
+                };
                 match msg {
-                    Ok(message) => {
+                    Ok(mut message) => {
+                        // only the operators send system messages
+                        message.system = false;
                         let event = entities::Event {
                             join: None,
                             leave: None,
+                            backfill: None,
                             message: Some(message.clone()),
+                            kick: None,
+                            end: None,
                         };
                         if let Err(err) = wtx.send(Ok(event)).await {
                             error!("worker: failed to send message: {:?}", err);
@@ -122,11 +419,30 @@ impl pb::game_server::Game for GameService {
                         {
                             error!("failed to send error message: {:?}", err);
                         }
+                        left = true;
                         break;
                     }
                 }
             }
-        });
+            if left {
+                info!("player left");
+                let event = entities::Event {
+                    join: None,
+                    leave: Some(entities::LeaveEvent {
+                        player_id: leaving_player,
+                    }),
+                    backfill: None,
+                    message: None,
+                    kick: None,
+                    end: None,
+                };
+                // the match may be over already
+                if let Err(err) = wtx.send(Ok(event)).await {
+                    debug!("failed to send leave: {:?}", err);
+                }
+            }
+        };
+        tokio::spawn(forward_messages.instrument(span));
         Ok(tonic::Response::new(rx))
     }
 
@@ -134,20 +450,126 @@ impl pb::game_server::Game for GameService {
         &self,
         _request: tonic::Request<pb::GetServerInfoRequest>,
     ) -> Result<tonic::Response<pb::GetServerInfoResponse>, tonic::Status> {
-        let num_matches = match WORKER_CHANNEL_MAP.read() {
+        let num_matches = match self.state.workers.read() {
             Ok(w) => w.len() as i32,
             Err(err) => return Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
         };
+        // reserved matches nobody joined yet take a match slot too, so that the director
+        // doesn't allocate more matches than the gameserver can run
+        let reservation_ttl = self.reservation_ttl;
+        let num_matches = match self.state.reservations.read() {
+            Ok(r) => {
+                num_matches
+                    + r.values()
+                        .filter(|(reserved, _)| reserved.elapsed() < reservation_ttl)
+                        .count() as i32
+            }
+            Err(err) => return Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        };
+        // a draining gameserver takes no more players
+        let backfills = match self.state.backfills.read() {
+            Ok(_) if self.state.is_draining() => Vec::new(),
+            Ok(b) => b
+                .iter()
+                .filter(|(_, backfill)| backfill.open_slots > 0)
+                .map(|(match_id, backfill)| pb::Backfill {
+                    match_id: match_id.clone(),
+                    match_profile: backfill.match_profile.clone(),
+                    open_slots: backfill.open_slots as i32,
+                    region: backfill.region.clone(),
+                    skill_rating: backfill.skill_rating,
+                })
+                .collect(),
+            Err(err) => return Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        };
         let res = pb::GetServerInfoResponse {
             number_of_matches: num_matches,
+            backfills: backfills,
         };
         Ok(tonic::Response::new(res))
     }
+
+    async fn reserve_match(
+        &self,
+        request: tonic::Request<pb::ReserveMatchRequest>,
+    ) -> Result<tonic::Response<pb::ReserveMatchResponse>, tonic::Status> {
+        let span = info_span!(
+            "reserve_match",
+            traceparent = %telemetry::traceparent(request.metadata())
+        );
+        self.authorize_director(request.metadata())?;
+        if self.state.is_draining() {
+            return Err(tonic::Status::new(
+                tonic::Code::Unavailable,
+                "the gameserver is draining",
+            ));
+        }
+        let req = request.into_inner();
+        span.in_scope(|| info!(match_id = %req.match_id, teams = ?req.teams, "reserved match"));
+        let reservation = entities::Reservation {
+            match_profile: req.match_profile,
+            teams: req.teams.into_iter().map(|t| t.player_ids).collect(),
+            region: req.region,
+            skill_rating: req.skill_rating,
+        };
+        let reservation_ttl = self.reservation_ttl;
+        match self.state.reservations.write() {
+            Ok(mut r) => {
+                r.retain(|_, (reserved, _)| reserved.elapsed() < reservation_ttl);
+                r.insert(req.match_id, (Instant::now(), reservation));
+            }
+            Err(err) => return Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        };
+        Ok(tonic::Response::new(pb::ReserveMatchResponse {}))
+    }
+
+    async fn backfill_match(
+        &self,
+        request: tonic::Request<pb::BackfillMatchRequest>,
+    ) -> Result<tonic::Response<pb::BackfillMatchResponse>, tonic::Status> {
+        let span = info_span!(
+            "backfill_match",
+            traceparent = %telemetry::traceparent(request.metadata())
+        );
+        self.authorize_director(request.metadata())?;
+        let req = request.into_inner();
+        span.in_scope(
+            || info!(match_id = %req.match_id, player_ids = ?req.player_ids, "backfilled match"),
+        );
+        let mut wtx = match self.state.workers.read() {
+            Ok(w) => match w.get(&req.match_id) {
+                Some(wtx) => wtx.clone(),
+                None => {
+                    return Err(tonic::Status::new(
+                        tonic::Code::NotFound,
+                        format!("match is not running: {}", req.match_id),
+                    ))
+                }
+            },
+            Err(err) => return Err(tonic::Status::new(tonic::Code::Aborted, err.to_string())),
+        };
+        let event = entities::Event {
+            join: None,
+            leave: None,
+            backfill: Some(entities::BackfillEvent {
+                player_ids: req.player_ids,
+            }),
+            message: None,
+            kick: None,
+            end: None,
+        };
+        wtx.send(Ok(event))
+            .await
+            .map_err(|err| tonic::Status::new(tonic::Code::Aborted, err.to_string()))?;
+        Ok(tonic::Response::new(pb::BackfillMatchResponse {}))
+    }
 }
 
 pub trait StatusManager {
     fn ready(&mut self) -> Result<(), Box<dyn std::error::Error>>;
     fn shutdown(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    /// Agones state of the gameserver, e.g. "Ready" or "Allocated".
+    fn state(&mut self) -> Result<String, Box<dyn std::error::Error>>;
 }
 
 pub struct Worker<SM, M, E>
@@ -158,31 +580,88 @@ where
     status_manager: SM,
     game_session: entities::GameSession<M, E>,
     rx: mpsc::Receiver<Result<entities::Event<M, E>, E>>,
+    state: Arc<ServerState>,
+    reservation_ttl: Duration,
 }
 
 impl<SM, M, E> Worker<SM, M, E>
 where
     SM: StatusManager,
     M: Send + Clone + std::fmt::Debug,
-    E: std::fmt::Debug,
+    E: Clone + std::fmt::Debug,
 {
     pub fn new(
         match_id: String,
         status_manager: SM,
         game_session: entities::GameSession<M, E>,
         rx: mpsc::Receiver<Result<entities::Event<M, E>, E>>,
+        state: Arc<ServerState>,
     ) -> Worker<SM, M, E> {
         Worker {
             match_id: match_id,
             status_manager: status_manager,
             game_session: game_session,
             rx: rx,
+            state: state,
+            reservation_ttl: Duration::from_secs(120),
         }
     }
 
+    /// Frees the seats of the reserved players who haven't joined within `ttl`.
+    pub fn with_reservation_ttl(mut self, ttl: Duration) -> Self {
+        self.reservation_ttl = ttl;
+        self
+    }
+
+    /// Publishes the open seats of the match for backfill, and its players for the operators.
+    fn update_backfill(&self) {
+        match self.state.backfills.write() {
+            Ok(mut b) => {
+                if let Some(backfill) = b.get_mut(&self.match_id) {
+                    backfill.open_slots = self.game_session.open_slots();
+                }
+            }
+            Err(err) => error!("{:?}", err),
+        };
+        match self.state.players.write() {
+            Ok(mut p) => {
+                p.insert(self.match_id.clone(), self.game_session.player_infos());
+            }
+            Err(err) => error!("{:?}", err),
+        };
+    }
+
+    /// Removes the match, and shuts the gameserver down when it was the last one.
+    fn finish(&mut self) {
+        self.state.remove_match(&self.match_id);
+        match self.state.workers.write() {
+            Ok(mut w) => {
+                w.remove(&self.match_id);
+                if w.len() == 0 {
+                    self.state.shutdown(&mut self.status_manager);
+                }
+            }
+            Err(err) => error!("{:?}", err),
+        };
+    }
+
     pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        info!("start worker: {}", self.match_id);
-        while let Some(event) = self.rx.recv().await {
+        info!("start worker");
+        let mut expiry = time::interval(self.reservation_ttl);
+        loop {
+            let event = tokio::select! {
+                event = self.rx.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = expiry.tick() => {
+                    if self.game_session.expire_reservations(self.reservation_ttl) {
+                        info!("freed the seats of reserved players who didn't join");
+                        self.update_backfill();
+                    }
+                    continue;
+                }
+            };
             if let Ok(event) = event {
                 if let Some(message) = event.message {
                     let mut failed_player = Vec::new();
@@ -195,58 +674,89 @@ where
                     for id in failed_player {
                         self.game_session.delete_player(id);
                         if self.game_session.num_players() == 0 {
-                            {
-                                match WORKER_CHANNEL_MAP.write() {
-                                    Ok(mut w) => {
-                                        w.remove(&self.match_id.clone());
-                                        if w.len() == 0 {
-                                            if self.status_manager.shutdown().is_err() {
-                                                error!("failed to shutdown");
-                                            }
-                                        }
-                                    }
-                                    Err(err) => error!("{:?}", err),
-                                };
-                            }
+                            self.finish();
                             return Ok(());
                         }
                     }
+                    self.update_backfill();
                     continue;
                 }
                 if let Some(join) = event.join {
                     self.game_session.add_player(join.player);
+                    self.update_backfill();
+                    continue;
+                }
+                if let Some(backfill) = event.backfill {
+                    self.game_session.backfill(backfill.player_ids);
+                    self.update_backfill();
                     continue;
                 }
                 if let Some(leave) = event.leave {
                     self.game_session.delete_player(leave.player_id);
                     if self.game_session.num_players() == 0 {
-                        {
-                            match WORKER_CHANNEL_MAP.write() {
-                                Ok(mut w) => {
-                                    w.remove(&self.match_id.clone());
-                                    if w.len() == 0 {
-                                        if self.status_manager.shutdown().is_err() {
-                                            error!("failed to shutdown");
-                                        }
-                                    }
-                                }
-                                Err(err) => error!("{:?}", err),
-                            };
+                        self.finish();
+                        return Ok(());
+                    }
+                    self.update_backfill();
+                    continue;
+                }
+                if let Some(kick) = event.kick {
+                    match self.state.kicked.write() {
+                        Ok(mut k) => {
+                            k.entry(self.match_id.clone())
+                                .or_default()
+                                .insert(kick.player_id.clone());
                         }
+                        Err(err) => error!("{:?}", err),
+                    };
+                    let player = self
+                        .game_session
+                        .players
+                        .iter_mut()
+                        .find(|player| player.id == kick.player_id);
+                    if let Some(player) = player {
+                        if let Err(err) = player.sender.send(Err(kick.reason)).await {
+                            error!("failed to kick player: {:?}", err);
+                        }
+                        // stops forwarding their messages
+                        let _ = player.kick.try_send(());
+                    }
+                    info!(player_id = %kick.player_id, "kicked player");
+                    self.game_session.delete_player(kick.player_id);
+                    if self.game_session.num_players() == 0 {
+                        self.finish();
                         return Ok(());
                     }
+                    self.update_backfill();
                     continue;
                 }
+                if let Some(end) = event.end {
+                    for player in &mut self.game_session.players {
+                        if let Err(err) = player.sender.send(Err(end.reason.clone())).await {
+                            error!("failed to end match: {:?}", err);
+                        }
+                    }
+                    info!("ended match");
+                    self.finish();
+                    return Ok(());
+                }
             }
         }
         Ok(())
     }
 }
 
+#[derive(Clone)]
 pub struct AgonesStatusManager {
     agones_sdk: agones::Sdk,
 }
 
+impl AgonesStatusManager {
+    pub fn new(agones_sdk: agones::Sdk) -> Self {
+        AgonesStatusManager { agones_sdk }
+    }
+}
+
 impl StatusManager for AgonesStatusManager {
     fn ready(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.agones_sdk
@@ -261,30 +771,12 @@ impl StatusManager for AgonesStatusManager {
             .map_err(|err| format!("failed to shutdown: {:?}", err))?;
         Ok(())
     }
-}
-
-lazy_static! {
-    pub static ref WORKER_CHANNEL_MAP: Arc<
-        RwLock<
-            HashMap<
-                MatchId,
-                mpsc::Sender<Result<entities::Event<pb::Message, tonic::Status>, tonic::Status>>,
-            >,
-        >,
-    > = Arc::new(RwLock::new(HashMap::new()));
-}
 
-pub async fn run_server(sdk: agones::Sdk, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
-    info!("start server");
-    let addr = addr.parse().unwrap();
-    let game_service = GameService {
-        agones_sdk: sdk.clone(),
-    };
-    let svc = pb::game_server::GameServer::new(game_service);
-    Server::builder()
-        .add_service(svc)
-        .serve(addr)
-        .await
-        .map_err(|e| format!("could not start game server: {:?}", e))?;
-    Ok(())
+    fn state(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let gs = self
+            .agones_sdk
+            .get_gameserver()
+            .map_err(|err| format!("could not run get_gameserver(): {:?}", err))?;
+        Ok(gs.status.map(|status| status.state).unwrap_or_default())
+    }
 }
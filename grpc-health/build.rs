@@ -0,0 +1,15 @@
+include!("src/build.rs");
+
+const PROTOS: &[&str] = &[
+    "../proto/grpc/health/v1/health.proto",
+    "../proto/grpc/reflection/v1alpha/reflection.proto",
+];
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure()
+        .build_server(true)
+        .compile(PROTOS, &["../proto"])?;
+
+    // descriptors of the services themselves for reflection
+    descriptor_set(PROTOS, &["../proto"], "grpc_descriptor.bin")
+}
@@ -0,0 +1,25 @@
+// Also included by the build script of this crate, which can't depend on the crate itself.
+
+/// Writes the descriptors of `protos` and their imports to `out` in `OUT_DIR`, for server
+/// reflection. Called from build scripts.
+pub fn descriptor_set(
+    protos: &[&str],
+    includes: &[&str],
+    out: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    let status = std::process::Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg(format!(
+            "--descriptor_set_out={}",
+            out_dir.join(out).display()
+        ))
+        .args(includes.iter().map(|include| format!("-I{}", include)))
+        .arg(format!("-I{}", prost_build::protoc_include().display()))
+        .args(protos)
+        .status()?;
+    if !status.success() {
+        return Err(format!("protoc failed: {}", status).into());
+    }
+    Ok(())
+}
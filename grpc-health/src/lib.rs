@@ -0,0 +1,212 @@
+//! The standard gRPC health checking (`grpc.health.v1`) and server reflection
+//! (`grpc.reflection.v1alpha`) services, so that Kubernetes probes and `grpcurl` work against
+//! every server.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tracing::{info, warn};
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+
+#[cfg(feature = "build")]
+pub mod build;
+pub mod reflection;
+
+pub mod pb {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub use pb::health_check_response::ServingStatus;
+
+/// How often readiness is checked.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Serving status of the whole server.
+pub const SERVER: &str = "";
+
+type Status = (watch::Sender<ServingStatus>, watch::Receiver<ServingStatus>);
+
+/// Serving statuses of the services of one server, reported by its health service.
+#[derive(Clone, Default)]
+pub struct HealthReporter {
+    statuses: Arc<Mutex<HashMap<String, Status>>>,
+}
+
+impl HealthReporter {
+    pub fn new() -> Self {
+        HealthReporter::default()
+    }
+
+    pub fn set_status(&self, service: &str, status: ServingStatus) {
+        let mut statuses = self.statuses.lock().unwrap();
+        match statuses.get(service) {
+            Some((tx, rx)) => {
+                if *rx.borrow() != status {
+                    info!("health of {:?}: {:?}", service, status);
+                    let _ = tx.broadcast(status);
+                }
+            }
+            None => {
+                info!("health of {:?}: {:?}", service, status);
+                statuses.insert(service.to_string(), watch::channel(status));
+            }
+        }
+    }
+
+    pub fn set_serving(&self, service: &str, serving: bool) {
+        let status = if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        self.set_status(service, status);
+    }
+
+    /// The status of `service`, `None` when it was never reported.
+    pub fn status(&self, service: &str) -> Option<ServingStatus> {
+        match self.statuses.lock().unwrap().get(service) {
+            Some((_, rx)) if *rx.borrow() != ServingStatus::ServiceUnknown => Some(*rx.borrow()),
+            _ => None,
+        }
+    }
+
+    /// Follows the status of `service`. A service that was never reported is `SERVICE_UNKNOWN`
+    /// until it is.
+    fn watch(&self, service: &str) -> watch::Receiver<ServingStatus> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .entry(service.to_string())
+            .or_insert_with(|| watch::channel(ServingStatus::ServiceUnknown))
+            .1
+            .clone()
+    }
+
+    /// Reports `services` as serving while `check` succeeds, checking every `interval`.
+    /// A check that takes longer than `interval` fails.
+    pub fn poll<F, Fut>(&self, services: &[&str], interval: Duration, mut check: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = bool> + Send,
+    {
+        let services: Vec<String> = services.iter().map(|s| s.to_string()).collect();
+        for service in &services {
+            self.set_serving(service, false);
+        }
+        let reporter = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let serving = match time::timeout(interval, check()).await {
+                    Ok(serving) => serving,
+                    Err(_) => {
+                        warn!("health check timed out");
+                        false
+                    }
+                };
+                for service in &services {
+                    reporter.set_serving(service, serving);
+                }
+            }
+        });
+    }
+
+    pub fn service(&self) -> pb::health_server::HealthServer<HealthService> {
+        pb::health_server::HealthServer::new(HealthService {
+            reporter: self.clone(),
+        })
+    }
+
+    /// Runs `server`, and the health service alone without TLS on `address` when it is given.
+    /// Kubernetes gRPC probes can't speak TLS, so they check this address when `server`
+    /// serves TLS. The health server stops with `server`.
+    pub fn with_plaintext_health<F>(
+        &self,
+        server: F,
+        address: Option<SocketAddr>,
+    ) -> BoxFuture<'static, Result<(), tonic::transport::Error>>
+    where
+        F: Future<Output = Result<(), tonic::transport::Error>> + Send + 'static,
+    {
+        let address = match address {
+            Some(address) => address,
+            None => return Box::pin(server),
+        };
+        let health = tonic::transport::Server::builder()
+            .add_service(self.service())
+            .serve(address);
+        Box::pin(async move {
+            tokio::select! {
+                res = server => res,
+                res = health => res,
+            }
+        })
+    }
+}
+
+/// Whether a call was answered by the server. Errors the server sends back mean it is up,
+/// while the errors of an unreachable server are `Unavailable` or `Unknown`.
+pub fn is_reachable<T>(result: &Result<T, tonic::Status>) -> bool {
+    match result {
+        Ok(_) => true,
+        Err(status) => match status.code() {
+            tonic::Code::Unavailable | tonic::Code::Unknown | tonic::Code::DeadlineExceeded => {
+                warn!("health check failed: {}", status);
+                false
+            }
+            _ => true,
+        },
+    }
+}
+
+pub struct HealthService {
+    reporter: HealthReporter,
+}
+
+fn response(status: ServingStatus) -> pb::HealthCheckResponse {
+    pb::HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+#[tonic::async_trait]
+impl pb::health_server::Health for HealthService {
+    async fn check(
+        &self,
+        request: tonic::Request<pb::HealthCheckRequest>,
+    ) -> Result<tonic::Response<pb::HealthCheckResponse>, tonic::Status> {
+        let service = request.into_inner().service;
+        let status = self.reporter.status(&service).ok_or_else(|| {
+            tonic::Status::new(
+                tonic::Code::NotFound,
+                format!("unknown service: {}", service),
+            )
+        })?;
+        Ok(tonic::Response::new(response(status)))
+    }
+
+    type WatchStream = mpsc::Receiver<Result<pb::HealthCheckResponse, tonic::Status>>;
+
+    async fn watch(
+        &self,
+        request: tonic::Request<pb::HealthCheckRequest>,
+    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let mut rx = self.reporter.watch(&request.into_inner().service);
+        let (mut tx, stream) = mpsc::channel(1);
+        tokio::spawn(async move {
+            // the first value is the current status
+            while let Some(status) = rx.recv().await {
+                if tx.send(Ok(response(status))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(stream))
+    }
+}
@@ -0,0 +1,228 @@
+//! Server reflection over the file descriptor sets the servers are built from, written by
+//! `protoc --include_imports --descriptor_set_out` in their build scripts.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use tracing::debug;
+use prost::Message;
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto};
+use tokio::sync::mpsc;
+
+pub mod pb {
+    tonic::include_proto!("grpc.reflection.v1alpha");
+}
+
+use pb::server_reflection_request::MessageRequest;
+use pb::server_reflection_response::MessageResponse;
+
+/// Descriptors of the health and reflection services, which every server serves.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/grpc_descriptor.bin"));
+
+// A FileDescriptorSet whose files are kept encoded, so that they are sent as they are,
+// including the options prost does not know.
+#[derive(Clone, PartialEq, Message)]
+struct EncodedFileDescriptorSet {
+    #[prost(bytes, repeated, tag = "1")]
+    file: Vec<Vec<u8>>,
+}
+
+struct File {
+    encoded: Vec<u8>,
+    dependencies: Vec<String>,
+}
+
+#[derive(Default)]
+struct Index {
+    files: HashMap<String, File>,
+    // fully qualified names of the services, methods, messages and enums to their file
+    symbols: HashMap<String, String>,
+    services: Vec<String>,
+}
+
+fn qualify(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+impl Index {
+    fn add_file(&mut self, encoded: Vec<u8>) -> Result<(), prost::DecodeError> {
+        let file = FileDescriptorProto::decode(encoded.as_slice())?;
+        let name = file.name.clone().unwrap_or_default();
+        let package = file.package.clone().unwrap_or_default();
+        for service in &file.service {
+            let service_name = qualify(&package, service.name());
+            for method in &service.method {
+                self.symbols
+                    .insert(qualify(&service_name, method.name()), name.clone());
+            }
+            self.symbols.insert(service_name.clone(), name.clone());
+            if !self.services.contains(&service_name) {
+                self.services.push(service_name);
+            }
+        }
+        self.add_messages(&name, &package, &file.message_type);
+        self.add_enums(&name, &package, &file.enum_type);
+        self.files.insert(
+            name,
+            File {
+                encoded,
+                dependencies: file.dependency,
+            },
+        );
+        Ok(())
+    }
+
+    fn add_messages(&mut self, file: &str, prefix: &str, messages: &[DescriptorProto]) {
+        for message in messages {
+            let name = qualify(prefix, message.name());
+            self.add_messages(file, &name, &message.nested_type);
+            self.add_enums(file, &name, &message.enum_type);
+            self.symbols.insert(name, file.to_string());
+        }
+    }
+
+    fn add_enums(&mut self, file: &str, prefix: &str, enums: &[EnumDescriptorProto]) {
+        for e in enums {
+            self.symbols
+                .insert(qualify(prefix, e.name()), file.to_string());
+        }
+    }
+
+    /// The file followed by its transitive dependencies.
+    fn file_with_dependencies(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+        self.files.get(name)?;
+        let mut encoded = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            if let Some(file) = self.files.get(&name) {
+                encoded.push(file.encoded.clone());
+                pending.extend(file.dependencies.iter().rev().cloned());
+            }
+        }
+        Some(encoded)
+    }
+
+    fn respond(&self, request: pb::ServerReflectionRequest) -> pb::ServerReflectionResponse {
+        let not_found = |what: &str| {
+            MessageResponse::ErrorResponse(pb::ErrorResponse {
+                error_code: tonic::Code::NotFound as i32,
+                error_message: format!("not found: {}", what),
+            })
+        };
+        let files = |encoded| {
+            MessageResponse::FileDescriptorResponse(pb::FileDescriptorResponse {
+                file_descriptor_proto: encoded,
+            })
+        };
+        let response = match &request.message_request {
+            Some(MessageRequest::FileByFilename(name)) => self
+                .file_with_dependencies(name)
+                .map(files)
+                .unwrap_or_else(|| not_found(name)),
+            Some(MessageRequest::FileContainingSymbol(symbol)) => self
+                .symbols
+                .get(symbol)
+                .and_then(|name| self.file_with_dependencies(name))
+                .map(files)
+                .unwrap_or_else(|| not_found(symbol)),
+            // the served protos are proto3, which has no extensions
+            Some(MessageRequest::FileContainingExtension(extension)) => not_found(&format!(
+                "extension {} of {}",
+                extension.extension_number, extension.containing_type
+            )),
+            Some(MessageRequest::AllExtensionNumbersOfType(name)) => {
+                if self.symbols.contains_key(name) {
+                    MessageResponse::AllExtensionNumbersResponse(pb::ExtensionNumberResponse {
+                        base_type_name: name.clone(),
+                        extension_number: vec![],
+                    })
+                } else {
+                    not_found(name)
+                }
+            }
+            Some(MessageRequest::ListServices(_)) => {
+                MessageResponse::ListServicesResponse(pb::ListServiceResponse {
+                    service: self
+                        .services
+                        .iter()
+                        .map(|name| pb::ServiceResponse { name: name.clone() })
+                        .collect(),
+                })
+            }
+            None => MessageResponse::ErrorResponse(pb::ErrorResponse {
+                error_code: tonic::Code::InvalidArgument as i32,
+                error_message: "empty request".to_string(),
+            }),
+        };
+        pb::ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(response),
+        }
+    }
+}
+
+pub struct ReflectionService {
+    index: Arc<Index>,
+}
+
+/// The reflection service of a server built from `descriptor_sets`. The health and reflection
+/// services are included.
+pub fn service(
+    descriptor_sets: &[&[u8]],
+) -> Result<
+    pb::server_reflection_server::ServerReflectionServer<ReflectionService>,
+    prost::DecodeError,
+> {
+    let mut index = Index::default();
+    for set in descriptor_sets.iter().chain(&[FILE_DESCRIPTOR_SET]) {
+        for file in EncodedFileDescriptorSet::decode(*set)?.file {
+            index.add_file(file)?;
+        }
+    }
+    Ok(pb::server_reflection_server::ServerReflectionServer::new(
+        ReflectionService {
+            index: Arc::new(index),
+        },
+    ))
+}
+
+#[tonic::async_trait]
+impl pb::server_reflection_server::ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream =
+        mpsc::Receiver<Result<pb::ServerReflectionResponse, tonic::Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        request: tonic::Request<tonic::Streaming<pb::ServerReflectionRequest>>,
+    ) -> Result<tonic::Response<Self::ServerReflectionInfoStream>, tonic::Status> {
+        let mut requests = request.into_inner();
+        let index = self.index.clone();
+        let (mut tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let request = match requests.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(err) => {
+                        debug!("reflection stream error: {:?}", err);
+                        break;
+                    }
+                };
+                if tx.send(Ok(index.respond(request))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(rx))
+    }
+}
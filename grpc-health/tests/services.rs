@@ -0,0 +1,195 @@
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use prost::Message;
+use tokio::time::{delay_for, timeout};
+use tonic::transport::{Channel, Server};
+
+use grpc_health::reflection::pb::server_reflection_request::MessageRequest;
+use grpc_health::reflection::pb::server_reflection_response::MessageResponse;
+use grpc_health::{pb, reflection, HealthReporter, ServingStatus, SERVER};
+
+type HealthClient = pb::health_client::HealthClient<Channel>;
+type ReflectionClient = reflection::pb::server_reflection_client::ServerReflectionClient<Channel>;
+
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn request(service: &str) -> pb::HealthCheckRequest {
+    pb::HealthCheckRequest {
+        service: service.to_string(),
+    }
+}
+
+async fn check(client: &mut HealthClient, service: &str) -> ServingStatus {
+    let res = client.check(request(service)).await.unwrap().into_inner();
+    ServingStatus::from_i32(res.status).unwrap()
+}
+
+async fn next(stream: &mut tonic::Streaming<pb::HealthCheckResponse>) -> ServingStatus {
+    let res = timeout(Duration::from_secs(1), stream.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    ServingStatus::from_i32(res.status).unwrap()
+}
+
+#[tokio::test]
+async fn health_follows_the_checks() {
+    let address = free_address();
+    let health = HealthReporter::new();
+    let ready = Arc::new(AtomicBool::new(false));
+    let checked = ready.clone();
+    health.poll(
+        &[SERVER, "test.Service"],
+        Duration::from_millis(20),
+        move || futures::future::ready(checked.load(Ordering::SeqCst)),
+    );
+    tokio::spawn(
+        Server::builder()
+            .add_service(health.service())
+            .serve(address),
+    );
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = HealthClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    assert_eq!(check(&mut client, SERVER).await, ServingStatus::NotServing);
+    let mut watch = client
+        .watch(request("test.Service"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(next(&mut watch).await, ServingStatus::NotServing);
+
+    ready.store(true, Ordering::SeqCst);
+    assert_eq!(next(&mut watch).await, ServingStatus::Serving);
+    assert_eq!(check(&mut client, SERVER).await, ServingStatus::Serving);
+
+    let err = client.check(request("unknown")).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+    let mut watch = client.watch(request("later")).await.unwrap().into_inner();
+    assert_eq!(next(&mut watch).await, ServingStatus::ServiceUnknown);
+    health.set_serving("later", true);
+    assert_eq!(next(&mut watch).await, ServingStatus::Serving);
+}
+
+async fn next_response(
+    stream: &mut tonic::Streaming<reflection::pb::ServerReflectionResponse>,
+) -> MessageResponse {
+    stream
+        .message()
+        .await
+        .unwrap()
+        .unwrap()
+        .message_response
+        .unwrap()
+}
+
+fn file_name(response: &MessageResponse) -> String {
+    match response {
+        MessageResponse::FileDescriptorResponse(files) => {
+            prost_types::FileDescriptorProto::decode(files.file_descriptor_proto[0].as_slice())
+                .unwrap()
+                .name
+                .unwrap()
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn reflection_describes_the_served_services() {
+    let address = free_address();
+    tokio::spawn(
+        Server::builder()
+            .add_service(HealthReporter::new().service())
+            .add_service(reflection::service(&[]).unwrap())
+            .serve(address),
+    );
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = ReflectionClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    let requests: Vec<_> = vec![
+        MessageRequest::ListServices(String::new()),
+        MessageRequest::FileContainingSymbol("grpc.health.v1.Health.Check".to_string()),
+        MessageRequest::FileContainingSymbol(
+            "grpc.health.v1.HealthCheckResponse.ServingStatus".to_string(),
+        ),
+        MessageRequest::FileByFilename("missing.proto".to_string()),
+    ]
+    .into_iter()
+    .map(|request| reflection::pb::ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(request),
+    })
+    .collect();
+    let mut responses = client
+        .server_reflection_info(futures::stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    match next_response(&mut responses).await {
+        MessageResponse::ListServicesResponse(list) => {
+            let names: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+            assert_eq!(
+                names,
+                vec![
+                    "grpc.health.v1.Health",
+                    "grpc.reflection.v1alpha.ServerReflection"
+                ]
+            );
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+    assert_eq!(
+        file_name(&next_response(&mut responses).await),
+        "grpc/health/v1/health.proto"
+    );
+    assert_eq!(
+        file_name(&next_response(&mut responses).await),
+        "grpc/health/v1/health.proto"
+    );
+    match next_response(&mut responses).await {
+        MessageResponse::ErrorResponse(err) => {
+            assert_eq!(err.error_code, tonic::Code::NotFound as i32)
+        }
+        other => panic!("unexpected response: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn health_is_served_in_plaintext_until_the_server_stops() {
+    let address = free_address();
+    let health = HealthReporter::new();
+    health.set_serving(SERVER, true);
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = async move {
+        let _ = stopped.await;
+        Ok(())
+    };
+    let running = tokio::spawn(health.with_plaintext_health(server, Some(address)));
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = HealthClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    assert_eq!(check(&mut client, SERVER).await, ServingStatus::Serving);
+
+    stop.send(()).unwrap();
+    running.await.unwrap().unwrap();
+    delay_for(Duration::from_millis(100)).await;
+    assert!(HealthClient::connect(format!("http://{}", address))
+        .await
+        .is_err());
+}
@@ -0,0 +1,24 @@
+use std::io;
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Accepts the connections of a server until `shutdown` resolves, for
+/// `serve_with_incoming`. Servers stop taking connections on shutdown while the calls in
+/// flight finish.
+pub fn incoming(
+    mut listener: TcpListener,
+    shutdown: Option<BoxFuture<'static, ()>>,
+) -> BoxStream<'static, io::Result<TcpStream>> {
+    let mut shutdown = shutdown.unwrap_or_else(|| Box::pin(futures::future::pending()));
+    Box::pin(async_stream::stream! {
+        loop {
+            let conn = tokio::select! {
+                conn = listener.accept() => conn,
+                _ = &mut shutdown => break,
+            };
+            yield conn.map(|(stream, _)| stream);
+        }
+    })
+}
@@ -0,0 +1,353 @@
+//! TLS for the gRPC servers and clients. Certificates, keys and CA certificates are PEM files
+//! that are read again when they change, so that rotated certificates are used without a
+//! restart. The bearer tokens of the director and operator calls are read the same way.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use tracing::{info, warn};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientCertVerified, ClientCertVerifier, ClientConfig,
+    ClientHello, DistinguishedNames, NoClientAuth, ResolvesClientCert, ResolvesServerCert,
+    RootCertStore, ServerCertVerified, ServerCertVerifier, ServerConfig, SignatureScheme, TLSError,
+    WebPKIVerifier,
+};
+use serde::{Deserialize, Serialize};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint, ServerTlsConfig};
+
+mod incoming;
+mod token;
+
+pub use incoming::incoming;
+pub use token::TokenFile;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Certificate paths of a server or a client.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain and private key. A server serves TLS when they are given, and a
+    /// client presents them to servers that require client certificates.
+    pub cert_path: String,
+    pub key_path: String,
+    /// PEM CA certificates. A server requires client certificates signed by them, and a client
+    /// connects over TLS and verifies the server with them when they are given.
+    pub ca_path: String,
+    /// Name the server certificate is checked against instead of the host, e.g. because
+    /// gameservers are reached by their IP.
+    pub domain_name: String,
+}
+
+impl TlsConfig {
+    pub fn is_server_enabled(&self) -> bool {
+        !self.cert_path.is_empty()
+    }
+
+    pub fn is_client_enabled(&self) -> bool {
+        !self.ca_path.is_empty()
+    }
+
+    /// Checks that the given files can be loaded.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cert_path.is_empty() != self.key_path.is_empty() {
+            return Err("cert_path and key_path must be given together".to_string());
+        }
+        if !self.cert_path.is_empty() {
+            load_key(&self.cert_path, &self.key_path).map_err(|err| err.to_string())?;
+        }
+        if !self.ca_path.is_empty() {
+            load_roots(&self.ca_path).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>, Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| Error(format!("cannot open {}: {}", path, err)))
+}
+
+fn load_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, Error> {
+    let certs = pemfile::certs(&mut open(cert_path)?)
+        .map_err(|_| Error(format!("cannot parse {}", cert_path)))?;
+    if certs.is_empty() {
+        return Err(Error(format!("no certificate in {}", cert_path)));
+    }
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?)
+        .map_err(|_| Error(format!("cannot parse {}", key_path)))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?)
+            .map_err(|_| Error(format!("cannot parse {}", key_path)))?;
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| Error(format!("no private key in {}", key_path)))?;
+    let key = sign::any_supported_type(key)
+        .map_err(|_| Error(format!("unsupported private key in {}", key_path)))?;
+    if !is_pair(&certs[0], key.as_ref()) {
+        return Err(Error(format!(
+            "the key in {} is not the key of the certificate in {}",
+            key_path, cert_path
+        )));
+    }
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+/// Whether `key` is the private key of `cert`, by checking a signature made with it, e.g.
+/// when only one of the files of a rotation was written yet.
+fn is_pair(cert: &Certificate, key: &dyn sign::SigningKey) -> bool {
+    let signer = match key.choose_scheme(&[
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::ED25519,
+        SignatureScheme::RSA_PSS_SHA256,
+        SignatureScheme::RSA_PKCS1_SHA256,
+    ]) {
+        Some(signer) => signer,
+        None => return false,
+    };
+    let algorithm = match signer.get_scheme() {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        SignatureScheme::RSA_PSS_SHA256 => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+        SignatureScheme::RSA_PKCS1_SHA256 => &webpki::RSA_PKCS1_2048_8192_SHA256,
+        _ => return false,
+    };
+    let message = b"grpc-tls key pair check";
+    let signature = match signer.sign(message) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    webpki::EndEntityCert::from(&cert.0)
+        .and_then(|cert| cert.verify_signature(algorithm, message, &signature))
+        .is_ok()
+}
+
+fn load_roots(ca_path: &str) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots
+        .add_pem_file(&mut open(ca_path)?)
+        .map_err(|_| Error(format!("cannot parse {}", ca_path)))?;
+    if added == 0 {
+        return Err(Error(format!("no CA certificate in {}", ca_path)));
+    }
+    Ok(roots)
+}
+
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &str) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// A certificate and key that are read again on the next handshake after their files change.
+struct ReloadingKey {
+    cert_path: String,
+    key_path: String,
+    current: Mutex<([Stamp; 2], CertifiedKey)>,
+}
+
+impl ReloadingKey {
+    fn new(cert_path: &str, key_path: &str) -> Result<Self, Error> {
+        let stamps = [stamp(cert_path), stamp(key_path)];
+        let key = load_key(cert_path, key_path)?;
+        Ok(ReloadingKey {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            current: Mutex::new((stamps, key)),
+        })
+    }
+
+    /// A rotation that cannot be loaded keeps the previous certificate.
+    fn get(&self) -> CertifiedKey {
+        let mut current = self.current.lock().unwrap();
+        let stamps = [stamp(&self.cert_path), stamp(&self.key_path)];
+        if stamps != current.0 {
+            match load_key(&self.cert_path, &self.key_path) {
+                Ok(key) => {
+                    info!("reloaded certificate {}", self.cert_path);
+                    current.1 = key;
+                }
+                Err(err) => warn!(
+                    "cannot reload certificate, keeping the previous one: {}",
+                    err
+                ),
+            }
+            current.0 = stamps;
+        }
+        current.1.clone()
+    }
+}
+
+impl ResolvesServerCert for ReloadingKey {
+    fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+        Some(self.get())
+    }
+}
+
+impl ResolvesClientCert for ReloadingKey {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<CertifiedKey> {
+        Some(self.get())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// CA certificates that are read again on the next handshake after their file changes.
+struct ReloadingRoots {
+    ca_path: String,
+    current: Mutex<(Stamp, RootCertStore)>,
+}
+
+impl ReloadingRoots {
+    fn new(ca_path: &str) -> Result<Self, Error> {
+        let stamp = stamp(ca_path);
+        let roots = load_roots(ca_path)?;
+        Ok(ReloadingRoots {
+            ca_path: ca_path.to_string(),
+            current: Mutex::new((stamp, roots)),
+        })
+    }
+
+    /// A rotation that cannot be loaded keeps the previous certificates.
+    fn get(&self) -> RootCertStore {
+        let mut current = self.current.lock().unwrap();
+        let stamp = stamp(&self.ca_path);
+        if stamp != current.0 {
+            match load_roots(&self.ca_path) {
+                Ok(roots) => {
+                    info!("reloaded CA certificates {}", self.ca_path);
+                    current.1 = roots;
+                }
+                Err(err) => warn!(
+                    "cannot reload CA certificates, keeping the previous ones: {}",
+                    err
+                ),
+            }
+            current.0 = stamp;
+        }
+        current.1.clone()
+    }
+}
+
+impl ClientCertVerifier for ReloadingRoots {
+    fn client_auth_root_subjects(&self) -> DistinguishedNames {
+        AllowAnyAuthenticatedClient::new(self.get()).client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+    ) -> Result<ClientCertVerified, TLSError> {
+        AllowAnyAuthenticatedClient::new(self.get()).verify_client_cert(presented_certs)
+    }
+}
+
+impl ServerCertVerifier for ReloadingRoots {
+    fn verify_server_cert(
+        &self,
+        _: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        WebPKIVerifier::new().verify_server_cert(
+            &self.get(),
+            presented_certs,
+            dns_name,
+            ocsp_response,
+        )
+    }
+}
+
+fn h2() -> Vec<Vec<u8>> {
+    vec![b"h2".to_vec()]
+}
+
+/// The rustls config of a server. Client certificates are required when the CA is given.
+pub fn server_config(config: &TlsConfig) -> Result<ServerConfig, Error> {
+    let mut server_config = if config.ca_path.is_empty() {
+        ServerConfig::new(NoClientAuth::new())
+    } else {
+        ServerConfig::new(Arc::new(ReloadingRoots::new(&config.ca_path)?))
+    };
+    server_config.cert_resolver = Arc::new(ReloadingKey::new(&config.cert_path, &config.key_path)?);
+    server_config.set_protocols(&h2());
+    Ok(server_config)
+}
+
+/// The rustls config of a client. The certificate is presented when it is given.
+pub fn client_config(config: &TlsConfig) -> Result<ClientConfig, Error> {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(ReloadingRoots::new(&config.ca_path)?));
+    if !config.cert_path.is_empty() {
+        client_config.client_auth_cert_resolver =
+            Arc::new(ReloadingKey::new(&config.cert_path, &config.key_path)?);
+    }
+    client_config.set_protocols(&h2());
+    Ok(client_config)
+}
+
+/// The TLS config of a tonic server, or `None` when TLS is off.
+pub fn server_tls(config: &TlsConfig) -> Result<Option<ServerTlsConfig>, Error> {
+    if !config.is_server_enabled() {
+        return Ok(None);
+    }
+    let mut tls = ServerTlsConfig::with_rustls();
+    tls.rustls_server_config(server_config(config)?);
+    Ok(Some(tls))
+}
+
+fn host(address: &str) -> &str {
+    match address.rfind(':') {
+        Some(i) => &address[..i],
+        None => address,
+    }
+}
+
+/// Connects to `address` (`host:port`), over TLS when the CA is given.
+pub async fn connect(address: &str, config: &TlsConfig) -> Result<Channel, Error> {
+    let transport_err =
+        |err: tonic::transport::Error| Error(format!("cannot connect to {}: {}", address, err));
+    if !config.is_client_enabled() {
+        let endpoint = Endpoint::from_shared(format!("http://{}", address))
+            .map_err(|err| Error(format!("invalid address {}: {}", address, err)))?;
+        return endpoint.connect().await.map_err(transport_err);
+    }
+    let domain_name = if config.domain_name.is_empty() {
+        host(address).to_string()
+    } else {
+        config.domain_name.clone()
+    };
+    let mut tls = ClientTlsConfig::with_rustls();
+    tls.rustls_client_config(client_config(config)?)
+        .domain_name(domain_name);
+    let mut endpoint = Endpoint::from_shared(format!("https://{}", address))
+        .map_err(|err| Error(format!("invalid address {}: {}", address, err)))?;
+    endpoint
+        .tls_config(&tls)
+        .connect()
+        .await
+        .map_err(transport_err)
+}
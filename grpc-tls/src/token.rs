@@ -0,0 +1,77 @@
+//! Bearer tokens for the calls that only the director or the operators make.
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use tracing::{error, info};
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+use super::{stamp, Error, Stamp};
+
+const AUTHORIZATION: &str = "authorization";
+
+/// A bearer token in a file, e.g. a mounted Secret, that is read again after the file
+/// changes. Clones share the token.
+#[derive(Clone, Debug)]
+pub struct TokenFile {
+    path: String,
+    current: Arc<Mutex<(Stamp, String)>>,
+}
+
+impl TokenFile {
+    pub fn new(path: &str) -> Self {
+        TokenFile {
+            path: path.to_string(),
+            current: Arc::new(Mutex::new((None, String::new()))),
+        }
+    }
+
+    /// The token without surrounding whitespace. An empty file is an error.
+    pub fn token(&self) -> Result<String, Error> {
+        let mut current = self.current.lock().unwrap();
+        let stamp = stamp(&self.path);
+        if stamp.is_none() || stamp != current.0 {
+            let token = fs::read_to_string(&self.path)
+                .map_err(|err| Error(format!("cannot read {}: {}", self.path, err)))?;
+            if current.0.is_some() {
+                info!("reloaded token {}", self.path);
+            }
+            *current = (stamp, token.trim().to_string());
+        }
+        if current.1.is_empty() {
+            return Err(Error(format!("no token in {}", self.path)));
+        }
+        Ok(current.1.clone())
+    }
+
+    /// Checks the `authorization: Bearer <token>` metadata of a request.
+    pub fn authorize(&self, metadata: &MetadataMap) -> Result<(), tonic::Status> {
+        let token = self.token().map_err(|err| {
+            error!("{}", err);
+            tonic::Status::new(tonic::Code::Unavailable, "the token is not available")
+        })?;
+        let given = metadata
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if !constant_time_eq(given.as_bytes(), format!("Bearer {}", token).as_bytes()) {
+            return Err(tonic::Status::new(
+                tonic::Code::Unauthenticated,
+                "invalid token",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds the token to the metadata of an outgoing request.
+    pub fn insert(&self, metadata: &mut MetadataMap) -> Result<(), Error> {
+        let value = MetadataValue::from_str(&format!("Bearer {}", self.token()?))
+            .map_err(|_| Error(format!("invalid token in {}", self.path)))?;
+        metadata.insert(AUTHORIZATION, value);
+        Ok(())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use grpc_tls::{client_config, server_config, TlsConfig};
+
+const SERVER_NAME: &str = "server.test";
+
+struct Certs {
+    dir: PathBuf,
+    ca: Certificate,
+}
+
+impl Certs {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("grpc-tls-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(params).unwrap();
+        fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        Certs { dir, ca }
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.dir.join(file).to_str().unwrap().to_string()
+    }
+
+    /// Writes a certificate for `name` signed by the CA to `<file>.pem` and `<file>-key.pem`.
+    fn issue(&self, file: &str, name: &str) -> TlsConfig {
+        let cert =
+            Certificate::from_params(CertificateParams::new(vec![name.to_string()])).unwrap();
+        let cert_path = self.path(&format!("{}.pem", file));
+        let key_path = self.path(&format!("{}-key.pem", file));
+        fs::write(
+            &cert_path,
+            cert.serialize_pem_with_signer(&self.ca).unwrap(),
+        )
+        .unwrap();
+        fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        TlsConfig {
+            cert_path,
+            key_path,
+            ca_path: self.path("ca.pem"),
+            domain_name: String::new(),
+        }
+    }
+}
+
+/// Runs one handshake and returns whether the server accepted it and the certificate the
+/// client received.
+async fn handshake(acceptor: TlsAcceptor, connector: TlsConnector) -> (bool, Vec<u8>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        match acceptor.accept(stream).await {
+            Ok(mut stream) => {
+                stream.write_all(b"ok").await.unwrap();
+                stream.flush().await.unwrap();
+                true
+            }
+            Err(_) => false,
+        }
+    });
+    let stream = TcpStream::connect(address).await.unwrap();
+    let domain = webpki::DNSNameRef::try_from_ascii_str(SERVER_NAME).unwrap();
+    let mut peer_cert = Vec::new();
+    if let Ok(mut stream) = connector.connect(domain, stream).await {
+        let mut buf = [0; 2];
+        if stream.read_exact(&mut buf).await.is_ok() {
+            let (_, session) = stream.get_ref();
+            peer_cert = rustls::Session::get_peer_certificates(session).unwrap()[0]
+                .0
+                .clone();
+        }
+    }
+    (server.await.unwrap(), peer_cert)
+}
+
+fn first_cert(path: &str) -> Vec<u8> {
+    let pem = fs::read(path).unwrap();
+    rustls::internal::pemfile::certs(&mut pem.as_slice()).unwrap()[0]
+        .0
+        .clone()
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_a_client_certificate_signed_by_the_ca() {
+    let certs = Certs::new("mtls");
+    let server = certs.issue("server", SERVER_NAME);
+    let client = certs.issue("client", "director");
+    let acceptor = TlsAcceptor::from(Arc::new(server_config(&server).unwrap()));
+
+    let connector = TlsConnector::from(Arc::new(client_config(&client).unwrap()));
+    let (accepted, peer_cert) = handshake(acceptor.clone(), connector).await;
+    assert!(accepted);
+    assert_eq!(peer_cert, first_cert(&server.cert_path));
+
+    let anonymous = TlsConfig {
+        ca_path: client.ca_path.clone(),
+        ..Default::default()
+    };
+    let connector = TlsConnector::from(Arc::new(client_config(&anonymous).unwrap()));
+    let (accepted, _) = handshake(acceptor.clone(), connector).await;
+    assert!(!accepted);
+
+    let other = Certs::new("other").issue("client", "director");
+    let connector = TlsConnector::from(Arc::new(
+        client_config(&TlsConfig {
+            ca_path: client.ca_path.clone(),
+            ..other
+        })
+        .unwrap(),
+    ));
+    let (accepted, _) = handshake(acceptor, connector).await;
+    assert!(!accepted);
+}
+
+#[tokio::test]
+async fn rotated_certificates_are_served_without_a_restart() {
+    let certs = Certs::new("rotation");
+    let server = certs.issue("server", SERVER_NAME);
+    let client = TlsConfig {
+        ca_path: server.ca_path.clone(),
+        ..Default::default()
+    };
+    let acceptor = TlsAcceptor::from(Arc::new(
+        server_config(&TlsConfig {
+            ca_path: String::new(),
+            ..server.clone()
+        })
+        .unwrap(),
+    ));
+    let connector = TlsConnector::from(Arc::new(client_config(&client).unwrap()));
+    let (_, before) = handshake(acceptor.clone(), connector.clone()).await;
+    assert_eq!(before, first_cert(&server.cert_path));
+
+    certs.issue("server", SERVER_NAME);
+    let (accepted, after) = handshake(acceptor.clone(), connector.clone()).await;
+    assert!(accepted);
+    assert_ne!(after, before);
+    assert_eq!(after, first_cert(&server.cert_path));
+
+    // a rotation whose key isn't written yet keeps the last good certificate
+    let rotated = certs.issue("rotated", SERVER_NAME);
+    fs::copy(&rotated.cert_path, &server.cert_path).unwrap();
+    let (accepted, kept) = handshake(acceptor.clone(), connector.clone()).await;
+    assert!(accepted);
+    assert_eq!(kept, after);
+
+    // a broken rotation keeps the last good certificate
+    fs::write(&server.key_path, "not a key").unwrap();
+    let (accepted, kept) = handshake(acceptor.clone(), connector.clone()).await;
+    assert!(accepted);
+    assert_eq!(kept, after);
+
+    fs::copy(&rotated.key_path, &server.key_path).unwrap();
+    let (accepted, now) = handshake(acceptor, connector).await;
+    assert!(accepted);
+    assert_eq!(now, first_cert(&rotated.cert_path));
+}
+
+#[tokio::test]
+async fn rotated_ca_certificates_are_trusted_without_a_restart() {
+    let certs = Certs::new("ca-rotation");
+    let server = certs.issue("server", SERVER_NAME);
+    let other = Certs::new("ca-rotation-other");
+    let client = other.issue("client", "director");
+
+    // the server trusts the CA of its own certificate, the client trusts the other CA
+    let server_ca = certs.path("trusted.pem");
+    fs::copy(&server.ca_path, &server_ca).unwrap();
+    let client_ca = other.path("trusted.pem");
+    fs::copy(&client.ca_path, &client_ca).unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(
+        server_config(&TlsConfig {
+            ca_path: server_ca.clone(),
+            ..server.clone()
+        })
+        .unwrap(),
+    ));
+    let connector = TlsConnector::from(Arc::new(
+        client_config(&TlsConfig {
+            ca_path: client_ca.clone(),
+            ..client.clone()
+        })
+        .unwrap(),
+    ));
+    let (accepted, _) = handshake(acceptor.clone(), connector.clone()).await;
+    assert!(!accepted);
+
+    // both sides trust the CA of the other after it's rotated in
+    fs::copy(&client.ca_path, &server_ca).unwrap();
+    fs::copy(&server.ca_path, &client_ca).unwrap();
+    let (accepted, peer_cert) = handshake(acceptor, connector).await;
+    assert!(accepted);
+    assert_eq!(peer_cert, first_cert(&server.cert_path));
+}
+
+#[test]
+fn validate_reports_unreadable_files() {
+    let config = TlsConfig {
+        cert_path: "/nonexistent/cert.pem".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.validate().unwrap_err(),
+        "cert_path and key_path must be given together"
+    );
+    let config = TlsConfig {
+        key_path: "/nonexistent/key.pem".to_string(),
+        ..config
+    };
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .starts_with("cannot open /nonexistent/cert.pem"));
+}
+
+#[test]
+fn validate_rejects_keys_of_other_certificates() {
+    let certs = Certs::new("pair");
+    let server = certs.issue("server", SERVER_NAME);
+    let client = certs.issue("client", "director");
+    let config = TlsConfig {
+        key_path: client.key_path.clone(),
+        ..server
+    };
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .contains("is not the key of the certificate"));
+}
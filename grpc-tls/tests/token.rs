@@ -0,0 +1,60 @@
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use tonic::metadata::MetadataMap;
+
+use grpc_tls::TokenFile;
+
+fn path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("grpc-tls-{}-{}", std::process::id(), name))
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn requests_carry_the_token_of_the_file() {
+    let path = path("token");
+    fs::write(&path, "secret\n").unwrap();
+    let token = TokenFile::new(&path);
+
+    let mut metadata = MetadataMap::new();
+    token.insert(&mut metadata).unwrap();
+    assert_eq!(
+        metadata.get("authorization").unwrap().to_str().unwrap(),
+        "Bearer secret"
+    );
+    token.authorize(&metadata).unwrap();
+
+    let mut wrong = MetadataMap::new();
+    wrong.insert("authorization", "Bearer secreT".parse().unwrap());
+    let err = token.authorize(&wrong).unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+    let err = token.authorize(&MetadataMap::new()).unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+    // a rotated token replaces the old one
+    thread::sleep(Duration::from_millis(10));
+    fs::write(&path, "rotated-secret").unwrap();
+    assert_eq!(token.token().unwrap(), "rotated-secret");
+    let err = token.authorize(&metadata).unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+}
+
+#[test]
+fn missing_and_empty_tokens_are_refused() {
+    let missing = TokenFile::new(&path("missing"));
+    assert!(missing.token().is_err());
+    let err = missing.authorize(&MetadataMap::new()).unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+
+    let path = path("empty");
+    fs::write(&path, "\n").unwrap();
+    let empty = TokenFile::new(&path);
+    assert!(empty.token().is_err());
+    let mut metadata = MetadataMap::new();
+    metadata.insert("authorization", "Bearer ".parse().unwrap());
+    assert!(empty.authorize(&metadata).is_err());
+}
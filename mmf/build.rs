@@ -1,3 +1,5 @@
+use grpc_health::build::descriptor_set;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure().build_server(true).compile(
         &[
@@ -6,5 +8,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ],
         &["../deps/open-match", "../deps/open-match/third_party"],
     )?;
+    descriptor_set(
+        &["../deps/open-match/api/matchfunction.proto"],
+        &["../deps/open-match", "../deps/open-match/third_party"],
+        "mmf_descriptor.bin",
+    )?;
     Ok(())
 }
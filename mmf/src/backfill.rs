@@ -0,0 +1,113 @@
+use std::time::SystemTime;
+
+use prost::Message;
+
+use super::match_function::{party_size, skill_rating, waiting_time};
+use super::params::MatchParams;
+use super::regions::best_region;
+use super::service::om;
+
+/// Profile extension set by the director with the running matches that need players.
+const BACKFILLS_EXTENSION: &str = "backfills";
+/// Match extension marking a proposal that adds players to a running match.
+pub const BACKFILL_EXTENSION: &str = "backfill";
+
+/// A running match that needs `open_slots` more players.
+#[derive(Clone, Debug)]
+pub struct Backfill {
+    pub match_id: String,
+    pub open_slots: usize,
+    /// Region of the match, empty for any region.
+    pub region: String,
+    /// Mean skill rating of the players the match was made with, if known.
+    pub skill_rating: Option<f64>,
+}
+
+/// Decodes the backfills of the profile, a `ListValue` of `Struct`s with `match_id` and
+/// `open_slots`, and optionally `region` and `skill_rating`.
+pub fn backfills(profile: &om::MatchProfile) -> Result<Vec<Backfill>, String> {
+    let any = match profile.extensions.get(BACKFILLS_EXTENSION) {
+        Some(any) => any,
+        None => return Ok(vec![]),
+    };
+    let list = prost_types::ListValue::decode(any.value.as_slice())
+        .map_err(|err| format!("cannot decode {}: {}", BACKFILLS_EXTENSION, err))?;
+    list.values
+        .into_iter()
+        .map(|value| {
+            let fields = match value.kind {
+                Some(prost_types::value::Kind::StructValue(s)) => s.fields,
+                kind => return Err(format!("invalid backfill: {:?}", kind)),
+            };
+            let match_id = match fields.get("match_id").and_then(|v| v.kind.as_ref()) {
+                Some(prost_types::value::Kind::StringValue(id)) => id.clone(),
+                _ => return Err("backfill without match_id".to_string()),
+            };
+            let open_slots = match fields.get("open_slots").and_then(|v| v.kind.as_ref()) {
+                Some(prost_types::value::Kind::NumberValue(n)) => *n as usize,
+                _ => return Err("backfill without open_slots".to_string()),
+            };
+            let region = match fields.get("region").and_then(|v| v.kind.as_ref()) {
+                Some(prost_types::value::Kind::StringValue(region)) => region.clone(),
+                _ => String::new(),
+            };
+            let skill_rating = match fields.get("skill_rating").and_then(|v| v.kind.as_ref()) {
+                Some(prost_types::value::Kind::NumberValue(n)) => Some(*n),
+                _ => None,
+            };
+            Ok(Backfill {
+                match_id,
+                open_slots,
+                region,
+                skill_rating,
+            })
+        })
+        .collect()
+}
+
+/// Whether the ticket would have been matched with the players of the running match: it
+/// plays best in the region of the match, and with the skill match function, its rating
+/// is within the skill window of the match.
+fn fits(ticket: &om::Ticket, backfill: &Backfill, params: &MatchParams, now: SystemTime) -> bool {
+    if !backfill.region.is_empty() {
+        match best_region(ticket, params.max_latency) {
+            Some(region) if region.is_empty() || region == backfill.region => {}
+            _ => return false,
+        }
+    }
+    match backfill.skill_rating {
+        Some(rating) if params.match_function == "skill" => {
+            let window = params.skill_window_after(waiting_time(ticket, now));
+            (skill_rating(ticket) - rating).abs() <= window
+        }
+        _ => true,
+    }
+}
+
+/// Takes the tickets that have waited the longest, up to `open_slots` players, out of
+/// `tickets`. Parties that don't fit and tickets that don't fit the match are skipped.
+pub fn fill(
+    tickets: &mut Vec<om::Ticket>,
+    backfill: &Backfill,
+    params: &MatchParams,
+    now: SystemTime,
+) -> Vec<om::Ticket> {
+    tickets.sort_by(|a, b| waiting_time(b, now).cmp(&waiting_time(a, now)));
+    let mut remaining = backfill.open_slots;
+    let mut filled = Vec::new();
+    let mut i = 0;
+    while i < tickets.len() && remaining > 0 {
+        let size = party_size(&tickets[i]);
+        if size <= remaining && fits(&tickets[i], backfill, params, now) {
+            remaining -= size;
+            filled.push(tickets.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    filled
+}
+
+pub fn backfill_extension(match_id: &str) -> Result<prost_types::Any, prost::EncodeError> {
+    om_ext::string_any(match_id)
+}
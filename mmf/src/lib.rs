@@ -0,0 +1,8 @@
+pub mod backfill;
+pub mod match_function;
+pub mod metrics;
+pub mod params;
+pub mod regions;
+pub mod server;
+pub mod service;
+pub mod teams;
@@ -1,8 +1,8 @@
-mod service;
+use mmf::server;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    service::run_server().await?;
+    telemetry::init("mmf")?;
+    server::run_server().await?;
     Ok(())
 }
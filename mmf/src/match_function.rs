@@ -0,0 +1,168 @@
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime};
+
+use super::params::MatchParams;
+use super::service::om;
+
+// Search fields set by the frontend.
+const SKILL_RATING_ARG: &str = "skill_rating";
+// Set on tickets that carry a whole party.
+const PARTY_SIZE_ARG: &str = "party_size";
+
+/// Number of players a ticket stands for. A party is queued as one ticket.
+pub fn party_size(ticket: &om::Ticket) -> usize {
+    ticket
+        .search_fields
+        .as_ref()
+        .and_then(|fields| fields.double_args.get(PARTY_SIZE_ARG))
+        .map(|size| *size as usize)
+        .unwrap_or(1)
+        .max(1)
+}
+
+pub fn skill_rating(ticket: &om::Ticket) -> f64 {
+    ticket
+        .search_fields
+        .as_ref()
+        .and_then(|fields| fields.double_args.get(SKILL_RATING_ARG))
+        .cloned()
+        .unwrap_or(0.0)
+}
+
+/// How long the ticket has been waiting. Tickets without `create_time` count as new.
+pub fn waiting_time(ticket: &om::Ticket, now: SystemTime) -> Duration {
+    ticket
+        .create_time
+        .clone()
+        .and_then(|t| SystemTime::try_from(t).ok())
+        .and_then(|t| now.duration_since(t).ok())
+        .unwrap_or_default()
+}
+
+/// Removes the tickets at `indices` from `tickets` and returns them in the given order.
+fn remove_tickets(tickets: &mut Vec<om::Ticket>, indices: Vec<usize>) -> Vec<om::Ticket> {
+    let taken = indices.iter().map(|&i| tickets[i].clone()).collect();
+    let mut indices = indices;
+    indices.sort_unstable();
+    for i in indices.into_iter().rev() {
+        tickets.remove(i);
+    }
+    taken
+}
+
+/// Picks tickets from `candidates` (indices into `tickets`, in order of preference) until
+/// they add up to exactly `num_players` players. Parties that don't fit are skipped.
+fn pick_tickets(
+    tickets: &[om::Ticket],
+    candidates: impl Iterator<Item = usize>,
+    num_players: usize,
+) -> Option<Vec<usize>> {
+    let mut picked = Vec::new();
+    let mut remaining = num_players;
+    for i in candidates {
+        let size = party_size(&tickets[i]);
+        if size <= remaining {
+            picked.push(i);
+            remaining -= size;
+            if remaining == 0 {
+                return Some(picked);
+            }
+        }
+    }
+    None
+}
+
+pub trait MatchFunction {
+    /// Groups tickets into matches of `num_matching_members` players. The tickets used in a
+    /// match are removed from `tickets`, the rest stay for the next run.
+    fn make_matches(&self, tickets: &mut Vec<om::Ticket>, now: SystemTime) -> Vec<Vec<om::Ticket>>;
+}
+
+/// Takes tickets strictly in arrival order.
+pub struct BasicMatchFunction {
+    pub num_matching_members: usize,
+}
+
+impl MatchFunction for BasicMatchFunction {
+    fn make_matches(
+        &self,
+        tickets: &mut Vec<om::Ticket>,
+        _now: SystemTime,
+    ) -> Vec<Vec<om::Ticket>> {
+        let mut matches = Vec::new();
+        while let Some(picked) = pick_tickets(tickets, 0..tickets.len(), self.num_matching_members)
+        {
+            matches.push(remove_tickets(tickets, picked));
+        }
+        matches
+    }
+}
+
+/// Matches players with similar skill ratings.
+///
+/// Starting from the ticket that has waited the longest, the closest rated tickets are
+/// taken as long as the rating spread of the match stays within the skill window of the
+/// oldest ticket (see [`MatchParams::skill_window_after`]).
+pub struct SkillBasedMatchFunction {
+    pub params: MatchParams,
+}
+
+impl MatchFunction for SkillBasedMatchFunction {
+    fn make_matches(&self, tickets: &mut Vec<om::Ticket>, now: SystemTime) -> Vec<Vec<om::Ticket>> {
+        // oldest first
+        tickets.sort_by(|a, b| waiting_time(b, now).cmp(&waiting_time(a, now)));
+
+        let mut matches = Vec::new();
+        let mut anchor = 0;
+        while anchor < tickets.len() {
+            let window = self
+                .params
+                .skill_window_after(waiting_time(&tickets[anchor], now));
+            let rating = skill_rating(&tickets[anchor]);
+
+            let mut candidates: Vec<usize> = (0..tickets.len())
+                .filter(|&i| i != anchor && (skill_rating(&tickets[i]) - rating).abs() <= window)
+                .collect();
+            candidates.sort_by(|&a, &b| {
+                let da = (skill_rating(&tickets[a]) - rating).abs();
+                let db = (skill_rating(&tickets[b]) - rating).abs();
+                da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+            let picked = pick_tickets(
+                tickets,
+                std::iter::once(anchor).chain(candidates.into_iter()),
+                self.params.num_matching_members(),
+            )
+            .filter(|picked| picked.first() == Some(&anchor))
+            .filter(|picked| {
+                let ratings = picked.iter().map(|&i| skill_rating(&tickets[i]));
+                let min = ratings.clone().fold(f64::INFINITY, f64::min);
+                let max = ratings.fold(f64::NEG_INFINITY, f64::max);
+                max - min <= window
+            });
+            match picked {
+                // the next oldest ticket moved to `anchor`
+                Some(picked) => matches.push(remove_tickets(tickets, picked)),
+                None => anchor += 1,
+            }
+        }
+        matches
+    }
+}
+
+/// Creates the match function selected by `params.match_function`.
+pub fn new_match_function(
+    params: &MatchParams,
+) -> Result<Box<dyn MatchFunction + Send + Sync>, String> {
+    let num_matching_members = params.num_matching_members();
+    match params.match_function.as_str() {
+        "basic" => Ok(Box::new(BasicMatchFunction {
+            num_matching_members,
+        })),
+        "skill" => Ok(Box::new(SkillBasedMatchFunction {
+            params: params.clone(),
+        })),
+        name => Err(format!("unknown match function: {}", name)),
+    }
+}
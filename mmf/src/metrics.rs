@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{register_histogram_vec, register_int_gauge_vec, HistogramVec, IntGaugeVec};
+
+lazy_static! {
+    static ref POOL_TICKETS: IntGaugeVec = register_int_gauge_vec!(
+        "mmf_pool_tickets",
+        "Number of tickets in a pool at the latest run of its profile.",
+        &["profile", "pool"]
+    )
+    .unwrap();
+    static ref RUN_PROPOSALS: HistogramVec = register_histogram_vec!(
+        "mmf_run_proposals",
+        "Number of matches proposed by a run, backfills included.",
+        &["profile"],
+        vec![0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0]
+    )
+    .unwrap();
+    static ref LEFTOVER_TICKETS: IntGaugeVec = register_int_gauge_vec!(
+        "mmf_leftover_tickets",
+        "Number of tickets left out of every match at the latest run of a profile.",
+        &["profile"]
+    )
+    .unwrap();
+    static ref RUN_DURATION: HistogramVec = register_histogram_vec!(
+        "mmf_run_duration_seconds",
+        "Duration of runs in seconds.",
+        &["profile"]
+    )
+    .unwrap();
+}
+
+pub fn record_pool(profile: &str, pool: &str, tickets: usize) {
+    POOL_TICKETS
+        .with_label_values(&[profile, pool])
+        .set(tickets as i64);
+}
+
+pub fn record_run(profile: &str, proposals: usize, leftover_tickets: usize, duration: Duration) {
+    RUN_PROPOSALS
+        .with_label_values(&[profile])
+        .observe(proposals as f64);
+    LEFTOVER_TICKETS
+        .with_label_values(&[profile])
+        .set(leftover_tickets as i64);
+    RUN_DURATION
+        .with_label_values(&[profile])
+        .observe(duration.as_secs_f64());
+}
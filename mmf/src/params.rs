@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use super::service::om;
+
+/// Profile extension set by the director with the parameters of the profile.
+const MMF_PARAMS_EXTENSION: &str = "mmf_params";
+
+/// Parameters of one match function run. The values from the config are the
+/// defaults, and each profile can override them in its `mmf_params` extension.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MatchParams {
+    pub match_function: String,
+    pub team_count: usize,
+    pub team_size: usize,
+    pub skill_window: f64,
+    pub skill_window_growth: f64,
+    pub skill_window_max: f64,
+    /// Highest latency in milliseconds a player accepts for the region of the match.
+    pub max_latency: f64,
+}
+
+impl MatchParams {
+    pub fn num_matching_members(&self) -> usize {
+        self.team_count * self.team_size
+    }
+
+    /// The skill window of a ticket that has waited for `waiting_time`.
+    pub fn skill_window_after(&self, waiting_time: Duration) -> f64 {
+        let window = self.skill_window + self.skill_window_growth * waiting_time.as_secs_f64();
+        window.min(self.skill_window_max)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.team_count == 0 || self.team_size == 0 {
+            return Err(format!(
+                "team_count and team_size must be positive: {} * {}",
+                self.team_count, self.team_size
+            ));
+        }
+        if self.skill_window < 0.0 || self.skill_window_growth < 0.0 {
+            return Err("skill_window and skill_window_growth must not be negative".to_string());
+        }
+        if self.max_latency <= 0.0 {
+            return Err(format!(
+                "max_latency must be positive: {}",
+                self.max_latency
+            ));
+        }
+        if self.skill_window_max < self.skill_window {
+            return Err(format!(
+                "skill_window_max must not be less than skill_window: {} < {}",
+                self.skill_window_max, self.skill_window
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns these parameters overridden by the `mmf_params` extension of the profile.
+    pub fn with_profile(&self, profile: &om::MatchProfile) -> Result<MatchParams, String> {
+        let mut params = self.clone();
+        let any = match profile.extensions.get(MMF_PARAMS_EXTENSION) {
+            Some(any) => any,
+            None => return Ok(params),
+        };
+        let overrides = prost_types::Struct::decode(any.value.as_slice())
+            .map_err(|err| format!("cannot decode {}: {}", MMF_PARAMS_EXTENSION, err))?;
+        for (key, value) in overrides.fields {
+            match (key.as_str(), value.kind) {
+                ("match_function", Some(prost_types::value::Kind::StringValue(name))) => {
+                    params.match_function = name
+                }
+                ("team_count", Some(prost_types::value::Kind::NumberValue(n))) => {
+                    params.team_count = n as usize
+                }
+                ("team_size", Some(prost_types::value::Kind::NumberValue(n))) => {
+                    params.team_size = n as usize
+                }
+                ("skill_window", Some(prost_types::value::Kind::NumberValue(n))) => {
+                    params.skill_window = n
+                }
+                ("skill_window_growth", Some(prost_types::value::Kind::NumberValue(n))) => {
+                    params.skill_window_growth = n
+                }
+                ("skill_window_max", Some(prost_types::value::Kind::NumberValue(n))) => {
+                    params.skill_window_max = n
+                }
+                ("max_latency", Some(prost_types::value::Kind::NumberValue(n))) => {
+                    params.max_latency = n
+                }
+                (key, kind) => {
+                    return Err(format!("invalid mmf parameter: {} = {:?}", key, kind));
+                }
+            }
+        }
+        params.validate()?;
+        Ok(params)
+    }
+}
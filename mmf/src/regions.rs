@@ -0,0 +1,92 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use super::match_function::party_size;
+use super::service::om;
+
+// Search fields set by the frontend. Latencies are keyed by region, e.g. "latency.asia".
+const LATENCY_ARG_PREFIX: &str = "latency.";
+const REGION_ARG: &str = "region";
+/// Match extension holding the region the match should be played in.
+pub const REGION_EXTENSION: &str = "region";
+
+/// Returns the region with the lowest latency within `max_latency`.
+///
+/// Tickets without latencies fall back to the region they asked for, and to `Some("")`
+/// (any region) if there is none. `None` means no region is acceptable.
+pub fn best_region(ticket: &om::Ticket, max_latency: f64) -> Option<String> {
+    let fields = match &ticket.search_fields {
+        Some(fields) => fields,
+        None => return Some("".to_string()),
+    };
+    let mut latencies = fields
+        .double_args
+        .iter()
+        .filter_map(|(arg, latency)| {
+            if arg.starts_with(LATENCY_ARG_PREFIX) {
+                Some((&arg[LATENCY_ARG_PREFIX.len()..], *latency))
+            } else {
+                None
+            }
+        })
+        .peekable();
+    if latencies.peek().is_none() {
+        return Some(
+            fields
+                .string_args
+                .get(REGION_ARG)
+                .cloned()
+                .unwrap_or_default(),
+        );
+    }
+    latencies
+        .filter(|(_, latency)| *latency <= max_latency)
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(region, _)| region.to_string())
+}
+
+/// Groups tickets by their best region. Tickets with no acceptable region are dropped.
+///
+/// Tickets that accept any region join the regional groups, first the one that is the fewest
+/// players short of its next match of `match_size`, so they don't wait in a pool of their own.
+/// They only form a group (`""`) when no ticket has a region.
+pub fn group_by_region(
+    tickets: Vec<om::Ticket>,
+    max_latency: f64,
+    match_size: usize,
+) -> BTreeMap<String, Vec<om::Ticket>> {
+    let mut groups: BTreeMap<String, Vec<om::Ticket>> = BTreeMap::new();
+    let mut any_region = Vec::new();
+    for ticket in tickets {
+        match best_region(&ticket, max_latency) {
+            Some(region) if region.is_empty() => any_region.push(ticket),
+            Some(region) => groups.entry(region).or_default().push(ticket),
+            None => {}
+        }
+    }
+    if groups.is_empty() {
+        if !any_region.is_empty() {
+            groups.insert(String::new(), any_region);
+        }
+        return groups;
+    }
+    let match_size = match_size.max(1);
+    for ticket in any_region {
+        let region = groups
+            .iter()
+            .map(|(region, tickets)| {
+                let players: usize = tickets.iter().map(party_size).sum();
+                let missing = match_size - players % match_size;
+                (missing, Reverse(players), region)
+            })
+            .min()
+            .map(|(_, _, region)| region.clone())
+            .unwrap();
+        groups.get_mut(&region).unwrap().push(ticket);
+    }
+    groups
+}
+
+pub fn region_extension(region: &str) -> Result<prost_types::Any, prost::EncodeError> {
+    om_ext::string_any(region)
+}
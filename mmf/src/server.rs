@@ -0,0 +1,236 @@
+use std::future::Future;
+use std::net::SocketAddr;
+
+use futures::future::BoxFuture;
+use grpc_health::HealthReporter;
+use grpc_tls::{incoming, TlsConfig};
+use serde::{Deserialize, Serialize};
+use settings::{EnvKey, Validate};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use super::match_function::new_match_function;
+use super::metrics;
+use super::params::MatchParams;
+use super::service::{om, MatchMakingFunctionService};
+
+const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mmf_descriptor.bin"));
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MmfConfig {
+    pub address: SocketAddr,
+    pub om_query_address: String,
+    /// Defaults of every profile, overridden by the `mmf_params` profile extension.
+    pub default_params: MatchParams,
+    /// Players per match. When set, the team size is this divided by the team count.
+    pub num_matching_members: Option<usize>,
+    /// mTLS of the server Open Match calls.
+    pub tls: TlsConfig,
+    /// Plaintext address serving only the health service, for Kubernetes gRPC probes, which
+    /// can't speak TLS, when `tls` is set.
+    pub health_address: Option<SocketAddr>,
+    /// mTLS to Open Match.
+    pub om_tls: TlsConfig,
+    pub metrics_address: SocketAddr,
+}
+
+impl Default for MmfConfig {
+    fn default() -> Self {
+        MmfConfig {
+            address: ([0, 0, 0, 0], 50502).into(),
+            om_query_address: "om-query.open-match.svc.cluster.local:50503".to_string(),
+            default_params: MatchParams {
+                match_function: "basic".to_string(),
+                team_count: 1,
+                team_size: 2,
+                skill_window: 100.0,
+                skill_window_growth: 10.0,
+                skill_window_max: 1000.0,
+                max_latency: 150.0,
+            },
+            num_matching_members: None,
+            tls: TlsConfig::default(),
+            health_address: None,
+            om_tls: TlsConfig::default(),
+            metrics_address: ([0, 0, 0, 0], 9090).into(),
+        }
+    }
+}
+
+/// Environment variables overriding the config file.
+pub const ENV_KEYS: &[EnvKey] = &[
+    ("ADDRESS", "address"),
+    ("OM_QUERY_ADDRESS", "om_query_address"),
+    ("MATCH_FUNCTION_NAME", "default_params.match_function"), // basic or skill
+    ("NUM_MATCHING_MEMBERS", "num_matching_members"),
+    ("TEAM_COUNT", "default_params.team_count"),
+    ("TEAM_SIZE", "default_params.team_size"),
+    ("SKILL_WINDOW", "default_params.skill_window"),
+    ("SKILL_WINDOW_GROWTH", "default_params.skill_window_growth"),
+    ("SKILL_WINDOW_MAX", "default_params.skill_window_max"),
+    ("MAX_LATENCY", "default_params.max_latency"),
+    ("TLS_CERT_PATH", "tls.cert_path"),
+    ("TLS_KEY_PATH", "tls.key_path"),
+    ("TLS_CA_PATH", "tls.ca_path"),
+    ("HEALTH_ADDRESS", "health_address"),
+    ("OM_TLS_CERT_PATH", "om_tls.cert_path"),
+    ("OM_TLS_KEY_PATH", "om_tls.key_path"),
+    ("OM_TLS_CA_PATH", "om_tls.ca_path"),
+    ("OM_TLS_DOMAIN_NAME", "om_tls.domain_name"),
+    ("METRICS_ADDRESS", "metrics_address"),
+];
+
+/// Keys applied without a restart.
+pub const RELOADABLE_KEYS: &[&str] = &["default_params", "num_matching_members"];
+
+impl Validate for MmfConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.om_query_address.is_empty() {
+            return Err("om_query_address must not be empty".to_string());
+        }
+        if let Some(n) = self.num_matching_members {
+            let team_count = self.default_params.team_count.max(1);
+            if n % team_count != 0 {
+                return Err(format!(
+                    "num_matching_members must be a multiple of team_count: {} % {} != 0",
+                    n, team_count
+                ));
+            }
+        }
+        let params = self.params();
+        params.validate()?;
+        new_match_function(&params)?;
+        if self.health_address == Some(self.address) {
+            return Err(format!(
+                "health_address must differ from address: {}",
+                self.address
+            ));
+        }
+        self.tls.validate().map_err(|err| format!("tls: {}", err))?;
+        self.om_tls
+            .validate()
+            .map_err(|err| format!("om_tls: {}", err))?;
+        Ok(())
+    }
+}
+
+impl MmfConfig {
+    /// Loads the config from the config file, the environment and the command line.
+    pub fn load() -> Result<Self, settings::Error> {
+        settings::load(ENV_KEYS)
+    }
+
+    /// The default parameters with the team size given by `num_matching_members`.
+    pub fn params(&self) -> MatchParams {
+        let mut params = self.default_params.clone();
+        if let Some(n) = self.num_matching_members {
+            params.team_size = n / params.team_count.max(1);
+        }
+        params
+    }
+}
+
+/// Builds the match function server.
+pub struct ServerBuilder {
+    config: MmfConfig,
+    updates: Option<watch::Receiver<MmfConfig>>,
+    listener: Option<TcpListener>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
+
+impl ServerBuilder {
+    pub fn new(config: MmfConfig) -> Self {
+        ServerBuilder {
+            config,
+            updates: None,
+            listener: None,
+            shutdown: None,
+        }
+    }
+
+    /// Runs the match function with the parameters of the latest config sent on `updates`.
+    /// Runs in flight keep their parameters. The addresses are only read at startup.
+    pub fn config_updates(mut self, updates: watch::Receiver<MmfConfig>) -> Self {
+        self.updates = Some(updates);
+        self
+    }
+
+    /// Serves on `listener` instead of binding the configured address.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Stops accepting connections once `signal` resolves.
+    pub fn shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Validates the parameters, connects to Open Match and binds the listener. The returned
+    /// future runs the server. The server is reported serving while Open Match answers.
+    pub async fn build(
+        self,
+    ) -> Result<BoxFuture<'static, Result<(), tonic::transport::Error>>, Box<dyn std::error::Error>>
+    {
+        self.config.validate()?;
+        let params = match self.updates {
+            Some(updates) => settings::map(updates, MmfConfig::params),
+            None => watch::channel(self.config.params()).1,
+        };
+        let mmf = MatchMakingFunctionService::new(
+            params,
+            self.config.om_query_address,
+            &self.config.om_tls,
+        )
+        .await?;
+        let health = HealthReporter::new();
+        health.poll(
+            &[grpc_health::SERVER, "openmatch.MatchFunction"],
+            grpc_health::PROBE_INTERVAL,
+            mmf.health_check(),
+        );
+        let reflection = grpc_health::reflection::service(&[FILE_DESCRIPTOR_SET])?;
+        let listener = match self.listener {
+            Some(listener) => listener,
+            None => TcpListener::bind(self.config.address).await?,
+        };
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(tls) = grpc_tls::server_tls(&self.config.tls)? {
+            builder.tls_config(&tls);
+        }
+        let server = builder
+            .add_service(om::match_function_server::MatchFunctionServer::new(mmf))
+            .add_service(health.service())
+            .add_service(reflection)
+            .serve_with_incoming(incoming(listener, self.shutdown));
+        Ok(health.with_plaintext_health(server, self.config.health_address))
+    }
+}
+
+pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
+    info!("start server");
+    let config = MmfConfig::load()?;
+    let metrics_address = config.metrics_address;
+    tokio::spawn(async move {
+        if let Err(err) = telemetry::metrics::serve(metrics_address).await {
+            error!("metrics server error: {:?}", err);
+        }
+    });
+    let updates = settings::watch(
+        config.clone(),
+        ENV_KEYS,
+        RELOADABLE_KEYS,
+        settings::RELOAD_INTERVAL,
+    );
+    ServerBuilder::new(config)
+        .config_updates(updates)
+        .build()
+        .await?
+        .await?;
+    Ok(())
+}
@@ -1,38 +1,62 @@
-use std::collections::HashMap;
-use std::env;
+use std::collections::{HashMap, HashSet};
+use std::time::{Instant, SystemTime};
 
+use futures::future::BoxFuture;
 use futures::StreamExt;
-use log::{debug, error, info};
-use tokio::sync::mpsc;
+use grpc_tls::TlsConfig;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info_span};
+use tracing_futures::Instrument;
 use uuid::Uuid;
 
+use super::backfill::{backfill_extension, backfills, fill, BACKFILL_EXTENSION};
+use super::match_function::new_match_function;
+use super::metrics;
+use super::params::MatchParams;
+use super::regions::{group_by_region, region_extension, REGION_EXTENSION};
+use super::teams::{balance_teams, teams_extension, TEAMS_EXTENSION};
+
 pub mod om {
     tonic::include_proto!("openmatch");
 }
 
 pub struct MatchMakingFunctionService {
-    match_function_name: String,
-    num_matching_members: usize,
-    om_mml_client: om::query_service_client::QueryServiceClient<tonic::transport::channel::Channel>,
+    // the latest default parameters, read at the start of every run
+    default_params: watch::Receiver<MatchParams>,
+    om_mml_client: om::query_service_client::QueryServiceClient<
+        telemetry::Traced<tonic::transport::channel::Channel>,
+    >,
 }
 
 impl MatchMakingFunctionService {
-    async fn new(
-        match_function_name: String,
-        num_matching_members: usize,
+    pub async fn new(
+        default_params: watch::Receiver<MatchParams>,
         om_query_address: String,
-    ) -> Result<Self, tonic::transport::Error> {
-        let client = om::query_service_client::QueryServiceClient::connect(format!(
-            "http://{}",
-            om_query_address
-        ))
-        .await?;
+        om_tls: &TlsConfig,
+    ) -> Result<Self, grpc_tls::Error> {
+        let channel = grpc_tls::connect(&om_query_address, om_tls).await?;
+        let client =
+            om::query_service_client::QueryServiceClient::new(telemetry::Traced::new(channel));
         Ok(MatchMakingFunctionService {
-            match_function_name,
-            num_matching_members,
+            default_params,
             om_mml_client: client,
         })
     }
+
+    /// Checks whether Open Match answers. The query has no pool, so it is rejected without
+    /// reading any ticket.
+    pub fn health_check(&self) -> impl FnMut() -> BoxFuture<'static, bool> + Send + 'static {
+        let client = self.om_mml_client.clone();
+        move || {
+            let mut client = client.clone();
+            Box::pin(async move {
+                let res = client
+                    .query_tickets(om::QueryTicketsRequest { pool: None })
+                    .await;
+                grpc_health::is_reachable(&res)
+            })
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -43,18 +67,35 @@ impl om::match_function_server::MatchFunction for MatchMakingFunctionService {
         request: tonic::Request<om::RunRequest>,
     ) -> Result<tonic::Response<Self::RunStream>, tonic::Status> {
         let (mut tx, rx) = mpsc::channel(1);
+        let traceparent = telemetry::traceparent(request.metadata()).to_string();
         let profile = request.into_inner().profile.ok_or(tonic::Status::new(
             tonic::Code::InvalidArgument,
             "profile is not specified",
         ))?;
-        debug!("requested. profile: {:?}", profile);
+        let span = info_span!(
+            "run",
+            traceparent = %traceparent,
+            match_profile = %profile.name
+        );
+        span.in_scope(|| debug!("requested. profile: {:?}", profile));
+        let params = self
+            .default_params
+            .borrow()
+            .with_profile(&profile)
+            .map_err(|err| tonic::Status::new(tonic::Code::InvalidArgument, err))?;
+        let match_function = new_match_function(&params)
+            .map_err(|err| tonic::Status::new(tonic::Code::InvalidArgument, err))?;
+        let backfills = backfills(&profile)
+            .map_err(|err| tonic::Status::new(tonic::Code::InvalidArgument, err))?;
         let mut om_mml_client = self.om_mml_client.clone();
-        let num_matching_members = self.num_matching_members;
-        let match_function_name = self.match_function_name.clone();
 
-        tokio::spawn(async move {
-            debug!("requested. profile: {:?}", profile);
-            for pool in profile.pools {
+        let make_proposals = async move {
+            let started = Instant::now();
+            let mut proposals = 0;
+            // A ticket can be in several pools of the profile, but only in one match.
+            let mut all_tickets = Vec::new();
+            let mut seen = HashSet::new();
+            for pool in profile.pools.iter() {
                 let req = om::QueryTicketsRequest {
                     pool: Some(pool.clone()),
                 };
@@ -72,11 +113,18 @@ impl om::match_function_server::MatchFunction for MatchMakingFunctionService {
                     }
                 };
 
-                let mut all_tickets = Vec::new();
                 futures::pin_mut!(stream);
+                let mut pool_tickets = 0;
                 while let Some(res) = stream.next().await {
                     match res {
-                        Ok(mut res) => all_tickets.append(&mut res.tickets),
+                        Ok(res) => {
+                            pool_tickets += res.tickets.len();
+                            all_tickets.extend(
+                                res.tickets
+                                    .into_iter()
+                                    .filter(|ticket| seen.insert(ticket.id.clone())),
+                            )
+                        }
                         Err(err) => {
                             if let Err(err) = tx
                                 .send(Err(tonic::Status::new(
@@ -91,58 +139,117 @@ impl om::match_function_server::MatchFunction for MatchMakingFunctionService {
                         }
                     }
                 }
-                debug!("all tickets: {:?}", all_tickets);
-                while all_tickets.len() >= num_matching_members {
-                    let tickets = all_tickets[0..num_matching_members].to_vec();
-                    all_tickets = all_tickets[num_matching_members..].to_vec();
-                    let result = om::RunResponse {
-                        proposal: Some(om::Match {
-                            match_id: Uuid::new_v4().to_string(),
-                            match_profile: profile.name.clone(),
-                            match_function: match_function_name.clone(),
-                            tickets: tickets,
-                            extensions: HashMap::new(),
-                        }),
-                    };
-                    debug!("result: {:?}", result);
-                    if let Err(err) = tx.send(Ok(result)).await.map_err(|err| {
-                        tonic::Status::new(tonic::Code::Unavailable, err.to_string())
-                    }) {
-                        error!("{:?}", err);
+                metrics::record_pool(&profile.name, &pool.name, pool_tickets);
+            }
+            debug!("all tickets: {:?}", all_tickets);
+            // fill the open seats of running matches before making new ones
+            let now = SystemTime::now();
+            for backfill in backfills {
+                let tickets = fill(&mut all_tickets, &backfill, &params, now);
+                if tickets.is_empty() {
+                    continue;
+                }
+                let mut extensions = HashMap::new();
+                match backfill_extension(&backfill.match_id) {
+                    Ok(backfill) => {
+                        extensions.insert(BACKFILL_EXTENSION.to_string(), backfill);
+                    }
+                    Err(err) => {
+                        error!("failed to encode backfill: {:?}", err);
+                        continue;
                     }
                 }
+                let proposal = om::Match {
+                    match_id: backfill.match_id,
+                    match_profile: profile.name.clone(),
+                    match_function: params.match_function.clone(),
+                    tickets: tickets,
+                    extensions: extensions,
+                };
+                send_proposal(&mut tx, proposal).await;
+                proposals += 1;
+            }
+
+            // players are only matched with others who play best in the same region
+            let mut matches = Vec::new();
+            let mut leftover_tickets = 0;
+            for (region, mut tickets) in group_by_region(
+                all_tickets,
+                params.max_latency,
+                params.num_matching_members(),
+            ) {
+                for tickets in match_function.make_matches(&mut tickets, now) {
+                    matches.push((region.clone(), tickets));
+                }
+                debug!("leftover tickets in {:?}: {}", region, tickets.len());
+                leftover_tickets += tickets.len();
             }
-        });
+            for (region, tickets) in matches {
+                let teams = match balance_teams(&tickets, params.team_count, params.team_size) {
+                    Some(teams) => teams,
+                    None => {
+                        debug!("cannot split tickets into teams: {:?}", tickets);
+                        leftover_tickets += tickets.len();
+                        continue;
+                    }
+                };
+                let mut extensions = HashMap::new();
+                match teams_extension(&tickets, &teams) {
+                    Ok(teams) => {
+                        extensions.insert(TEAMS_EXTENSION.to_string(), teams);
+                    }
+                    Err(err) => {
+                        error!("failed to encode teams: {:?}", err);
+                        continue;
+                    }
+                }
+                if !region.is_empty() {
+                    match region_extension(&region) {
+                        Ok(region) => {
+                            extensions.insert(REGION_EXTENSION.to_string(), region);
+                        }
+                        Err(err) => {
+                            error!("failed to encode region: {:?}", err);
+                            continue;
+                        }
+                    }
+                }
+                let proposal = om::Match {
+                    match_id: Uuid::new_v4().to_string(),
+                    match_profile: profile.name.clone(),
+                    match_function: params.match_function.clone(),
+                    tickets: tickets,
+                    extensions: extensions,
+                };
+                send_proposal(&mut tx, proposal).await;
+                proposals += 1;
+            }
+            metrics::record_run(
+                &profile.name,
+                proposals,
+                leftover_tickets,
+                started.elapsed(),
+            );
+        };
+        tokio::spawn(make_proposals.instrument(span));
         Ok(tonic::Response::new(rx))
     }
 }
 
-pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
-    info!("start server");
-
-    let match_function_name = env::var("MATCH_FUNCTION_NAME").unwrap_or("basic".to_string());
-    let num_matching_members: usize = env::var("NUM_MATCHING_MEMBERS")
-        .unwrap_or("2".to_string())
-        .parse()
-        .expect("cannot parse NUM_MATCHING_MEMBERS");
-    let om_query_address = env::var("OM_QUERY_ADDRESS")
-        .unwrap_or("om-query.open-match.svc.cluster.local:50503".to_string());
-    let address = env::var("ADDRESS")
-        .unwrap_or("0.0.0.0:50502".to_string())
-        .parse()
-        .expect("cannot parse ADDRESS");
-
-    let mmf = MatchMakingFunctionService::new(
-        match_function_name,
-        num_matching_members,
-        om_query_address,
-    )
-    .await?;
-
-    let svc = om::match_function_server::MatchFunctionServer::new(mmf);
-    tonic::transport::Server::builder()
-        .add_service(svc)
-        .serve(address)
-        .await?;
-    Ok(())
+async fn send_proposal(
+    tx: &mut mpsc::Sender<Result<om::RunResponse, tonic::Status>>,
+    proposal: om::Match,
+) {
+    let ticket_ids: Vec<&str> = proposal.tickets.iter().map(|t| t.id.as_str()).collect();
+    debug!(match_id = %proposal.match_id, ticket_ids = ?ticket_ids, "proposal");
+    let result = om::RunResponse {
+        proposal: Some(proposal),
+    };
+    if let Err(err) = tx
+        .send(Ok(result))
+        .await
+        .map_err(|err| tonic::Status::new(tonic::Code::Unavailable, err.to_string()))
+    {
+        error!("{:?}", err);
+    }
 }
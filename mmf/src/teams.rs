@@ -0,0 +1,132 @@
+use super::match_function::{party_size, skill_rating};
+use super::service::om;
+
+/// Match extension holding the players of each team.
+pub const TEAMS_EXTENSION: &str = "teams";
+
+struct Team {
+    tickets: Vec<usize>,
+    players: usize,
+    total_skill: f64,
+}
+
+fn ticket_skill(ticket: &om::Ticket) -> f64 {
+    // a party ticket is rated by the mean of its members
+    skill_rating(ticket) * party_size(ticket) as f64
+}
+
+fn skill_gap(teams: &[Team]) -> f64 {
+    let min = teams
+        .iter()
+        .map(|t| t.total_skill)
+        .fold(f64::INFINITY, f64::min);
+    let max = teams
+        .iter()
+        .map(|t| t.total_skill)
+        .fold(f64::NEG_INFINITY, f64::max);
+    max - min
+}
+
+/// Splits the tickets of a match into `team_count` teams of `team_size` players each,
+/// keeping parties together and the difference in total skill between teams small.
+///
+/// Returns the indices of the tickets in each team, or `None` if the parties can't be
+/// packed into teams of that size.
+pub fn balance_teams(
+    tickets: &[om::Ticket],
+    team_count: usize,
+    team_size: usize,
+) -> Option<Vec<Vec<usize>>> {
+    let mut order: Vec<usize> = (0..tickets.len()).collect();
+    // place big parties first, then the strongest players
+    order.sort_by(|&a, &b| {
+        party_size(&tickets[b])
+            .cmp(&party_size(&tickets[a]))
+            .then_with(|| {
+                ticket_skill(&tickets[b])
+                    .partial_cmp(&ticket_skill(&tickets[a]))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    });
+
+    let mut teams: Vec<Team> = (0..team_count)
+        .map(|_| Team {
+            tickets: Vec::new(),
+            players: 0,
+            total_skill: 0.0,
+        })
+        .collect();
+    for i in order {
+        let size = party_size(&tickets[i]);
+        let team = teams
+            .iter_mut()
+            .filter(|t| t.players + size <= team_size)
+            .min_by(|a, b| {
+                a.total_skill
+                    .partial_cmp(&b.total_skill)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })?;
+        team.tickets.push(i);
+        team.players += size;
+        team.total_skill += ticket_skill(&tickets[i]);
+    }
+    if teams.iter().any(|t| t.players != team_size) {
+        return None;
+    }
+
+    // Swap tickets of the same party size between teams while it narrows the gap.
+    let mut improved = true;
+    while improved {
+        improved = false;
+        let gap = skill_gap(&teams);
+        'search: for a in 0..teams.len() {
+            for b in (a + 1)..teams.len() {
+                for ia in 0..teams[a].tickets.len() {
+                    for ib in 0..teams[b].tickets.len() {
+                        let ta = &tickets[teams[a].tickets[ia]];
+                        let tb = &tickets[teams[b].tickets[ib]];
+                        if party_size(ta) != party_size(tb) {
+                            continue;
+                        }
+                        let delta = ticket_skill(tb) - ticket_skill(ta);
+                        teams[a].total_skill += delta;
+                        teams[b].total_skill -= delta;
+                        if skill_gap(&teams) + f64::EPSILON < gap {
+                            let t = teams[a].tickets[ia];
+                            teams[a].tickets[ia] = teams[b].tickets[ib];
+                            teams[b].tickets[ib] = t;
+                            improved = true;
+                            break 'search;
+                        }
+                        teams[a].total_skill -= delta;
+                        teams[b].total_skill += delta;
+                    }
+                }
+            }
+        }
+    }
+    Some(teams.into_iter().map(|t| t.tickets).collect())
+}
+
+/// Encodes the team layout as a `ListValue` with one `ListValue` of player ids per team.
+pub fn teams_extension(
+    tickets: &[om::Ticket],
+    teams: &[Vec<usize>],
+) -> Result<prost_types::Any, prost::EncodeError> {
+    om_ext::list_any(
+        teams
+            .iter()
+            .map(|team| prost_types::Value {
+                kind: Some(prost_types::value::Kind::ListValue(
+                    prost_types::ListValue {
+                        values: team
+                            .iter()
+                            .flat_map(|&i| om_ext::player_ids(&tickets[i].extensions))
+                            .map(om_ext::string_value)
+                            .collect(),
+                    },
+                )),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+
+use prost::Message;
+
+use mmf::backfill::{backfills, fill, Backfill};
+use mmf::params::MatchParams;
+use mmf::service::om;
+
+fn params(match_function: &str) -> MatchParams {
+    MatchParams {
+        match_function: match_function.to_string(),
+        team_count: 2,
+        team_size: 2,
+        skill_window: 100.0,
+        skill_window_growth: 10.0,
+        skill_window_max: 1000.0,
+        max_latency: 150.0,
+    }
+}
+
+fn ticket(
+    id: &str,
+    skill_rating: f64,
+    party_size: usize,
+    latencies: &[(&str, f64)],
+    waited: Duration,
+    now: SystemTime,
+) -> om::Ticket {
+    let mut double_args: HashMap<String, f64> = latencies
+        .iter()
+        .map(|(region, latency)| (format!("latency.{}", region), *latency))
+        .collect();
+    double_args.insert("skill_rating".to_string(), skill_rating);
+    double_args.insert("party_size".to_string(), party_size as f64);
+    om::Ticket {
+        id: id.to_string(),
+        search_fields: Some(om::SearchFields {
+            double_args,
+            ..Default::default()
+        }),
+        create_time: Some((now - waited).into()),
+        ..Default::default()
+    }
+}
+
+fn backfill(open_slots: usize, region: &str, skill_rating: Option<f64>) -> Backfill {
+    Backfill {
+        match_id: "match-1".to_string(),
+        open_slots,
+        region: region.to_string(),
+        skill_rating,
+    }
+}
+
+fn ids(tickets: &[om::Ticket]) -> Vec<&str> {
+    tickets.iter().map(|t| t.id.as_str()).collect()
+}
+
+#[test]
+fn backfills_are_decoded_from_the_profile() {
+    let backfill = |fields: Vec<(&str, prost_types::value::Kind)>| prost_types::Value {
+        kind: Some(prost_types::value::Kind::StructValue(prost_types::Struct {
+            fields: fields
+                .into_iter()
+                .map(|(key, kind)| (key.to_string(), prost_types::Value { kind: Some(kind) }))
+                .collect::<BTreeMap<_, _>>(),
+        })),
+    };
+    let list = prost_types::ListValue {
+        values: vec![
+            backfill(vec![
+                (
+                    "match_id",
+                    prost_types::value::Kind::StringValue("match-1".to_string()),
+                ),
+                ("open_slots", prost_types::value::Kind::NumberValue(2.0)),
+                (
+                    "region",
+                    prost_types::value::Kind::StringValue("asia".to_string()),
+                ),
+                (
+                    "skill_rating",
+                    prost_types::value::Kind::NumberValue(1500.0),
+                ),
+            ]),
+            backfill(vec![
+                (
+                    "match_id",
+                    prost_types::value::Kind::StringValue("match-2".to_string()),
+                ),
+                ("open_slots", prost_types::value::Kind::NumberValue(1.0)),
+            ]),
+        ],
+    };
+    let mut buf = Vec::new();
+    list.encode(&mut buf).unwrap();
+    let mut extensions = HashMap::new();
+    extensions.insert(
+        "backfills".to_string(),
+        prost_types::Any {
+            type_url: "type.googleapis.com/google.protobuf.ListValue".to_string(),
+            value: buf,
+        },
+    );
+    let profile = om::MatchProfile {
+        extensions,
+        ..Default::default()
+    };
+
+    let decoded = backfills(&profile).unwrap();
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0].open_slots, 2);
+    assert_eq!(decoded[0].region, "asia");
+    assert_eq!(decoded[0].skill_rating, Some(1500.0));
+    assert_eq!(decoded[1].region, "");
+    assert_eq!(decoded[1].skill_rating, None);
+    assert!(backfills(&om::MatchProfile::default()).unwrap().is_empty());
+}
+
+#[test]
+fn the_oldest_tickets_that_fit_fill_the_seats() {
+    let now = SystemTime::now();
+    let mut tickets = vec![
+        ticket("new", 0.0, 1, &[], Duration::from_secs(1), now),
+        ticket("party", 0.0, 2, &[], Duration::from_secs(30), now),
+        ticket("old", 0.0, 1, &[], Duration::from_secs(20), now),
+    ];
+    let filled = fill(&mut tickets, &backfill(1, "", None), &params("basic"), now);
+
+    // the party doesn't fit the single seat
+    assert_eq!(ids(&filled), vec!["old"]);
+    assert_eq!(ids(&tickets), vec!["party", "new"]);
+}
+
+#[test]
+fn only_players_of_the_region_of_the_match_fill_it() {
+    let now = SystemTime::now();
+    let mut tickets = vec![
+        ticket(
+            "us",
+            0.0,
+            1,
+            &[("us", 20.0), ("asia", 140.0)],
+            Duration::from_secs(30),
+            now,
+        ),
+        ticket(
+            "asia",
+            0.0,
+            1,
+            &[("asia", 30.0)],
+            Duration::from_secs(20),
+            now,
+        ),
+        ticket("anywhere", 0.0, 1, &[], Duration::from_secs(10), now),
+        ticket(
+            "too-far",
+            0.0,
+            1,
+            &[("asia", 300.0)],
+            Duration::from_secs(5),
+            now,
+        ),
+    ];
+    let filled = fill(
+        &mut tickets,
+        &backfill(4, "asia", None),
+        &params("basic"),
+        now,
+    );
+
+    assert_eq!(ids(&filled), vec!["asia", "anywhere"]);
+    assert_eq!(ids(&tickets), vec!["us", "too-far"]);
+}
+
+#[test]
+fn skill_matches_are_filled_within_the_skill_window() {
+    let now = SystemTime::now();
+    let tickets = vec![
+        ticket("close", 1550.0, 1, &[], Duration::from_secs(0), now),
+        ticket("far", 1800.0, 1, &[], Duration::from_secs(0), now),
+        // the window of a ticket that waited 20 seconds is 300 wide
+        ticket(
+            "far-but-waited",
+            1750.0,
+            1,
+            &[],
+            Duration::from_secs(20),
+            now,
+        ),
+    ];
+
+    let mut skill_tickets = tickets.clone();
+    let filled = fill(
+        &mut skill_tickets,
+        &backfill(3, "", Some(1500.0)),
+        &params("skill"),
+        now,
+    );
+    assert_eq!(ids(&filled), vec!["far-but-waited", "close"]);
+    assert_eq!(ids(&skill_tickets), vec!["far"]);
+
+    // the basic match function doesn't match by skill
+    let mut basic_tickets = tickets;
+    let filled = fill(
+        &mut basic_tickets,
+        &backfill(3, "", Some(1500.0)),
+        &params("basic"),
+        now,
+    );
+    assert_eq!(filled.len(), 3);
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use mmf::match_function::{BasicMatchFunction, MatchFunction, SkillBasedMatchFunction};
+use mmf::params::MatchParams;
+use mmf::service::om;
+
+fn ticket(id: &str, skill_rating: f64, waited: Duration, now: SystemTime) -> om::Ticket {
+    let mut double_args = HashMap::new();
+    double_args.insert("skill_rating".to_string(), skill_rating);
+    om::Ticket {
+        id: id.to_string(),
+        search_fields: Some(om::SearchFields {
+            double_args,
+            ..Default::default()
+        }),
+        create_time: Some((now - waited).into()),
+        ..Default::default()
+    }
+}
+
+fn ids(tickets: &[om::Ticket]) -> Vec<&str> {
+    let mut ids: Vec<&str> = tickets.iter().map(|t| t.id.as_str()).collect();
+    ids.sort();
+    ids
+}
+
+fn skill_params() -> MatchParams {
+    MatchParams {
+        match_function: "skill".to_string(),
+        team_count: 2,
+        team_size: 1,
+        skill_window: 10.0,
+        skill_window_growth: 10.0,
+        skill_window_max: 100.0,
+        max_latency: 150.0,
+    }
+}
+
+fn skill_function() -> SkillBasedMatchFunction {
+    SkillBasedMatchFunction {
+        params: skill_params(),
+    }
+}
+
+#[test]
+fn the_skill_window_widens_while_tickets_wait() {
+    let now = SystemTime::now();
+    let mf = skill_function();
+
+    let mut tickets = vec![
+        ticket("a", 1000.0, Duration::from_secs(0), now),
+        ticket("b", 1050.0, Duration::from_secs(0), now),
+    ];
+    assert!(mf.make_matches(&mut tickets, now).is_empty());
+    assert_eq!(tickets.len(), 2);
+
+    // 10 + 10 * 5 covers the gap of 50
+    let mut tickets = vec![
+        ticket("a", 1000.0, Duration::from_secs(5), now),
+        ticket("b", 1050.0, Duration::from_secs(0), now),
+    ];
+    let matches = mf.make_matches(&mut tickets, now);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(ids(&matches[0]), vec!["a", "b"]);
+    assert!(tickets.is_empty());
+
+    // the window stops at max_window
+    let mut tickets = vec![
+        ticket("a", 1000.0, Duration::from_secs(3600), now),
+        ticket("b", 1150.0, Duration::from_secs(0), now),
+    ];
+    assert!(mf.make_matches(&mut tickets, now).is_empty());
+}
+
+#[test]
+fn the_closest_ratings_are_matched_and_the_rest_left_over() {
+    let now = SystemTime::now();
+    let mf = skill_function();
+    let mut tickets = vec![
+        ticket("new-close", 1005.0, Duration::from_secs(0), now),
+        ticket("far", 2000.0, Duration::from_secs(0), now),
+        ticket("oldest", 1000.0, Duration::from_secs(1), now),
+        ticket("new-closer", 1002.0, Duration::from_secs(0), now),
+    ];
+    let matches = mf.make_matches(&mut tickets, now);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(ids(&matches[0]), vec!["new-closer", "oldest"]);
+    assert_eq!(ids(&tickets), vec!["far", "new-close"]);
+}
+
+#[test]
+fn parties_are_matched_whole() {
+    let now = SystemTime::now();
+    let mf = SkillBasedMatchFunction {
+        params: MatchParams {
+            team_size: 2,
+            ..skill_params()
+        },
+    };
+    let mut party = ticket("party", 1000.0, Duration::from_secs(0), now);
+    party
+        .search_fields
+        .as_mut()
+        .unwrap()
+        .double_args
+        .insert("party_size".to_string(), 3.0);
+    let mut tickets = vec![
+        party,
+        ticket("solo-1", 1001.0, Duration::from_secs(0), now),
+        ticket("solo-2", 1002.0, Duration::from_secs(0), now),
+    ];
+    let matches = mf.make_matches(&mut tickets, now);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].len(), 2);
+    assert_eq!(tickets.len(), 1);
+}
+
+#[test]
+fn basic_matches_take_tickets_in_order() {
+    let now = SystemTime::now();
+    let mf = BasicMatchFunction {
+        num_matching_members: 2,
+    };
+    let mut tickets = vec![
+        ticket("a", 0.0, Duration::from_secs(0), now),
+        ticket("b", 5000.0, Duration::from_secs(0), now),
+        ticket("c", 0.0, Duration::from_secs(0), now),
+    ];
+    let matches = mf.make_matches(&mut tickets, now);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(ids(&matches[0]), vec!["a", "b"]);
+    assert_eq!(ids(&tickets), vec!["c"]);
+}
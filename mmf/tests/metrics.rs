@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use mmf::metrics;
+
+/// Returns the value of the sample of `name` with all of `labels`, or 0 if there is none.
+fn sample(name: &str, labels: &[&str]) -> f64 {
+    let metrics = String::from_utf8(telemetry::metrics::gather().unwrap()).unwrap();
+    metrics
+        .lines()
+        .filter(|line| line.starts_with(name))
+        .filter(|line| labels.iter().all(|label| line.contains(label)))
+        .filter_map(|line| line.rsplit(' ').next())
+        .map(|value| value.parse().unwrap())
+        .next()
+        .unwrap_or(0.0)
+}
+
+#[test]
+fn pools_keep_the_tickets_of_the_latest_run() {
+    metrics::record_pool("ranked", "asia", 10);
+    metrics::record_pool("ranked", "us", 4);
+    metrics::record_pool("ranked", "asia", 6);
+
+    let pool = |pool| {
+        sample(
+            "mmf_pool_tickets",
+            &["profile=\"ranked\"", &format!("pool=\"{}\"", pool)],
+        )
+    };
+    assert_eq!(pool("asia"), 6.0);
+    assert_eq!(pool("us"), 4.0);
+}
+
+#[test]
+fn runs_record_their_proposals_and_leftover_tickets() {
+    metrics::record_run("casual", 3, 5, Duration::from_millis(20));
+    metrics::record_run("casual", 2, 1, Duration::from_millis(10));
+
+    let profile = ["profile=\"casual\""];
+    assert_eq!(sample("mmf_leftover_tickets", &profile), 1.0);
+    assert_eq!(sample("mmf_run_proposals_count", &profile), 2.0);
+    assert_eq!(sample("mmf_run_proposals_sum", &profile), 5.0);
+    assert_eq!(sample("mmf_run_duration_seconds_count", &profile), 2.0);
+}
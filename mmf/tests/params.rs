@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, HashMap};
+
+use prost::Message;
+
+use mmf::params::MatchParams;
+use mmf::service::om;
+
+fn defaults() -> MatchParams {
+    MatchParams {
+        match_function: "basic".to_string(),
+        team_count: 1,
+        team_size: 2,
+        skill_window: 100.0,
+        skill_window_growth: 10.0,
+        skill_window_max: 1000.0,
+        max_latency: 150.0,
+    }
+}
+
+fn profile(fields: Vec<(&str, prost_types::value::Kind)>) -> om::MatchProfile {
+    let fields: BTreeMap<String, prost_types::Value> = fields
+        .into_iter()
+        .map(|(key, kind)| (key.to_string(), prost_types::Value { kind: Some(kind) }))
+        .collect();
+    let mut buf = Vec::new();
+    prost_types::Struct { fields }.encode(&mut buf).unwrap();
+    let mut extensions = HashMap::new();
+    extensions.insert(
+        "mmf_params".to_string(),
+        prost_types::Any {
+            type_url: "type.googleapis.com/google.protobuf.Struct".to_string(),
+            value: buf,
+        },
+    );
+    om::MatchProfile {
+        name: "ranked".to_string(),
+        extensions,
+        ..Default::default()
+    }
+}
+
+fn number(n: f64) -> prost_types::value::Kind {
+    prost_types::value::Kind::NumberValue(n)
+}
+
+#[test]
+fn profiles_without_params_use_the_defaults() {
+    let params = defaults()
+        .with_profile(&om::MatchProfile::default())
+        .unwrap();
+    assert_eq!(params.match_function, "basic");
+    assert_eq!(params.num_matching_members(), 2);
+
+    let params = defaults().with_profile(&profile(vec![])).unwrap();
+    assert_eq!(params.team_size, 2);
+}
+
+#[test]
+fn profiles_override_only_the_params_they_set() {
+    let params = defaults()
+        .with_profile(&profile(vec![
+            (
+                "match_function",
+                prost_types::value::Kind::StringValue("skill".to_string()),
+            ),
+            ("team_count", number(2.0)),
+            ("team_size", number(3.0)),
+            ("skill_window", number(50.0)),
+        ]))
+        .unwrap();
+    assert_eq!(params.match_function, "skill");
+    assert_eq!(params.num_matching_members(), 6);
+    assert_eq!(params.skill_window, 50.0);
+    assert_eq!(params.skill_window_growth, 10.0);
+    assert_eq!(params.skill_window_max, 1000.0);
+    assert_eq!(params.max_latency, 150.0);
+}
+
+#[test]
+fn invalid_params_are_refused() {
+    for fields in vec![
+        // unknown key
+        vec![("skill_windw", number(50.0))],
+        // wrong type
+        vec![(
+            "team_size",
+            prost_types::value::Kind::StringValue("3".to_string()),
+        )],
+        // invalid once applied
+        vec![("team_count", number(0.0))],
+        vec![("skill_window", number(2000.0))],
+        vec![("max_latency", number(-1.0))],
+    ] {
+        let description = format!("{:?}", fields);
+        assert!(
+            defaults().with_profile(&profile(fields)).is_err(),
+            "{} was accepted",
+            description
+        );
+    }
+
+    let mut undecodable = profile(vec![]);
+    undecodable.extensions.get_mut("mmf_params").unwrap().value = vec![0xff, 0xff];
+    assert!(defaults().with_profile(&undecodable).is_err());
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use mmf::regions::{best_region, group_by_region};
+use mmf::service::om;
+
+fn ticket(id: &str, latencies: &[(&str, f64)], region: Option<&str>) -> om::Ticket {
+    let double_args: HashMap<String, f64> = latencies
+        .iter()
+        .map(|(region, latency)| (format!("latency.{}", region), *latency))
+        .collect();
+    let string_args: HashMap<String, String> = region
+        .into_iter()
+        .map(|region| ("region".to_string(), region.to_string()))
+        .collect();
+    om::Ticket {
+        id: id.to_string(),
+        search_fields: Some(om::SearchFields {
+            double_args,
+            string_args,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn groups(tickets: Vec<om::Ticket>, match_size: usize) -> Vec<(String, Vec<String>)> {
+    group_by_region(tickets, 100.0, match_size)
+        .into_iter()
+        .map(|(region, tickets)| (region, tickets.into_iter().map(|t| t.id).collect()))
+        .collect()
+}
+
+#[test]
+fn the_region_with_the_lowest_acceptable_latency_is_best() {
+    let near = ticket("near", &[("asia", 30.0), ("us", 120.0)], None);
+    assert_eq!(best_region(&near, 100.0), Some("asia".to_string()));
+    let far = ticket("far", &[("asia", 130.0), ("us", 120.0)], Some("us"));
+    assert_eq!(best_region(&far, 100.0), None);
+    let asked = ticket("asked", &[], Some("eu"));
+    assert_eq!(best_region(&asked, 100.0), Some("eu".to_string()));
+    let anywhere = ticket("anywhere", &[], None);
+    assert_eq!(best_region(&anywhere, 100.0), Some(String::new()));
+}
+
+#[test]
+fn tickets_of_any_region_complete_the_regional_groups() {
+    let tickets = vec![
+        ticket("asia-1", &[("asia", 30.0)], None),
+        ticket("asia-2", &[("asia", 40.0)], None),
+        ticket("us-1", &[], Some("us")),
+        ticket("any-1", &[], None),
+        ticket("any-2", &[], None),
+        ticket("any-3", &[], None),
+        ticket("too-far", &[("asia", 300.0)], None),
+    ];
+    // asia is two players short of a match of four, so it is filled first
+    assert_eq!(
+        groups(tickets, 4),
+        vec![
+            (
+                "asia".to_string(),
+                vec!["asia-1", "asia-2", "any-1", "any-2"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            ),
+            (
+                "us".to_string(),
+                vec!["us-1", "any-3"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn tickets_of_any_region_are_grouped_when_nobody_has_a_region() {
+    let tickets = vec![ticket("any-1", &[], None), ticket("any-2", &[], None)];
+    assert_eq!(
+        groups(tickets, 2),
+        vec![(
+            String::new(),
+            vec!["any-1".to_string(), "any-2".to_string()]
+        )]
+    );
+    assert!(groups(vec![ticket("too-far", &[("asia", 300.0)], None)], 2).is_empty());
+}
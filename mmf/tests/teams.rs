@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use mmf::service::om;
+use mmf::teams::balance_teams;
+
+fn ticket(id: &str, skill_rating: f64, party_size: usize) -> om::Ticket {
+    let mut double_args = HashMap::new();
+    double_args.insert("skill_rating".to_string(), skill_rating);
+    double_args.insert("party_size".to_string(), party_size as f64);
+    om::Ticket {
+        id: id.to_string(),
+        search_fields: Some(om::SearchFields {
+            double_args,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn team_ids<'a>(tickets: &'a [om::Ticket], teams: &[Vec<usize>]) -> Vec<Vec<&'a str>> {
+    let mut teams: Vec<Vec<&str>> = teams
+        .iter()
+        .map(|team| {
+            let mut ids: Vec<&str> = team.iter().map(|&i| tickets[i].id.as_str()).collect();
+            ids.sort();
+            ids
+        })
+        .collect();
+    teams.sort();
+    teams
+}
+
+#[test]
+fn uneven_parties_are_kept_together_and_balanced() {
+    let tickets = vec![
+        ticket("strong-pair", 50.0, 2),
+        ticket("weak-pair", 10.0, 2),
+        ticket("strong-solo", 50.0, 1),
+        ticket("weak-solo", 10.0, 1),
+    ];
+    let teams = balance_teams(&tickets, 2, 3).unwrap();
+
+    // the pairs can't share a team, and the strong pair gets the weak solo player
+    assert_eq!(
+        team_ids(&tickets, &teams),
+        vec![
+            vec!["strong-pair", "weak-solo"],
+            vec!["strong-solo", "weak-pair"],
+        ]
+    );
+}
+
+#[test]
+fn players_are_spread_by_skill() {
+    let tickets = vec![
+        ticket("a", 40.0, 1),
+        ticket("b", 30.0, 1),
+        ticket("c", 20.0, 1),
+        ticket("d", 10.0, 1),
+    ];
+    let teams = balance_teams(&tickets, 2, 2).unwrap();
+
+    assert_eq!(
+        team_ids(&tickets, &teams),
+        vec![vec!["a", "d"], vec!["b", "c"]]
+    );
+}
+
+#[test]
+fn parties_that_dont_fit_the_teams_are_refused() {
+    // a party larger than a team
+    let tickets = vec![ticket("trio", 10.0, 3), ticket("solo", 10.0, 1)];
+    assert_eq!(balance_teams(&tickets, 2, 2), None);
+
+    // enough players, but pairs can't fill teams of three
+    let tickets = vec![
+        ticket("pair-1", 10.0, 2),
+        ticket("pair-2", 10.0, 2),
+        ticket("pair-3", 10.0, 2),
+    ];
+    assert_eq!(balance_teams(&tickets, 2, 3), None);
+}
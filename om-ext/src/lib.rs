@@ -0,0 +1,71 @@
+//! Extensions the services put on Open Match tickets, profiles and matches.
+//!
+//! Every service generates its own Open Match types, so these work on the `extensions` maps
+//! and the `Any` values in them.
+
+use std::collections::HashMap;
+
+use prost::Message;
+use prost_types::value::Kind;
+use prost_types::{Any, ListValue, Value};
+
+/// Ticket extension with the id of the player who asked for the match.
+pub const PLAYER_ID_EXTENSION: &str = "player_id";
+/// Ticket extension with the ids of the members of a party ticket.
+pub const PLAYER_IDS_EXTENSION: &str = "player_ids";
+
+pub fn string_value(s: String) -> Value {
+    Value {
+        kind: Some(Kind::StringValue(s)),
+    }
+}
+
+/// Encodes `value` as a `StringValue`.
+pub fn string_any(value: &str) -> Result<Any, prost::EncodeError> {
+    let mut buf = Vec::new();
+    value.to_string().encode(&mut buf)?;
+    Ok(Any {
+        type_url: "type.googleapis.com/google.protobuf.StringValue".to_string(),
+        value: buf,
+    })
+}
+
+/// Encodes `values` as a `ListValue`.
+pub fn list_any(values: Vec<Value>) -> Result<Any, prost::EncodeError> {
+    let mut buf = Vec::new();
+    ListValue { values }.encode(&mut buf)?;
+    Ok(Any {
+        type_url: "type.googleapis.com/google.protobuf.ListValue".to_string(),
+        value: buf,
+    })
+}
+
+/// Encodes `values` as a `ListValue` of strings.
+pub fn string_list_any(values: &[String]) -> Result<Any, prost::EncodeError> {
+    list_any(values.iter().cloned().map(string_value).collect())
+}
+
+/// The strings of a `ListValue`. Other values are skipped.
+pub fn strings(list: ListValue) -> Vec<String> {
+    list.values
+        .into_iter()
+        .filter_map(|v| match v.kind {
+            Some(Kind::StringValue(s)) => Some(s),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the ids of every player a ticket with `extensions` stands for.
+pub fn player_ids(extensions: &HashMap<String, Any>) -> Vec<String> {
+    if let Some(any) = extensions.get(PLAYER_IDS_EXTENSION) {
+        if let Ok(list) = ListValue::decode(any.value.as_slice()) {
+            return strings(list);
+        }
+    }
+    extensions
+        .get(PLAYER_ID_EXTENSION)
+        .and_then(|any| String::decode(any.value.as_slice()).ok())
+        .into_iter()
+        .collect()
+}
@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+
+use om_ext::{player_ids, string_any, string_list_any, PLAYER_IDS_EXTENSION, PLAYER_ID_EXTENSION};
+
+#[test]
+fn party_tickets_stand_for_all_of_their_members() {
+    let mut extensions = HashMap::new();
+    extensions.insert(
+        PLAYER_ID_EXTENSION.to_string(),
+        string_any("leader").unwrap(),
+    );
+    assert_eq!(player_ids(&extensions), vec!["leader"]);
+
+    extensions.insert(
+        PLAYER_IDS_EXTENSION.to_string(),
+        string_list_any(&["leader".to_string(), "member".to_string()]).unwrap(),
+    );
+    assert_eq!(player_ids(&extensions), vec!["leader", "member"]);
+
+    assert!(player_ids(&HashMap::new()).is_empty());
+}
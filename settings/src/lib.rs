@@ -0,0 +1,420 @@
+//! Configuration loading shared by the binaries.
+//!
+//! A config is resolved from its defaults, then the file given with `--config` or
+//! `CONFIG_PATH` (TOML if the path ends with `.toml`, YAML otherwise), then the environment
+//! variables of the service, then flags such as `--max-party-size 4`. `--print-config` prints
+//! the resolved config and exits.
+//!
+//! `watch` resolves the config again periodically, so that a mounted ConfigMap can be changed
+//! without restarting the service. Only the keys a service applies while running are reloaded,
+//! changes of the others are logged as needing a restart.
+
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use tracing::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::watch;
+use tokio::time;
+
+const CONFIG_PATH_ENV: &str = "CONFIG_PATH";
+
+/// How often `watch` resolves the config. Kubernetes takes up to a minute to update a mounted
+/// ConfigMap anyway.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error(message)
+    }
+}
+
+/// An environment variable and the config key it overrides, e.g.
+/// `("TEAM_SIZE", "default_params.team_size")`.
+pub type EnvKey = (&'static str, &'static str);
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), String>;
+}
+
+#[derive(Debug)]
+pub struct Resolved<T> {
+    pub config: T,
+    pub print_config: bool,
+}
+
+#[derive(Default)]
+struct Args {
+    config_path: Option<String>,
+    print_config: bool,
+    overrides: Vec<(String, String)>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, Error> {
+    let mut parsed = Args::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            return Err(Error(format!("unexpected argument: {}", arg)));
+        }
+        let flag = arg.trim_start_matches('-');
+        if flag == "print-config" {
+            parsed.print_config = true;
+            continue;
+        }
+        let (name, value) = match flag.find('=') {
+            Some(i) => (&flag[..i], flag[i + 1..].to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| Error(format!("missing value of --{}", flag)))?;
+                (flag, value.clone())
+            }
+        };
+        if name == "config" {
+            parsed.config_path = Some(value);
+        } else {
+            parsed.overrides.push((name.replace('-', "_"), value));
+        }
+    }
+    Ok(parsed)
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Merges `value` into `tree`. Keys that are not in the defaults are rejected, except under
+/// maps that are empty by default.
+fn merge(tree: &mut Value, value: Value, path: &str) -> Result<(), Error> {
+    match (tree, value) {
+        (Value::Object(tree), Value::Object(value)) if !tree.is_empty() => {
+            for (key, value) in value {
+                let path = join(path, &key);
+                match tree.get_mut(&key) {
+                    Some(tree) => merge(tree, value, &path)?,
+                    None => return Err(Error(format!("unknown key: {}", path))),
+                }
+            }
+            Ok(())
+        }
+        (tree, value) => {
+            *tree = value;
+            Ok(())
+        }
+    }
+}
+
+fn read_file(path: &str) -> Result<Value, Error> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| Error(format!("cannot read config file {}: {}", path, err)))?;
+    let value = if path.ends_with(".toml") {
+        toml::from_str(&content).map_err(|err| err.to_string())
+    } else {
+        serde_yaml::from_str(&content).map_err(|err| err.to_string())
+    };
+    value.map_err(|err| Error(format!("cannot parse config file {}: {}", path, err)))
+}
+
+/// Parses "k=v,k2=v2".
+fn labels(raw: &str) -> Result<Value, String> {
+    let mut map = Map::new();
+    for label in raw.split(',').map(|label| label.trim()) {
+        if label.is_empty() {
+            continue;
+        }
+        let mut kv = label.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) if !key.is_empty() => {
+                map.insert(key.to_string(), Value::String(value.to_string()));
+            }
+            _ => return Err(format!("expected key=value, got {:?}", label)),
+        }
+    }
+    Ok(Value::Object(map))
+}
+
+fn flow(raw: &str) -> Result<Value, String> {
+    serde_yaml::from_str(raw).map_err(|err| err.to_string())
+}
+
+/// Converts a value given as text to the type of the default value.
+fn coerce(default: &Value, raw: &str) -> Result<Value, String> {
+    let raw = raw.trim();
+    match default {
+        Value::String(_) => Ok(Value::String(raw.to_string())),
+        Value::Bool(_) => raw
+            .parse()
+            .map(Value::Bool)
+            .map_err(|_| format!("expected true or false, got {:?}", raw)),
+        Value::Number(n) => {
+            let number = if n.is_f64() {
+                raw.parse().ok().and_then(serde_json::Number::from_f64)
+            } else if n.is_u64() {
+                raw.parse::<u64>().ok().map(Into::into)
+            } else {
+                raw.parse::<i64>().ok().map(Into::into)
+            };
+            number
+                .map(Value::Number)
+                .ok_or_else(|| format!("expected a number, got {:?}", raw))
+        }
+        // maps are "k=v,k2=v2", lists are separated by ";"
+        Value::Object(_) if raw.starts_with('{') => flow(raw),
+        Value::Object(_) => labels(raw),
+        Value::Array(_) if raw.starts_with('[') => flow(raw),
+        Value::Array(_) => raw
+            .split(';')
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| {
+                if item.contains('=') {
+                    labels(item)
+                } else {
+                    Ok(Value::String(item.trim().to_string()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        Value::Null => flow(raw),
+    }
+}
+
+fn set(tree: &mut Value, key: &str, raw: &str, source: &str) -> Result<(), Error> {
+    let mut target = tree;
+    for part in key.split('.') {
+        target = match target {
+            Value::Object(map) => match map.get_mut(part) {
+                Some(value) => value,
+                None => return Err(Error(format!("{}: unknown key: {}", source, key))),
+            },
+            _ => return Err(Error(format!("{}: unknown key: {}", source, key))),
+        };
+    }
+    *target = coerce(target, raw).map_err(|err| Error(format!("{}: {}", source, err)))?;
+    Ok(())
+}
+
+/// Resolves the config from `args` (without the program name) and the environment.
+pub fn resolve<T, F>(args: &[String], env: F, env_keys: &[EnvKey]) -> Result<Resolved<T>, Error>
+where
+    T: Default + Serialize + DeserializeOwned + Validate,
+    F: Fn(&str) -> Option<String>,
+{
+    let args = parse_args(args)?;
+    let mut tree = serde_json::to_value(T::default())
+        .map_err(|err| Error(format!("cannot encode the defaults: {}", err)))?;
+
+    if let Some(path) = args.config_path.or_else(|| env(CONFIG_PATH_ENV)) {
+        merge(&mut tree, read_file(&path)?, "")?;
+    }
+    for (var, key) in env_keys {
+        if let Some(raw) = env(var) {
+            set(&mut tree, key, &raw, var)?;
+        }
+    }
+    for (key, raw) in args.overrides.iter() {
+        set(&mut tree, key, raw, &format!("--{}", key.replace('_', "-")))?;
+    }
+
+    let config: T =
+        serde_json::from_value(tree).map_err(|err| Error(format!("invalid config: {}", err)))?;
+    config
+        .validate()
+        .map_err(|err| Error(format!("invalid config: {}", err)))?;
+    Ok(Resolved {
+        config,
+        print_config: args.print_config,
+    })
+}
+
+/// Resolves the config from the command line and the environment.
+pub fn reload<T>(env_keys: &[EnvKey]) -> Result<T, Error>
+where
+    T: Default + Serialize + DeserializeOwned + Validate,
+{
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    Ok(resolve(&args, |key| std::env::var(key).ok(), env_keys)?.config)
+}
+
+/// Resolves the config from the command line and the environment. With `--print-config`, the
+/// config is printed as YAML and the process exits.
+pub fn load<T>(env_keys: &[EnvKey]) -> Result<T, Error>
+where
+    T: Default + Serialize + DeserializeOwned + Validate,
+{
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let resolved = resolve(&args, |key| std::env::var(key).ok(), env_keys)?;
+    if resolved.print_config {
+        let yaml = serde_yaml::to_string(&resolved.config)
+            .map_err(|err| Error(format!("cannot print the config: {}", err)))?;
+        println!("{}", yaml);
+        std::process::exit(0);
+    }
+    Ok(resolved.config)
+}
+
+/// Lists the keys whose values differ, as `key: old -> new`.
+pub fn diff(old: &Value, new: &Value) -> Vec<String> {
+    changes(old, new)
+        .into_iter()
+        .map(|(_, change)| change)
+        .collect()
+}
+
+/// Lists the keys whose values differ with their `key: old -> new` description.
+fn changes(old: &Value, new: &Value) -> Vec<(String, String)> {
+    let mut changes = Vec::new();
+    diff_into(old, new, "", &mut changes);
+    changes
+}
+
+fn diff_into(old: &Value, new: &Value, path: &str, changes: &mut Vec<(String, String)>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = join(path, key);
+                match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) => diff_into(old, new, &path, changes),
+                    (old, new) => {
+                        let change = format!(
+                            "{}: {} -> {}",
+                            path,
+                            old.unwrap_or(&Value::Null),
+                            new.unwrap_or(&Value::Null)
+                        );
+                        changes.push((path, change));
+                    }
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) if old.len() == new.len() => {
+            for (i, (old, new)) in old.iter().zip(new.iter()).enumerate() {
+                diff_into(old, new, &join(path, &i.to_string()), changes);
+            }
+        }
+        (old, new) if old != new => {
+            changes.push((path.to_string(), format!("{}: {} -> {}", path, old, new)))
+        }
+        _ => {}
+    }
+}
+
+/// Whether `path` is one of the `reloadable` keys or under one of them.
+fn is_reloadable(path: &str, reloadable: &[&str]) -> bool {
+    reloadable
+        .iter()
+        .any(|key| path == *key || (path.starts_with(key) && path[key.len()..].starts_with('.')))
+}
+
+/// Calls `load` every `interval` and sends the value when one of the `reloadable` keys, or a
+/// key under them, changed. Changes of the other keys are logged as needing a restart. Values
+/// that fail to load are logged and the last good one is kept.
+pub fn watch_with<T, F>(
+    initial: T,
+    reloadable: &'static [&'static str],
+    interval: Duration,
+    mut load: F,
+) -> watch::Receiver<T>
+where
+    T: Serialize + Clone + Send + Sync + 'static,
+    F: FnMut() -> Result<T, Error> + Send + 'static,
+{
+    let (tx, rx) = watch::channel(initial.clone());
+    tokio::spawn(async move {
+        let mut last = serde_json::to_value(&initial).unwrap_or(Value::Null);
+        let mut last_err = None;
+        let mut ticks = time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let value = match load() {
+                Ok(value) => value,
+                Err(err) => {
+                    // logged once until the config changes
+                    let err = err.to_string();
+                    if last_err.as_ref() != Some(&err) {
+                        error!("invalid config, keeping the last good one: {}", err);
+                        last_err = Some(err);
+                    }
+                    continue;
+                }
+            };
+            last_err = None;
+            let current = serde_json::to_value(&value).unwrap_or(Value::Null);
+            let (applied, ignored): (Vec<_>, Vec<_>) = changes(&last, &current)
+                .into_iter()
+                .partition(|(path, _)| is_reloadable(path, reloadable));
+            last = current;
+            if !ignored.is_empty() {
+                let ignored: Vec<String> = ignored.into_iter().map(|(_, change)| change).collect();
+                warn!(
+                    "config changes that need a restart to apply: {}",
+                    ignored.join(", ")
+                );
+            }
+            if applied.is_empty() {
+                continue;
+            }
+            let applied: Vec<String> = applied.into_iter().map(|(_, change)| change).collect();
+            info!("config reloaded: {}", applied.join(", "));
+            if tx.broadcast(value).is_err() {
+                break; // nobody is watching anymore
+            }
+        }
+    });
+    rx
+}
+
+/// Resolves the config from the command line and the environment every `interval` and sends
+/// it when one of the `reloadable` keys changed. Invalid configs are logged and the last good
+/// one is kept.
+pub fn watch<T>(
+    initial: T,
+    env_keys: &'static [EnvKey],
+    reloadable: &'static [&'static str],
+    interval: Duration,
+) -> watch::Receiver<T>
+where
+    T: Default + Serialize + DeserializeOwned + Validate + Clone + Send + Sync + 'static,
+{
+    watch_with(initial, reloadable, interval, move || reload(env_keys))
+}
+
+/// Sends `f` of every value sent on `rx`.
+pub fn map<T, U, F>(mut rx: watch::Receiver<T>, f: F) -> watch::Receiver<U>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+    F: Fn(&T) -> U + Send + 'static,
+{
+    let (tx, mapped) = watch::channel(f(&rx.borrow()));
+    tokio::spawn(async move {
+        while let Some(value) = rx.recv().await {
+            if tx.broadcast(f(&value)).is_err() {
+                break;
+            }
+        }
+    });
+    mapped
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use settings::{resolve, EnvKey, Resolved, Validate};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Nested {
+    size: usize,
+    ratio: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    address: String,
+    enabled: bool,
+    nested: Nested,
+    labels: HashMap<String, String>,
+    selectors: Vec<HashMap<String, String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1:80".to_string(),
+            enabled: false,
+            nested: Nested {
+                size: 2,
+                ratio: 0.5,
+            },
+            labels: HashMap::new(),
+            selectors: Vec::new(),
+        }
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), String> {
+        if self.nested.size == 0 {
+            return Err("nested.size must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+const ENV_KEYS: &[EnvKey] = &[
+    ("ADDRESS", "address"),
+    ("SIZE", "nested.size"),
+    ("LABELS", "labels"),
+    ("SELECTORS", "selectors"),
+];
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    move |key| vars.get(key).cloned()
+}
+
+fn write_file(name: &str, content: &str) -> String {
+    let path = std::env::temp_dir().join(format!("settings-{}-{}", std::process::id(), name));
+    fs::write(&path, content).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn defaults_are_used_without_overrides() {
+    let resolved: Resolved<Config> = resolve(&[], env(&[]), ENV_KEYS).unwrap();
+    assert_eq!(resolved.config, Config::default());
+    assert!(!resolved.print_config);
+}
+
+#[test]
+fn flags_override_env_which_overrides_the_file() {
+    let path = write_file(
+        "config.yaml",
+        "address: file:1\nenabled: true\nnested:\n  size: 3\n  ratio: 0.25\n",
+    );
+    let resolved: Resolved<Config> = resolve(
+        &args(&["--config", &path, "--nested.size=5", "--print-config"]),
+        env(&[("ADDRESS", "env:1"), ("SIZE", "4")]),
+        ENV_KEYS,
+    )
+    .unwrap();
+    assert_eq!(resolved.config.address, "env:1");
+    assert!(resolved.config.enabled);
+    assert_eq!(resolved.config.nested.size, 5);
+    assert_eq!(resolved.config.nested.ratio, 0.25);
+    assert!(resolved.print_config);
+}
+
+#[test]
+fn toml_file_is_read_from_config_path() {
+    let path = write_file("config.toml", "[nested]\nratio = 2.0\n");
+    let config: Config = resolve(&[], env(&[("CONFIG_PATH", &path)]), ENV_KEYS)
+        .unwrap()
+        .config;
+    assert_eq!(config.nested.ratio, 2.0);
+    assert_eq!(config.nested.size, 2);
+}
+
+#[test]
+fn maps_and_lists_are_parsed_from_text() {
+    let config: Config = resolve(
+        &args(&["--labels", "team=a, mode=ranked"]),
+        env(&[("SELECTORS", "zone=a;zone=b,tier=1")]),
+        ENV_KEYS,
+    )
+    .unwrap()
+    .config;
+    assert_eq!(config.labels.len(), 2);
+    assert_eq!(config.labels["mode"], "ranked");
+    assert_eq!(config.selectors.len(), 2);
+    assert_eq!(config.selectors[1]["tier"], "1");
+}
+
+#[test]
+fn invalid_values_are_rejected_with_their_source() {
+    let err = resolve::<Config, _>(&[], env(&[("SIZE", "many")]), ENV_KEYS).unwrap_err();
+    assert_eq!(err.to_string(), "SIZE: expected a number, got \"many\"");
+
+    let err = resolve::<Config, _>(&args(&["--unknown", "1"]), env(&[]), ENV_KEYS).unwrap_err();
+    assert_eq!(err.to_string(), "--unknown: unknown key: unknown");
+
+    let path = write_file("unknown.yaml", "nested:\n  colour: red\n");
+    let err = resolve::<Config, _>(&args(&["--config", &path]), env(&[]), ENV_KEYS).unwrap_err();
+    assert_eq!(err.to_string(), "unknown key: nested.colour");
+
+    let err = resolve::<Config, _>(&args(&["--nested-size", "0"]), env(&[]), ENV_KEYS).unwrap_err();
+    assert!(err.to_string().contains("unknown key"));
+
+    let err = resolve::<Config, _>(&args(&["--nested.size", "0"]), env(&[]), ENV_KEYS).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid config: nested.size must be positive"
+    );
+}
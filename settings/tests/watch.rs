@@ -0,0 +1,113 @@
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::{delay_for, timeout};
+
+use settings::{diff, resolve, watch_with, Error, Validate};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Config {
+    name: String,
+    size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            name: "default".to_string(),
+            size: 2,
+        }
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), String> {
+        if self.size == 0 {
+            return Err("size must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn load(path: &str) -> Result<Config, Error> {
+    let args = vec!["--config".to_string(), path.to_string()];
+    Ok(resolve(&args, |_| None, &[])?.config)
+}
+
+#[tokio::test]
+async fn changed_configs_are_sent_and_invalid_ones_are_skipped() {
+    let path = std::env::temp_dir()
+        .join(format!("settings-{}-watch.yaml", std::process::id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+    fs::write(&path, "size: 3\n").unwrap();
+    let watched = path.clone();
+    let mut rx = watch_with(
+        load(&path).unwrap(),
+        &["size", "name"],
+        Duration::from_millis(20),
+        move || load(&watched),
+    );
+    assert_eq!(rx.recv().await.unwrap().size, 3);
+
+    fs::write(&path, "size: 0\n").unwrap();
+    delay_for(Duration::from_millis(100)).await;
+    assert_eq!(rx.borrow().size, 3);
+
+    fs::write(&path, "size: 4\nname: reloaded\n").unwrap();
+    let config = timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(config.size, 4);
+    assert_eq!(config.name, "reloaded");
+}
+
+#[tokio::test]
+async fn only_changes_of_reloadable_keys_are_sent() {
+    let path = std::env::temp_dir()
+        .join(format!("settings-{}-reloadable.yaml", std::process::id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+    fs::write(&path, "size: 3\n").unwrap();
+    let watched = path.clone();
+    let mut rx = watch_with(
+        load(&path).unwrap(),
+        &["size"],
+        Duration::from_millis(20),
+        move || load(&watched),
+    );
+    rx.recv().await.unwrap();
+
+    // the name needs a restart
+    fs::write(&path, "size: 3\nname: renamed\n").unwrap();
+    assert!(timeout(Duration::from_millis(200), rx.recv())
+        .await
+        .is_err());
+
+    fs::write(&path, "size: 5\nname: renamed\n").unwrap();
+    let config = timeout(Duration::from_secs(1), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(config.size, 5);
+}
+
+#[test]
+fn diff_lists_changed_keys() {
+    let old = json!({"a": 1, "b": {"c": "x", "d": [1, 2]}, "e": [1]});
+    let new = json!({"a": 1, "b": {"c": "y", "d": [1, 3]}, "e": [1, 2], "f": true});
+    assert_eq!(
+        diff(&old, &new),
+        vec![
+            "b.c: \"x\" -> \"y\"",
+            "b.d.1: 2 -> 3",
+            "e: [1] -> [1,2]",
+            "f: null -> true",
+        ]
+    );
+}
@@ -0,0 +1,13 @@
+const PROTOS: &[&str] = &[
+    "../proto/opentelemetry/proto/common/v1/common.proto",
+    "../proto/opentelemetry/proto/resource/v1/resource.proto",
+    "../proto/opentelemetry/proto/trace/v1/trace.proto",
+    "../proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
+];
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure()
+        .build_server(false)
+        .compile(PROTOS, &["../proto"])?;
+    Ok(())
+}
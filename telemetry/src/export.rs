@@ -0,0 +1,345 @@
+//! Export of the finished spans, in batches from a thread of its own.
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use tokio::runtime;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
+use tonic::transport::{Channel, Endpoint};
+use tracing::warn;
+
+use super::trace::{hex, SpanData};
+use super::Error;
+
+pub(crate) mod otlp {
+    pub mod opentelemetry {
+        pub mod proto {
+            pub mod common {
+                pub mod v1 {
+                    tonic::include_proto!("opentelemetry.proto.common.v1");
+                }
+            }
+            pub mod resource {
+                pub mod v1 {
+                    tonic::include_proto!("opentelemetry.proto.resource.v1");
+                }
+            }
+            pub mod trace {
+                pub mod v1 {
+                    tonic::include_proto!("opentelemetry.proto.trace.v1");
+                }
+            }
+            pub mod collector {
+                pub mod trace {
+                    pub mod v1 {
+                        tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+                    }
+                }
+            }
+        }
+    }
+    pub use opentelemetry::proto::collector::trace::v1::{
+        trace_service_client::TraceServiceClient, ExportTraceServiceRequest,
+    };
+    pub use opentelemetry::proto::common::v1::{
+        any_value, AnyValue, InstrumentationScope, KeyValue,
+    };
+    pub use opentelemetry::proto::resource::v1::Resource;
+    pub use opentelemetry::proto::trace::v1::{
+        span, status, ResourceSpans, ScopeSpans, Span, Status,
+    };
+}
+
+const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4317";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// When the spans are exported, from the `OTEL_BSP_*` variables of the OpenTelemetry batch
+/// span processor.
+pub(crate) struct Batching {
+    /// Longest time a span waits for its batch, `OTEL_BSP_SCHEDULE_DELAY` in milliseconds.
+    interval: Duration,
+    /// Spans exported at once, `OTEL_BSP_MAX_EXPORT_BATCH_SIZE`.
+    max_batch_size: usize,
+    /// Spans waiting to be exported, `OTEL_BSP_MAX_QUEUE_SIZE`. Newer spans are dropped while
+    /// the queue is full, e.g. while the collector is down.
+    max_queue_size: usize,
+}
+
+impl Batching {
+    pub(crate) fn from_env() -> Result<Self, Error> {
+        Ok(Batching {
+            interval: Duration::from_millis(positive_var("OTEL_BSP_SCHEDULE_DELAY", 5000)?),
+            max_batch_size: positive_var("OTEL_BSP_MAX_EXPORT_BATCH_SIZE", 512)? as usize,
+            max_queue_size: positive_var("OTEL_BSP_MAX_QUEUE_SIZE", 2048)? as usize,
+        })
+    }
+}
+
+fn positive_var(name: &str, default: u64) -> Result<u64, Error> {
+    let raw = match env::var(name) {
+        Ok(raw) => raw,
+        Err(_) => return Ok(default),
+    };
+    match raw.parse() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(Error(format!(
+            "{} must be a positive number: {}",
+            name, raw
+        ))),
+    }
+}
+
+/// The queue of the finished spans the exporter thread reads.
+pub(crate) struct SpanQueue {
+    tx: Mutex<mpsc::Sender<SpanData>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl SpanQueue {
+    /// Queues `span`, or drops it when the queue is full.
+    pub(crate) fn push(&self, span: SpanData) {
+        if self.tx.lock().unwrap().try_send(span).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub(crate) enum Exporter {
+    Otlp {
+        endpoint: String,
+        client: Option<otlp::TraceServiceClient<Channel>>,
+    },
+    /// JSON lines for reading the traces without a collector.
+    Writer(Box<dyn Write + Send>),
+}
+
+impl Exporter {
+    /// The exporter of `OTEL_TRACES_EXPORTER`: `otlp`, `stdout`, `file` or `none`. It is `otlp`
+    /// when only `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+    pub(crate) fn from_env() -> Result<Option<Self>, Error> {
+        let endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok();
+        let default = if endpoint.is_some() { "otlp" } else { "none" };
+        let exporter = env::var("OTEL_TRACES_EXPORTER").unwrap_or_else(|_| default.to_string());
+        match exporter.as_str() {
+            "none" => Ok(None),
+            "otlp" => Ok(Some(Exporter::Otlp {
+                endpoint: endpoint.unwrap_or_else(|| DEFAULT_OTLP_ENDPOINT.to_string()),
+                client: None,
+            })),
+            "stdout" => Ok(Some(Exporter::Writer(Box::new(io::stdout())))),
+            "file" => {
+                let path = env::var("OTEL_TRACES_FILE").map_err(|_| {
+                    Error("OTEL_TRACES_FILE is required by the file exporter".into())
+                })?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|err| Error(format!("cannot open {}: {}", path, err)))?;
+                Ok(Some(Exporter::Writer(Box::new(BufWriter::new(file)))))
+            }
+            other => Err(Error(format!("unknown OTEL_TRACES_EXPORTER: {}", other))),
+        }
+    }
+
+    async fn export(&mut self, service_name: &str, spans: &[SpanData]) -> Result<(), String> {
+        match self {
+            Exporter::Otlp { endpoint, client } => {
+                // reconnect after a failure
+                let mut c = match client.take() {
+                    Some(c) => c,
+                    None => {
+                        let endpoint = Endpoint::from_shared(endpoint.clone())
+                            .map_err(|err| err.to_string())?
+                            .timeout(EXPORT_TIMEOUT);
+                        let channel = time::timeout(EXPORT_TIMEOUT, endpoint.connect())
+                            .await
+                            .map_err(|err| err.to_string())?
+                            .map_err(|err| err.to_string())?;
+                        otlp::TraceServiceClient::new(channel)
+                    }
+                };
+                c.export(otlp_request(service_name, spans))
+                    .await
+                    .map_err(|err| err.to_string())?;
+                *client = Some(c);
+                Ok(())
+            }
+            Exporter::Writer(writer) => {
+                for span in spans {
+                    writeln!(writer, "{}", json_line(service_name, span))
+                        .map_err(|err| err.to_string())?;
+                }
+                writer.flush().map_err(|err| err.to_string())
+            }
+        }
+    }
+}
+
+/// Starts exporting the spans pushed to the returned queue.
+pub(crate) fn spawn(
+    mut exporter: Exporter,
+    service_name: String,
+    batching: Batching,
+) -> Result<SpanQueue, Error> {
+    let (tx, mut rx) = mpsc::channel::<SpanData>(batching.max_queue_size);
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let queue = SpanQueue {
+        tx: Mutex::new(tx),
+        dropped: dropped.clone(),
+    };
+    let mut runtime = runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .map_err(|err| Error(format!("cannot start the exporter: {}", err)))?;
+    let run = async move {
+        let mut batch = Vec::new();
+        let mut deadline = Instant::now() + batching.interval;
+        loop {
+            let closed = match time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(span)) => {
+                    batch.push(span);
+                    if batch.len() < batching.max_batch_size {
+                        continue;
+                    }
+                    false
+                }
+                Ok(None) => true,
+                Err(_) => false,
+            };
+            let dropped = dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("dropped {} spans, the export queue was full", dropped);
+            }
+            if !batch.is_empty() {
+                let spans = mem::replace(&mut batch, Vec::new());
+                if let Err(err) = exporter.export(&service_name, &spans).await {
+                    warn!("failed to export {} spans: {}", spans.len(), err);
+                }
+            }
+            if closed {
+                return;
+            }
+            deadline = Instant::now() + batching.interval;
+        }
+    };
+    thread::Builder::new()
+        .name("telemetry".to_string())
+        .spawn(move || {
+            super::trace::ignore_spans_of_this_thread();
+            runtime.block_on(run)
+        })
+        .map_err(|err| Error(format!("cannot start the exporter: {}", err)))?;
+    Ok(queue)
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn key_value(key: &str, value: &str) -> otlp::KeyValue {
+    otlp::KeyValue {
+        key: key.to_string(),
+        value: Some(otlp::AnyValue {
+            value: Some(otlp::any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+fn key_values(attributes: &[(&'static str, String)]) -> Vec<otlp::KeyValue> {
+    attributes
+        .iter()
+        .map(|(key, value)| key_value(key, value))
+        .collect()
+}
+
+fn otlp_request(service_name: &str, spans: &[SpanData]) -> otlp::ExportTraceServiceRequest {
+    let spans = spans
+        .iter()
+        .map(|span| otlp::Span {
+            trace_id: span.context.trace_id.to_vec(),
+            span_id: span.context.span_id.to_vec(),
+            parent_span_id: span.parent_span_id.map_or(Vec::new(), |id| id.to_vec()),
+            flags: span.context.sampled as u32,
+            name: span.name.to_string(),
+            kind: if span.server {
+                otlp::span::SpanKind::Server as i32
+            } else {
+                otlp::span::SpanKind::Internal as i32
+            },
+            start_time_unix_nano: unix_nanos(span.start),
+            end_time_unix_nano: unix_nanos(span.end),
+            attributes: key_values(&span.attributes),
+            events: span
+                .events
+                .iter()
+                .map(|event| otlp::span::Event {
+                    time_unix_nano: unix_nanos(event.time),
+                    name: event.name.clone(),
+                    attributes: key_values(&event.attributes),
+                    dropped_attributes_count: 0,
+                })
+                .collect(),
+            status: span.error.as_ref().map(|message| otlp::Status {
+                message: message.clone(),
+                code: otlp::status::StatusCode::Error as i32,
+            }),
+            ..Default::default()
+        })
+        .collect();
+    otlp::ExportTraceServiceRequest {
+        resource_spans: vec![otlp::ResourceSpans {
+            resource: Some(otlp::Resource {
+                attributes: vec![key_value("service.name", service_name)],
+                dropped_attributes_count: 0,
+            }),
+            scope_spans: vec![otlp::ScopeSpans {
+                scope: Some(otlp::InstrumentationScope {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    ..Default::default()
+                }),
+                spans: spans,
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }],
+    }
+}
+
+fn json_line(service_name: &str, span: &SpanData) -> serde_json::Value {
+    let attributes = |attributes: &[(&'static str, String)]| {
+        attributes
+            .iter()
+            .map(|(key, value)| (key.to_string(), json!(value)))
+            .collect::<serde_json::Map<_, _>>()
+    };
+    json!({
+        "service_name": service_name,
+        "trace_id": hex(&span.context.trace_id),
+        "span_id": hex(&span.context.span_id),
+        "parent_span_id": span.parent_span_id.map(|id| hex(&id)),
+        "name": span.name,
+        "kind": if span.server { "server" } else { "internal" },
+        "start_time_unix_nano": unix_nanos(span.start),
+        "end_time_unix_nano": unix_nanos(span.end),
+        "attributes": attributes(&span.attributes),
+        "events": span.events.iter().map(|event| json!({
+            "time_unix_nano": unix_nanos(event.time),
+            "name": event.name,
+            "attributes": attributes(&event.attributes),
+        })).collect::<Vec<_>>(),
+        "error": span.error,
+    })
+}
@@ -0,0 +1,63 @@
+//! Logging and tracing of the servers.
+//!
+//! Events are written to stdout as JSON lines, with the fields of the spans they happen in,
+//! so that the ticket, match, player and gameserver of a request can be followed across the
+//! services. Records of the `log` crate are included.
+//!
+//! Spans are also exported as OpenTelemetry traces. Their context is carried to the other
+//! services in the `traceparent` header of every request made through [`Traced`], and server
+//! spans continue the trace of the request from their `traceparent` field.
+//!
+//! The metrics of the services are served by [`metrics::serve`].
+
+use std::env;
+use std::fmt;
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::Registry;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt as format, EnvFilter};
+
+mod export;
+pub mod metrics;
+mod propagation;
+pub mod trace;
+
+pub use propagation::{inject, traceparent, Traced, TRACEPARENT_HEADER};
+pub use trace::TraceLayer;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Installs the global subscriber. `RUST_LOG` filters the events and spans, and
+/// `LOG_FORMAT=text` writes them as text instead of JSON. The spans are exported by the
+/// exporter of `OTEL_TRACES_EXPORTER` as `service_name`, unless `OTEL_SERVICE_NAME` is set, in
+/// batches configured by the `OTEL_BSP_*` variables.
+pub fn init(service_name: &str) -> Result<(), Error> {
+    let service_name = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| service_name.to_string());
+    let traces = match export::Exporter::from_env()? {
+        Some(exporter) => TraceLayer::with_exporter(export::spawn(
+            exporter,
+            service_name,
+            export::Batching::from_env()?,
+        )?),
+        None => TraceLayer::new(),
+    };
+    let subscriber = Registry::default()
+        .with(EnvFilter::from_default_env())
+        .with(traces);
+    let res = match env::var("LOG_FORMAT").as_ref().map(String::as_str) {
+        Ok("text") => subscriber.with(format::layer()).try_init(),
+        Ok("json") | Err(_) => subscriber.with(format::layer().json()).try_init(),
+        Ok(format) => return Err(Error(format!("unknown LOG_FORMAT: {}", format))),
+    };
+    res.map_err(|err| Error(format!("cannot install the subscriber: {}", err)))
+}
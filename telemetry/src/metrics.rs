@@ -0,0 +1,39 @@
+//! Prometheus metrics. The services register their metrics in the default registry, and
+//! `serve` serves all of them.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, TextEncoder};
+use tracing::error;
+
+use super::Error;
+
+/// Encodes all metrics in the Prometheus text format.
+pub fn gather() -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .map_err(|err| Error(format!("cannot encode the metrics: {:?}", err)))?;
+    Ok(buf)
+}
+
+async fn handle(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    match gather() {
+        Ok(buf) => Ok(Response::new(Body::from(buf))),
+        Err(err) => {
+            error!("failed to gather metrics: {}", err);
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(res)
+        }
+    }
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub async fn serve(address: SocketAddr) -> Result<(), hyper::Error> {
+    let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+    Server::bind(&address).serve(make_svc).await
+}
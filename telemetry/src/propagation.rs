@@ -0,0 +1,60 @@
+//! The trace context in the `traceparent` header of the gRPC and HTTP requests.
+
+use std::task::{Context, Poll};
+
+use http::header::{HeaderMap, HeaderValue};
+use tonic::metadata::MetadataMap;
+use tower_service::Service;
+
+use super::trace;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Adds the context of the current span to an outgoing request.
+pub fn inject(headers: &mut HeaderMap) {
+    let value =
+        trace::current().and_then(|context| HeaderValue::from_str(&context.traceparent()).ok());
+    if let Some(value) = value {
+        headers.insert(TRACEPARENT_HEADER, value);
+    }
+}
+
+/// The `traceparent` of an incoming request, or an empty string, for the `traceparent` field
+/// of its server span.
+pub fn traceparent(metadata: &MetadataMap) -> &str {
+    metadata
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
+
+/// A channel that sends the context of the current span with every request, for the
+/// generated gRPC clients.
+#[derive(Clone, Debug)]
+pub struct Traced<S> {
+    inner: S,
+}
+
+impl<S> Traced<S> {
+    pub fn new(inner: S) -> Self {
+        Traced { inner: inner }
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for Traced<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        inject(req.headers_mut());
+        self.inner.call(req)
+    }
+}
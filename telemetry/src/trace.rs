@@ -0,0 +1,297 @@
+//! Trace context of the spans.
+//!
+//! Every span gets a trace and span id, and a span continues the trace of its parent. A span
+//! with a `traceparent` field continues the trace of a remote caller instead and is exported
+//! as a server span.
+
+use std::cell::Cell;
+use std::fmt;
+use std::time::SystemTime;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+use super::export::SpanQueue;
+
+/// Field of the server spans that holds the `traceparent` of the request.
+pub const TRACEPARENT_FIELD: &str = "traceparent";
+
+thread_local! {
+    // set on the exporter thread so that exporting doesn't make more spans
+    static IGNORED: Cell<bool> = Cell::new(false);
+}
+
+/// Ids of a span that are carried to other services.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpanContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl SpanContext {
+    /// Parses a W3C `traceparent`: `<version>-<trace id>-<span id>-<flags>`.
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        if parts.len() < 4 || parts[0] == "ff" || (parts[0] == "00" && parts.len() != 4) {
+            return None;
+        }
+        let mut version = [0; 1];
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+        let mut flags = [0; 1];
+        decode_hex(parts[0], &mut version)?;
+        decode_hex(parts[1], &mut trace_id)?;
+        decode_hex(parts[2], &mut span_id)?;
+        decode_hex(parts[3], &mut flags)?;
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(SpanContext {
+            trace_id: trace_id,
+            span_id: span_id,
+            sampled: flags[0] & 1 == 1,
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    fn root() -> Self {
+        SpanContext {
+            trace_id: random_id(),
+            span_id: random_id(),
+            sampled: true,
+        }
+    }
+
+    fn child(&self) -> Self {
+        SpanContext {
+            trace_id: self.trace_id,
+            span_id: random_id(),
+            sampled: self.sampled,
+        }
+    }
+}
+
+/// Context of the current span.
+pub fn current() -> Option<SpanContext> {
+    let id = tracing::Span::current().id()?;
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let span = registry.span(&id)?;
+        let extensions = span.extensions();
+        let context = extensions.get::<SpanData>().map(|data| data.context);
+        context
+    })
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str, out: &mut [u8]) -> Option<()> {
+    if s.len() != out.len() * 2 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(())
+}
+
+// ids are never all zeros
+fn random_id<T: AsMut<[u8]> + Default>() -> T {
+    let mut id = T::default();
+    while id.as_mut().iter().all(|b| *b == 0) {
+        rand::Rng::fill(&mut rand::thread_rng(), id.as_mut());
+    }
+    id
+}
+
+pub(crate) fn ignore_spans_of_this_thread() {
+    IGNORED.with(|ignored| ignored.set(true));
+}
+
+pub(crate) struct SpanEvent {
+    pub time: SystemTime,
+    pub name: String,
+    pub attributes: Vec<(&'static str, String)>,
+}
+
+/// A span with everything that is exported.
+pub(crate) struct SpanData {
+    pub context: SpanContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub name: &'static str,
+    pub server: bool,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, String)>,
+    pub events: Vec<SpanEvent>,
+    // message of the first error event
+    pub error: Option<String>,
+}
+
+struct Fields<'a> {
+    attributes: &'a mut Vec<(&'static str, String)>,
+    message: Option<String>,
+    traceparent: Option<String>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(attributes: &'a mut Vec<(&'static str, String)>) -> Self {
+        Fields {
+            attributes: attributes,
+            message: None,
+            traceparent: None,
+        }
+    }
+
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = Some(value),
+            TRACEPARENT_FIELD => self.traceparent = Some(value),
+            name => self.attributes.push((name, value)),
+        }
+    }
+}
+
+impl<'a> Visit for Fields<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{:?}", value));
+    }
+}
+
+/// Keeps the context of every span and sends the finished ones to the exporter.
+#[derive(Default)]
+pub struct TraceLayer {
+    exporter: Option<SpanQueue>,
+}
+
+impl TraceLayer {
+    /// A layer that only propagates the contexts and exports nothing.
+    pub fn new() -> Self {
+        TraceLayer { exporter: None }
+    }
+
+    pub(crate) fn with_exporter(exporter: SpanQueue) -> Self {
+        TraceLayer {
+            exporter: Some(exporter),
+        }
+    }
+}
+
+impl<S> Layer<S> for TraceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if IGNORED.with(|ignored| ignored.get()) {
+            return;
+        }
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut attributes = Vec::new();
+        let mut fields = Fields::new(&mut attributes);
+        attrs.record(&mut fields);
+        let server = fields.traceparent.is_some();
+        let remote = fields
+            .traceparent
+            .and_then(|traceparent| SpanContext::from_traceparent(&traceparent));
+        let parent = match remote {
+            Some(remote) => Some(remote),
+            None if server => None,
+            None => span.parent().and_then(|parent| {
+                let extensions = parent.extensions();
+                let context = extensions.get::<SpanData>().map(|data| data.context);
+                context
+            }),
+        };
+        let now = SystemTime::now();
+        let data = SpanData {
+            context: parent.map_or_else(SpanContext::root, |parent| parent.child()),
+            parent_span_id: parent.map(|parent| parent.span_id),
+            name: attrs.metadata().name(),
+            server: server,
+            start: now,
+            end: now,
+            attributes: attributes,
+            events: Vec::new(),
+            error: None,
+        };
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(&mut Fields::new(&mut data.attributes));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let id = match event.parent() {
+            Some(id) => id.clone(),
+            None if event.is_contextual() => match ctx.current_span().id() {
+                Some(id) => id.clone(),
+                None => return,
+            },
+            None => return,
+        };
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        let data = match extensions.get_mut::<SpanData>() {
+            Some(data) => data,
+            None => return,
+        };
+        let mut attributes = Vec::new();
+        let mut fields = Fields::new(&mut attributes);
+        event.record(&mut fields);
+        let name = fields
+            .message
+            .unwrap_or_else(|| event.metadata().name().to_string());
+        if *event.metadata().level() == Level::ERROR && data.error.is_none() {
+            data.error = Some(name.clone());
+        }
+        data.events.push(SpanEvent {
+            time: SystemTime::now(),
+            name: name,
+            attributes: attributes,
+        });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let exporter = match &self.exporter {
+            Some(exporter) => exporter,
+            None => return,
+        };
+        if let Some(span) = ctx.span(&id) {
+            if let Some(mut data) = span.extensions_mut().remove::<SpanData>() {
+                if data.context.sampled {
+                    data.end = SystemTime::now();
+                    exporter.push(data);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+use tracing::info_span;
+
+fn lines(path: &Path) -> Vec<Value> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+// `init` installs the global subscriber, so this is the only test of this file.
+#[test]
+fn spans_are_written_to_the_file_in_batches() {
+    let path = env::temp_dir().join(format!("telemetry-{}-spans.json", std::process::id()));
+    let _ = fs::remove_file(&path);
+    env::set_var("RUST_LOG", "info");
+    env::set_var("OTEL_TRACES_EXPORTER", "file");
+    env::set_var("OTEL_TRACES_FILE", &path);
+    env::set_var("OTEL_BSP_SCHEDULE_DELAY", "500");
+    env::set_var("OTEL_BSP_MAX_EXPORT_BATCH_SIZE", "10");
+    telemetry::init("export-test").unwrap();
+
+    for i in 0..25 {
+        info_span!("work", ticket_id = %i).in_scope(|| {});
+    }
+    // full batches are written right away
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(lines(&path).len(), 20);
+
+    // and the rest once it waited for the schedule delay
+    thread::sleep(Duration::from_millis(600));
+    let spans = lines(&path);
+    assert_eq!(spans.len(), 25);
+    let span = &spans[24];
+    assert_eq!(span["service_name"], "export-test");
+    assert_eq!(span["name"], "work");
+    assert_eq!(span["kind"], "internal");
+    assert_eq!(span["attributes"]["ticket_id"], "24");
+    assert_eq!(span["trace_id"].as_str().unwrap().len(), 32);
+    assert_eq!(span["span_id"].as_str().unwrap().len(), 16);
+    assert_eq!(span["parent_span_id"], Value::Null);
+}
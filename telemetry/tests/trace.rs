@@ -0,0 +1,61 @@
+use http::header::HeaderMap;
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::Registry;
+
+use telemetry::trace::{current, SpanContext};
+use telemetry::TraceLayer;
+
+const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+#[test]
+fn traceparent_round_trips() {
+    let context = SpanContext::from_traceparent(TRACEPARENT).unwrap();
+    assert_eq!(
+        context.span_id,
+        [0xb7, 0xad, 0x6b, 0x71, 0x69, 0x20, 0x33, 0x31]
+    );
+    assert!(context.sampled);
+    assert_eq!(context.traceparent(), TRACEPARENT);
+
+    for invalid in &[
+        "",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+        "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+        "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b716920333x-01",
+        "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+    ] {
+        assert_eq!(SpanContext::from_traceparent(invalid), None, "{}", invalid);
+    }
+}
+
+#[test]
+fn spans_continue_the_trace_of_the_request() {
+    let subscriber = Registry::default().with(TraceLayer::new());
+    tracing::subscriber::with_default(subscriber, || {
+        let remote = SpanContext::from_traceparent(TRACEPARENT).unwrap();
+        let server = info_span!("join", traceparent = TRACEPARENT);
+        let child = server.in_scope(|| info_span!("match"));
+
+        let server_context = server.in_scope(current).unwrap();
+        let child_context = child.in_scope(current).unwrap();
+        assert_eq!(server_context.trace_id, remote.trace_id);
+        assert_eq!(child_context.trace_id, remote.trace_id);
+        assert_ne!(server_context.span_id, remote.span_id);
+        assert_ne!(child_context.span_id, server_context.span_id);
+
+        let mut headers = HeaderMap::new();
+        child.in_scope(|| telemetry::inject(&mut headers));
+        assert_eq!(
+            headers[telemetry::TRACEPARENT_HEADER],
+            child_context.traceparent()
+        );
+
+        // a request without a context starts a new trace
+        let root = info_span!("join", traceparent = "");
+        let root_context = root.in_scope(current).unwrap();
+        assert_ne!(root_context.trace_id, remote.trace_id);
+        assert!(current().is_none());
+    });
+}
@@ -0,0 +1,20 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure().build_server(true).compile(
+        &[
+            "../deps/open-match/api/frontend.proto",
+            "../deps/open-match/api/backend.proto",
+            "../deps/open-match/api/query.proto",
+            "../deps/open-match/api/matchfunction.proto",
+        ],
+        &["../deps/open-match", "../deps/open-match/third_party"],
+    )?;
+    tonic_build::configure().build_server(true).compile(
+        &["../deps/agones/proto/sdk/sdk.proto"],
+        &[
+            "../deps/agones/proto/sdk",
+            "../deps/agones/proto/googleapis",
+            "../deps/agones/proto/grpc-gateway",
+        ],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+use tokio::sync::{mpsc, watch};
+
+use super::sdk;
+
+// Labels and annotations set through the SDK are prefixed like in Agones.
+const METADATA_PREFIX: &str = "agones.dev/sdk-";
+
+struct SdkState {
+    gameserver: Mutex<sdk::GameServer>,
+    tx: watch::Sender<sdk::GameServer>,
+    rx: watch::Receiver<sdk::GameServer>,
+    health_checks: AtomicUsize,
+}
+
+/// In-memory Agones SDK sidecar of one gameserver.
+///
+/// `agones::Sdk::new()` connects to it when `AGONES_SDK_GRPC_PORT` is set to the port it serves
+/// on. The gameserver starts in the `Scheduled` state.
+#[derive(Clone)]
+pub struct FakeAgonesSdk {
+    state: Arc<SdkState>,
+}
+
+impl FakeAgonesSdk {
+    pub fn new(name: &str, address: &str, port: i32, labels: HashMap<String, String>) -> Self {
+        let gameserver = sdk::GameServer {
+            object_meta: Some(sdk::game_server::ObjectMeta {
+                name: name.to_string(),
+                namespace: "default".to_string(),
+                labels,
+                ..Default::default()
+            }),
+            status: Some(sdk::game_server::Status {
+                state: "Scheduled".to_string(),
+                address: address.to_string(),
+                ports: vec![sdk::game_server::status::Port {
+                    name: "default".to_string(),
+                    port,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (tx, rx) = watch::channel(gameserver.clone());
+        FakeAgonesSdk {
+            state: Arc::new(SdkState {
+                gameserver: Mutex::new(gameserver),
+                tx,
+                rx,
+                health_checks: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn gameserver(&self) -> sdk::GameServer {
+        self.state.gameserver.lock().unwrap().clone()
+    }
+
+    pub fn name(&self) -> String {
+        self.gameserver()
+            .object_meta
+            .map(|meta| meta.name)
+            .unwrap_or_default()
+    }
+
+    pub fn state(&self) -> String {
+        self.gameserver()
+            .status
+            .map(|status| status.state)
+            .unwrap_or_default()
+    }
+
+    pub fn set_state(&self, state: &str) {
+        debug!("gameserver {} is {}", self.name(), state);
+        self.update(|gs| {
+            if let Some(status) = gs.status.as_mut() {
+                status.state = state.to_string();
+            }
+        });
+    }
+
+    /// Number of health pings received.
+    pub fn health_checks(&self) -> usize {
+        self.state.health_checks.load(Ordering::SeqCst)
+    }
+
+    fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut sdk::GameServer),
+    {
+        let mut gameserver = self.state.gameserver.lock().unwrap();
+        f(&mut gameserver);
+        let _ = self.state.tx.broadcast(gameserver.clone());
+    }
+
+    pub fn service(&self) -> sdk::sdk_server::SdkServer<SdkService> {
+        sdk::sdk_server::SdkServer::new(SdkService { sdk: self.clone() })
+    }
+
+    pub async fn serve(self, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.service())
+            .serve(address)
+            .await
+    }
+}
+
+pub struct SdkService {
+    sdk: FakeAgonesSdk,
+}
+
+#[tonic::async_trait]
+impl sdk::sdk_server::Sdk for SdkService {
+    async fn ready(
+        &self,
+        _request: tonic::Request<sdk::Empty>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        self.sdk.set_state("Ready");
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+
+    async fn allocate(
+        &self,
+        _request: tonic::Request<sdk::Empty>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        self.sdk.set_state("Allocated");
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+
+    async fn shutdown(
+        &self,
+        _request: tonic::Request<sdk::Empty>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        self.sdk.set_state("Shutdown");
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+
+    async fn health(
+        &self,
+        request: tonic::Request<tonic::Streaming<sdk::Empty>>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        let mut stream = request.into_inner();
+        while stream.message().await?.is_some() {
+            self.sdk.state.health_checks.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+
+    async fn get_game_server(
+        &self,
+        _request: tonic::Request<sdk::Empty>,
+    ) -> Result<tonic::Response<sdk::GameServer>, tonic::Status> {
+        Ok(tonic::Response::new(self.sdk.gameserver()))
+    }
+
+    type WatchGameServerStream = mpsc::Receiver<Result<sdk::GameServer, tonic::Status>>;
+
+    async fn watch_game_server(
+        &self,
+        _request: tonic::Request<sdk::Empty>,
+    ) -> Result<tonic::Response<Self::WatchGameServerStream>, tonic::Status> {
+        let mut rx = self.sdk.state.rx.clone();
+        let (mut tx, stream) = mpsc::channel(4);
+        tokio::spawn(async move {
+            // the first value is the current gameserver
+            while let Some(gameserver) = rx.recv().await {
+                if tx.send(Ok(gameserver)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(stream))
+    }
+
+    async fn set_label(
+        &self,
+        request: tonic::Request<sdk::KeyValue>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        let kv = request.into_inner();
+        self.sdk.update(|gs| {
+            if let Some(meta) = gs.object_meta.as_mut() {
+                meta.labels
+                    .insert(format!("{}{}", METADATA_PREFIX, kv.key), kv.value);
+            }
+        });
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+
+    async fn set_annotation(
+        &self,
+        request: tonic::Request<sdk::KeyValue>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        let kv = request.into_inner();
+        self.sdk.update(|gs| {
+            if let Some(meta) = gs.object_meta.as_mut() {
+                meta.annotations
+                    .insert(format!("{}{}", METADATA_PREFIX, kv.key), kv.value);
+            }
+        });
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+
+    async fn reserve(
+        &self,
+        request: tonic::Request<sdk::Duration>,
+    ) -> Result<tonic::Response<sdk::Empty>, tonic::Status> {
+        let seconds = request.into_inner().seconds;
+        self.sdk.set_state("Reserved");
+        if seconds > 0 {
+            // back to Ready unless allocated or shut down in the meantime
+            let sdk = self.sdk.clone();
+            tokio::spawn(async move {
+                tokio::time::delay_for(Duration::from_secs(seconds as u64)).await;
+                if sdk.state() == "Reserved" {
+                    sdk.set_state("Ready");
+                }
+            });
+        }
+        Ok(tonic::Response::new(sdk::Empty {}))
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use settings::{EnvKey, Validate};
+
+use testing::FakeAgonesSdk;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Config {
+    address: SocketAddr,
+    gs_name: String,
+    gs_address: String,
+    gs_port: i32,
+    gs_labels: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: ([127, 0, 0, 1], 9357).into(),
+            gs_name: "gameserver".to_string(),
+            gs_address: "127.0.0.1".to_string(),
+            gs_port: 10000,
+            gs_labels: HashMap::new(),
+        }
+    }
+}
+
+const ENV_KEYS: &[EnvKey] = &[
+    ("ADDRESS", "address"),
+    ("GS_NAME", "gs_name"),
+    ("GS_ADDRESS", "gs_address"),
+    ("GS_PORT", "gs_port"),
+    ("GS_LABELS", "gs_labels"),
+];
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), String> {
+        if self.gs_name.is_empty() {
+            return Err("gs_name must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Runs an in-memory Agones SDK sidecar, so a gameserver or the director in self-allocation mode
+/// can run without a cluster. Start them with `AGONES_SDK_GRPC_PORT` set to the port of ADDRESS.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    telemetry::init("fake-agones-sdk")?;
+
+    let config: Config = settings::load(ENV_KEYS)?;
+    info!("start fake agones sdk. address: {}", config.address);
+    FakeAgonesSdk::new(
+        &config.gs_name,
+        &config.gs_address,
+        config.gs_port,
+        config.gs_labels,
+    )
+    .serve(config.address)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use log::debug;
+
+use director_worker::{
+    AllocationError, AllocationParams, Backfill, GameServerAllocationClient, Port, Status,
+};
+use gameserver_client::{GameServerClient, GameServerClientImpl};
+
+use super::agones::FakeAgonesSdk;
+
+/// Label Agones sets on the gameservers of a fleet.
+pub const FLEET_LABEL: &str = "agones.dev/fleet";
+
+/// Allocates the `Ready` gameservers of an in-memory fleet, like the Agones allocation API.
+///
+/// Gameservers that shut down are made `Ready` again on the next allocation, like the fresh
+/// gameserver a fleet would create in their place.
+pub struct FakeFleetAllocationClient {
+    gameservers: Vec<FakeAgonesSdk>,
+    region_label: String,
+}
+
+impl FakeFleetAllocationClient {
+    pub fn new(gameservers: Vec<FakeAgonesSdk>, region_label: String) -> Self {
+        FakeFleetAllocationClient {
+            gameservers,
+            region_label,
+        }
+    }
+
+    fn selected(&self, sdk: &FakeAgonesSdk, params: &AllocationParams) -> bool {
+        let labels = match sdk.gameserver().object_meta {
+            Some(meta) => meta.labels,
+            None => return false,
+        };
+        let fleet = params
+            .fleet
+            .as_ref()
+            .map_or(true, |fleet| labels.get(FLEET_LABEL) == Some(fleet));
+        let region = match &params.region {
+            Some(region) if !region.is_empty() => labels.get(&self.region_label) == Some(region),
+            _ => true,
+        };
+        fleet && region
+    }
+}
+
+fn status(sdk: &FakeAgonesSdk) -> Result<Status, AllocationError> {
+    let status = sdk
+        .gameserver()
+        .status
+        .ok_or(AllocationError::Api(anyhow::anyhow!("empty status")))?;
+    let port = status
+        .ports
+        .first()
+        .ok_or(AllocationError::Api(anyhow::anyhow!("port is empty")))?;
+    Ok(Status::new(
+        status.state,
+        status.address,
+        vec![Port::new(port.name.clone(), port.port)],
+    ))
+}
+
+fn address(sdk: &FakeAgonesSdk) -> Option<String> {
+    let status = sdk.gameserver().status?;
+    let port = status.ports.first()?;
+    Some(format!("{}:{}", status.address, port.port))
+}
+
+#[async_trait]
+impl GameServerAllocationClient for FakeFleetAllocationClient {
+    async fn allocate(&self, params: &AllocationParams) -> Result<Status, AllocationError> {
+        for sdk in self.gameservers.iter() {
+            if sdk.state() == "Shutdown" {
+                sdk.set_state("Ready");
+            }
+        }
+        let sdk = self
+            .gameservers
+            .iter()
+            .find(|sdk| sdk.state() == "Ready" && self.selected(sdk, params))
+            .ok_or(AllocationError::NoCapacity(
+                "no ready gameserver in the fleet".to_string(),
+            ))?;
+        sdk.set_state("Allocated");
+        debug!(
+            "gameserver allocated. name: {}, match_id: {}",
+            sdk.name(),
+            params.match_id
+        );
+        status(sdk)
+    }
+
+    async fn backfills(&self) -> anyhow::Result<Vec<Backfill>> {
+        let mut all_backfills = Vec::new();
+        for sdk in self.gameservers.iter() {
+            if sdk.state() != "Allocated" {
+                continue;
+            }
+            let address = match address(sdk) {
+                Some(address) => address,
+                None => continue,
+            };
+            let client = GameServerClientImpl::new(address.clone())
+                .await
+                .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+            let backfills = client
+                .get_backfills()
+                .await
+                .map_err(|err| anyhow::anyhow!(err.to_string()))?;
+            all_backfills.extend(backfills.into_iter().map(|b| Backfill {
+                match_id: b.match_id,
+                match_profile: b.match_profile,
+                open_slots: b.open_slots,
+                address: address.clone(),
+                region: b.region,
+                skill_rating: b.skill_rating,
+            }));
+        }
+        Ok(all_backfills)
+    }
+}
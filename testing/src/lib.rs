@@ -0,0 +1,73 @@
+//! In-memory stand-ins for Open Match and Agones, for tests and local development.
+//!
+//! The Open Match backend calls the match function configured in `FetchMatchesRequest` and
+//! accepts every proposal whose tickets are still available, so the whole pipeline can run
+//! without a cluster.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+mod agones;
+mod fleet;
+mod services;
+mod store;
+
+pub use agones::{FakeAgonesSdk, SdkService};
+pub use fleet::{FakeFleetAllocationClient, FLEET_LABEL};
+pub use services::{BackendService, FrontendService, QueryService};
+
+pub mod om {
+    tonic::include_proto!("openmatch");
+}
+
+pub mod sdk {
+    tonic::include_proto!("agones.dev.sdk");
+}
+
+#[derive(Clone, Default)]
+pub struct FakeOpenMatch {
+    store: Arc<Mutex<store::TicketStore>>,
+}
+
+impl FakeOpenMatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frontend_service(
+        &self,
+    ) -> om::frontend_service_server::FrontendServiceServer<FrontendService> {
+        om::frontend_service_server::FrontendServiceServer::new(FrontendService {
+            store: self.store.clone(),
+        })
+    }
+
+    pub fn backend_service(
+        &self,
+    ) -> om::backend_service_server::BackendServiceServer<BackendService> {
+        om::backend_service_server::BackendServiceServer::new(BackendService {
+            store: self.store.clone(),
+        })
+    }
+
+    pub fn query_service(&self) -> om::query_service_server::QueryServiceServer<QueryService> {
+        om::query_service_server::QueryServiceServer::new(QueryService {
+            store: self.store.clone(),
+        })
+    }
+
+    /// Returns all tickets, including pending and assigned ones.
+    pub fn tickets(&self) -> Vec<om::Ticket> {
+        self.store.lock().unwrap().all()
+    }
+
+    /// Serves the three services on one address.
+    pub async fn serve(self, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.frontend_service())
+            .add_service(self.backend_service())
+            .add_service(self.query_service())
+            .serve(address)
+            .await
+    }
+}
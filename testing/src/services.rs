@@ -0,0 +1,220 @@
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error};
+use tokio::sync::mpsc;
+
+use super::om;
+use super::store::TicketStore;
+
+fn not_found(ticket_id: &str) -> tonic::Status {
+    tonic::Status::new(
+        tonic::Code::NotFound,
+        format!("ticket not found. ticket_id: {}", ticket_id),
+    )
+}
+
+pub struct FrontendService {
+    pub(crate) store: Arc<Mutex<TicketStore>>,
+}
+
+#[tonic::async_trait]
+impl om::frontend_service_server::FrontendService for FrontendService {
+    async fn create_ticket(
+        &self,
+        request: tonic::Request<om::CreateTicketRequest>,
+    ) -> Result<tonic::Response<om::Ticket>, tonic::Status> {
+        let ticket = request.into_inner().ticket.ok_or(tonic::Status::new(
+            tonic::Code::InvalidArgument,
+            "ticket is not specified",
+        ))?;
+        let ticket = self.store.lock().unwrap().create(ticket);
+        debug!("ticket created. ticket_id: {}", ticket.id);
+        Ok(tonic::Response::new(ticket))
+    }
+
+    async fn delete_ticket(
+        &self,
+        request: tonic::Request<om::DeleteTicketRequest>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let ticket_id = request.into_inner().ticket_id;
+        self.store.lock().unwrap().delete(&ticket_id);
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn get_ticket(
+        &self,
+        request: tonic::Request<om::GetTicketRequest>,
+    ) -> Result<tonic::Response<om::Ticket>, tonic::Status> {
+        let ticket_id = request.into_inner().ticket_id;
+        let ticket = self
+            .store
+            .lock()
+            .unwrap()
+            .get(&ticket_id)
+            .ok_or_else(|| not_found(&ticket_id))?;
+        Ok(tonic::Response::new(ticket))
+    }
+
+    type WatchAssignmentsStream =
+        mpsc::Receiver<Result<om::WatchAssignmentsResponse, tonic::Status>>;
+
+    async fn watch_assignments(
+        &self,
+        request: tonic::Request<om::WatchAssignmentsRequest>,
+    ) -> Result<tonic::Response<Self::WatchAssignmentsStream>, tonic::Status> {
+        let ticket_id = request.into_inner().ticket_id;
+        let (tx, rx) = mpsc::channel(4);
+        if !self.store.lock().unwrap().watch(&ticket_id, tx) {
+            return Err(not_found(&ticket_id));
+        }
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+pub struct BackendService {
+    pub(crate) store: Arc<Mutex<TicketStore>>,
+}
+
+#[tonic::async_trait]
+impl om::backend_service_server::BackendService for BackendService {
+    type FetchMatchesStream = mpsc::Receiver<Result<om::FetchMatchesResponse, tonic::Status>>;
+
+    async fn fetch_matches(
+        &self,
+        request: tonic::Request<om::FetchMatchesRequest>,
+    ) -> Result<tonic::Response<Self::FetchMatchesStream>, tonic::Status> {
+        let req = request.into_inner();
+        let config = req.config.ok_or(tonic::Status::new(
+            tonic::Code::InvalidArgument,
+            "config is not specified",
+        ))?;
+        let profile = req.profile.ok_or(tonic::Status::new(
+            tonic::Code::InvalidArgument,
+            "profile is not specified",
+        ))?;
+        if config.r#type != om::function_config::Type::Grpc as i32 {
+            return Err(tonic::Status::new(
+                tonic::Code::Unimplemented,
+                "only grpc match functions are supported",
+            ));
+        }
+
+        let mut mmf_client = om::match_function_client::MatchFunctionClient::connect(format!(
+            "http://{}:{}",
+            config.host, config.port
+        ))
+        .await
+        .map_err(|err| tonic::Status::new(tonic::Code::Unavailable, err.to_string()))?;
+        let mut stream = mmf_client
+            .run(tonic::Request::new(om::RunRequest {
+                profile: Some(profile),
+            }))
+            .await?
+            .into_inner();
+
+        let (mut tx, rx) = mpsc::channel(16);
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            loop {
+                let proposal = match stream.message().await {
+                    Ok(Some(res)) => match res.proposal {
+                        Some(proposal) => proposal,
+                        None => continue,
+                    },
+                    Ok(None) => break,
+                    Err(status) => {
+                        error!("match function failed. status: {:?}", status);
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                if !store.lock().unwrap().propose(&proposal) {
+                    continue;
+                }
+                let res = om::FetchMatchesResponse {
+                    r#match: Some(proposal),
+                };
+                if tx.send(Ok(res)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(rx))
+    }
+
+    async fn assign_tickets(
+        &self,
+        request: tonic::Request<om::AssignTicketsRequest>,
+    ) -> Result<tonic::Response<om::AssignTicketsResponse>, tonic::Status> {
+        let groups = request.into_inner().assignments;
+        let failures = self.store.lock().unwrap().assign(groups);
+        Ok(tonic::Response::new(om::AssignTicketsResponse { failures }))
+    }
+
+    async fn release_tickets(
+        &self,
+        request: tonic::Request<om::ReleaseTicketsRequest>,
+    ) -> Result<tonic::Response<om::ReleaseTicketsResponse>, tonic::Status> {
+        let ticket_ids = request.into_inner().ticket_ids;
+        self.store.lock().unwrap().release(&ticket_ids);
+        Ok(tonic::Response::new(om::ReleaseTicketsResponse {}))
+    }
+
+    async fn release_all_tickets(
+        &self,
+        _request: tonic::Request<om::ReleaseAllTicketsRequest>,
+    ) -> Result<tonic::Response<om::ReleaseAllTicketsResponse>, tonic::Status> {
+        self.store.lock().unwrap().release_all();
+        Ok(tonic::Response::new(om::ReleaseAllTicketsResponse {}))
+    }
+}
+
+pub struct QueryService {
+    pub(crate) store: Arc<Mutex<TicketStore>>,
+}
+
+#[tonic::async_trait]
+impl om::query_service_server::QueryService for QueryService {
+    type QueryTicketsStream = mpsc::Receiver<Result<om::QueryTicketsResponse, tonic::Status>>;
+
+    async fn query_tickets(
+        &self,
+        request: tonic::Request<om::QueryTicketsRequest>,
+    ) -> Result<tonic::Response<Self::QueryTicketsStream>, tonic::Status> {
+        let pool = request.into_inner().pool.ok_or(tonic::Status::new(
+            tonic::Code::InvalidArgument,
+            "pool is not specified",
+        ))?;
+        let tickets = self.store.lock().unwrap().query(&pool);
+        let (mut tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let _ = tx.send(Ok(om::QueryTicketsResponse { tickets })).await;
+        });
+        Ok(tonic::Response::new(rx))
+    }
+
+    type QueryTicketIdsStream = mpsc::Receiver<Result<om::QueryTicketIdsResponse, tonic::Status>>;
+
+    async fn query_ticket_ids(
+        &self,
+        request: tonic::Request<om::QueryTicketIdsRequest>,
+    ) -> Result<tonic::Response<Self::QueryTicketIdsStream>, tonic::Status> {
+        let pool = request.into_inner().pool.ok_or(tonic::Status::new(
+            tonic::Code::InvalidArgument,
+            "pool is not specified",
+        ))?;
+        let ids = self
+            .store
+            .lock()
+            .unwrap()
+            .query(&pool)
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        let (mut tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let _ = tx.send(Ok(om::QueryTicketIdsResponse { ids })).await;
+        });
+        Ok(tonic::Response::new(rx))
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+use log::debug;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::om;
+
+pub type AssignmentSender = mpsc::Sender<Result<om::WatchAssignmentsResponse, tonic::Status>>;
+
+/// In-memory state shared by the fake services.
+///
+/// Tickets of proposed matches are pending until they are assigned or released, and assigned
+/// tickets are no longer returned by queries, like in Open Match.
+#[derive(Default)]
+pub struct TicketStore {
+    tickets: HashMap<String, om::Ticket>,
+    pending: HashSet<String>,
+    watchers: HashMap<String, Vec<AssignmentSender>>,
+}
+
+impl TicketStore {
+    pub fn create(&mut self, mut ticket: om::Ticket) -> om::Ticket {
+        ticket.id = Uuid::new_v4().to_string();
+        ticket.assignment = None;
+        ticket.create_time = Some(SystemTime::now().into());
+        self.tickets.insert(ticket.id.clone(), ticket.clone());
+        ticket
+    }
+
+    pub fn get(&self, ticket_id: &str) -> Option<om::Ticket> {
+        self.tickets.get(ticket_id).cloned()
+    }
+
+    pub fn delete(&mut self, ticket_id: &str) {
+        self.tickets.remove(ticket_id);
+        self.pending.remove(ticket_id);
+        self.watchers.remove(ticket_id);
+    }
+
+    pub fn all(&self) -> Vec<om::Ticket> {
+        self.tickets.values().cloned().collect()
+    }
+
+    /// Returns the tickets in the pool that are neither pending nor assigned.
+    pub fn query(&self, pool: &om::Pool) -> Vec<om::Ticket> {
+        let mut tickets: Vec<om::Ticket> = self
+            .tickets
+            .values()
+            .filter(|t| t.assignment.is_none() && !self.pending.contains(&t.id))
+            .filter(|t| in_pool(t, pool))
+            .cloned()
+            .collect();
+        tickets.sort_by(|a, b| a.id.cmp(&b.id));
+        tickets
+    }
+
+    /// Marks the tickets of the proposal as pending. Proposals with a ticket that is unknown,
+    /// pending or assigned are rejected, which is all the evaluation the fake does.
+    pub fn propose(&mut self, proposal: &om::Match) -> bool {
+        let available = proposal.tickets.iter().all(|t| {
+            !self.pending.contains(&t.id)
+                && self
+                    .tickets
+                    .get(&t.id)
+                    .map_or(false, |t| t.assignment.is_none())
+        });
+        if !available {
+            debug!("proposal rejected. match_id: {}", proposal.match_id);
+            return false;
+        }
+        for t in proposal.tickets.iter() {
+            self.pending.insert(t.id.clone());
+        }
+        true
+    }
+
+    pub fn assign(&mut self, groups: Vec<om::AssignmentGroup>) -> Vec<om::AssignmentFailure> {
+        let mut failures = Vec::new();
+        for group in groups {
+            for ticket_id in group.ticket_ids {
+                let ticket = match self.tickets.get_mut(&ticket_id) {
+                    Some(ticket) => ticket,
+                    None => {
+                        failures.push(om::AssignmentFailure {
+                            ticket_id,
+                            cause: om::assignment_failure::Cause::TicketNotFound as i32,
+                        });
+                        continue;
+                    }
+                };
+                ticket.assignment = group.assignment.clone();
+                self.pending.remove(&ticket_id);
+                // watchers whose stream is gone or full are dropped
+                let watchers: Vec<AssignmentSender> = self
+                    .watchers
+                    .remove(&ticket_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|mut tx| {
+                        tx.try_send(Ok(om::WatchAssignmentsResponse {
+                            assignment: group.assignment.clone(),
+                        }))
+                        .ok()
+                        .map(|_| tx)
+                    })
+                    .collect();
+                self.watchers.insert(ticket_id, watchers);
+            }
+        }
+        failures
+    }
+
+    pub fn release(&mut self, ticket_ids: &[String]) {
+        for ticket_id in ticket_ids {
+            self.pending.remove(ticket_id);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Registers a watcher of the ticket's assignment. The current assignment, if any, is sent
+    /// right away.
+    pub fn watch(&mut self, ticket_id: &str, mut tx: AssignmentSender) -> bool {
+        let ticket = match self.tickets.get(ticket_id) {
+            Some(ticket) => ticket,
+            None => return false,
+        };
+        if ticket.assignment.is_some() {
+            let _ = tx.try_send(Ok(om::WatchAssignmentsResponse {
+                assignment: ticket.assignment.clone(),
+            }));
+        }
+        self.watchers
+            .entry(ticket_id.to_string())
+            .or_default()
+            .push(tx);
+        true
+    }
+}
+
+fn in_pool(ticket: &om::Ticket, pool: &om::Pool) -> bool {
+    let default_fields = om::SearchFields::default();
+    let fields = ticket.search_fields.as_ref().unwrap_or(&default_fields);
+    let doubles = pool.double_range_filters.iter().all(|f| {
+        fields
+            .double_args
+            .get(&f.double_arg)
+            .map_or(false, |v| f.min <= *v && *v <= f.max)
+    });
+    let strings = pool.string_equals_filters.iter().all(|f| {
+        fields
+            .string_args
+            .get(&f.string_arg)
+            .map_or(false, |v| *v == f.value)
+    });
+    let tags = pool
+        .tag_present_filters
+        .iter()
+        .all(|f| fields.tags.contains(&f.tag));
+    doubles && strings && tags && created_in(ticket, pool)
+}
+
+fn created_in(ticket: &om::Ticket, pool: &om::Pool) -> bool {
+    let created = match &ticket.create_time {
+        Some(t) => (t.seconds, t.nanos),
+        None => return pool.created_before.is_none() && pool.created_after.is_none(),
+    };
+    let before = pool
+        .created_before
+        .as_ref()
+        .map_or(true, |t| created < (t.seconds, t.nanos));
+    let after = pool
+        .created_after
+        .as_ref()
+        .map_or(true, |t| created > (t.seconds, t.nanos));
+    before && after
+}
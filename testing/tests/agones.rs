@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::Value;
+use tokio::sync::watch;
+use tokio::time::{delay_for, timeout};
+
+use director_worker::{
+    AgonesGameServerAllocationClient, AllocationError, AllocationParams, AllocationPolicy,
+    GameServerAllocationClient,
+};
+use testing::{sdk, FakeAgonesSdk, FakeFleetAllocationClient, FLEET_LABEL};
+
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn gameserver(name: &str, port: i32, fleet: &str, region: &str) -> FakeAgonesSdk {
+    let mut labels = HashMap::new();
+    labels.insert(FLEET_LABEL.to_string(), fleet.to_string());
+    labels.insert("region".to_string(), region.to_string());
+    let sdk = FakeAgonesSdk::new(name, "127.0.0.1", port, labels);
+    sdk.set_state("Ready");
+    sdk
+}
+
+fn params(fleet: Option<&str>, region: Option<&str>) -> AllocationParams {
+    AllocationParams {
+        match_id: "match".to_string(),
+        fleet: fleet.map(|f| f.to_string()),
+        region: region.map(|r| r.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn fleet_allocates_matching_ready_gameservers() {
+    let asia = gameserver("gs-asia", 7000, "gameserver", "asia");
+    let us = gameserver("gs-us", 7001, "gameserver", "us");
+    let client =
+        FakeFleetAllocationClient::new(vec![asia.clone(), us.clone()], "region".to_string());
+
+    client
+        .allocate(&params(Some("gameserver"), Some("us")))
+        .await
+        .unwrap();
+    assert_eq!(us.state(), "Allocated");
+    assert_eq!(asia.state(), "Ready");
+
+    let err = client
+        .allocate(&params(None, Some("us")))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AllocationError::NoCapacity(_)));
+    let err = client
+        .allocate(&params(Some("other"), None))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AllocationError::NoCapacity(_)));
+}
+
+#[tokio::test]
+async fn fleet_replaces_shutdown_gameservers() {
+    let gs = gameserver("gs", 7000, "gameserver", "asia");
+    let client = FakeFleetAllocationClient::new(vec![gs.clone()], "region".to_string());
+
+    client.allocate(&params(None, None)).await.unwrap();
+    assert!(client.allocate(&params(None, None)).await.is_err());
+    gs.set_state("Shutdown");
+    client.allocate(&params(None, None)).await.unwrap();
+    assert_eq!(gs.state(), "Allocated");
+}
+
+#[tokio::test]
+async fn sdk_updates_and_watches_the_gameserver() {
+    let address = free_address();
+    let gs = FakeAgonesSdk::new("gs", "127.0.0.1", 7000, HashMap::new());
+    tokio::spawn(gs.clone().serve(address));
+    delay_for(Duration::from_millis(100)).await;
+
+    let mut client = sdk::sdk_client::SdkClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+    let mut watch = client
+        .watch_game_server(tonic::Request::new(sdk::Empty {}))
+        .await
+        .unwrap()
+        .into_inner();
+    let current = watch.message().await.unwrap().unwrap();
+    assert_eq!(current.status.unwrap().state, "Scheduled");
+
+    client
+        .ready(tonic::Request::new(sdk::Empty {}))
+        .await
+        .unwrap();
+    let updated = timeout(Duration::from_secs(1), watch.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(updated.status.unwrap().state, "Ready");
+
+    client
+        .set_label(tonic::Request::new(sdk::KeyValue {
+            key: "mode".to_string(),
+            value: "ranked".to_string(),
+        }))
+        .await
+        .unwrap();
+    let gameserver = client
+        .get_game_server(tonic::Request::new(sdk::Empty {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        gameserver
+            .object_meta
+            .unwrap()
+            .labels
+            .get("agones.dev/sdk-mode"),
+        Some(&"ranked".to_string())
+    );
+}
+
+const ALLOCATED: &str = r#"{
+    "kind": "GameServerAllocation",
+    "apiVersion": "allocation.agones.dev/v1",
+    "status": {"state": "Allocated", "address": "127.0.0.1", "ports": [{"name": "default", "port": 7000}]}
+}"#;
+
+/// Answers every GameServerAllocation and keeps the requests.
+async fn fake_api_server(address: SocketAddr, requests: Arc<Mutex<Vec<Value>>>) {
+    let make_service = make_service_fn(move |_| {
+        let requests = requests.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                let requests = requests.clone();
+                async move {
+                    let body = hyper::body::to_bytes(req.into_body()).await?;
+                    requests
+                        .lock()
+                        .unwrap()
+                        .push(serde_json::from_slice(&body).unwrap());
+                    Ok::<_, hyper::Error>(Response::new(Body::from(ALLOCATED)))
+                }
+            }))
+        }
+    });
+    Server::bind(&address).serve(make_service).await.unwrap();
+}
+
+#[tokio::test]
+async fn reloaded_allocation_policies_are_used() {
+    let address = free_address();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(fake_api_server(address, requests.clone()));
+    delay_for(Duration::from_millis(100)).await;
+
+    let policy = AllocationPolicy {
+        fleet_name: "old-fleet".to_string(),
+        ..Default::default()
+    };
+    let (tx, rx) = watch::channel(policy.clone());
+    let client = AgonesGameServerAllocationClient::with_api_server(
+        format!("http://{}", address),
+        "default".to_string(),
+        policy,
+    )
+    .with_policy_updates(rx);
+
+    client.allocate(&params(None, None)).await.unwrap();
+    tx.broadcast(AllocationPolicy {
+        fleet_name: "new-fleet".to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    client.allocate(&params(None, None)).await.unwrap();
+
+    let fleets: Vec<Value> = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|req| req["spec"]["required"]["matchLabels"]["agones.dev/fleet"].clone())
+        .collect();
+    assert_eq!(fleets, vec!["old-fleet", "new-fleet"]);
+}
@@ -0,0 +1,584 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{delay_for, timeout};
+
+use director_worker::profiles::ProfileConfig;
+use director_worker::{
+    AllocationError, AllocationParams, CancellationToken, Director, DirectorConfig,
+    GameServerAllocationClient, MatchProfiles, OpenMatchDirector, RetryPolicy, Status, Worker,
+    WorkerConfig,
+};
+use testing::{om, FakeAgonesSdk, FakeFleetAllocationClient, FakeOpenMatch};
+
+type FrontendClient = om::frontend_service_client::FrontendServiceClient<tonic::transport::Channel>;
+type QueryClient = om::query_service_client::QueryServiceClient<tonic::transport::Channel>;
+
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Pairs the tickets of the first pool of the profile, and fails after `fail_after`
+/// proposals when it is set. With `teams` the proposals ask for a reservation.
+struct PairMatchFunction {
+    query_address: SocketAddr,
+    fail_after: Option<usize>,
+    teams: bool,
+}
+
+#[tonic::async_trait]
+impl om::match_function_server::MatchFunction for PairMatchFunction {
+    type RunStream = mpsc::Receiver<Result<om::RunResponse, tonic::Status>>;
+
+    async fn run(
+        &self,
+        request: tonic::Request<om::RunRequest>,
+    ) -> Result<tonic::Response<Self::RunStream>, tonic::Status> {
+        let profile = request.into_inner().profile.unwrap();
+        let mut client = QueryClient::connect(format!("http://{}", self.query_address))
+            .await
+            .map_err(|err| tonic::Status::new(tonic::Code::Unavailable, err.to_string()))?;
+        let tickets = query(&mut client, profile.pools[0].clone()).await;
+        let (mut tx, rx) = mpsc::channel(16);
+        let fail_after = self.fail_after;
+        let mut extensions = HashMap::new();
+        if self.teams {
+            extensions.insert("teams".to_string(), om_ext::list_any(Vec::new()).unwrap());
+        }
+        tokio::spawn(async move {
+            for (i, pair) in tickets.chunks_exact(2).enumerate() {
+                if fail_after == Some(i) {
+                    let _ = tx
+                        .send(Err(tonic::Status::new(
+                            tonic::Code::Internal,
+                            "mmf crashed",
+                        )))
+                        .await;
+                    break;
+                }
+                let proposal = om::Match {
+                    match_id: format!("{}-{}-{}", profile.name, pair[0].id, i),
+                    match_profile: profile.name.clone(),
+                    match_function: "pair".to_string(),
+                    tickets: pair.to_vec(),
+                    extensions: extensions.clone(),
+                };
+                let res = om::RunResponse {
+                    proposal: Some(proposal),
+                };
+                if tx.send(Ok(res)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+async fn query(client: &mut QueryClient, pool: om::Pool) -> Vec<om::Ticket> {
+    let mut stream = client
+        .query_tickets(tonic::Request::new(om::QueryTicketsRequest {
+            pool: Some(pool),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut tickets = Vec::new();
+    while let Some(res) = stream.message().await.unwrap() {
+        tickets.extend(res.tickets);
+    }
+    tickets
+}
+
+/// A fleet whose allocation API fails `api_errors` times before it answers, and takes
+/// `delay` for every call.
+struct FakeAllocator {
+    fleet: FakeFleetAllocationClient,
+    api_errors: Mutex<u32>,
+    delay: Duration,
+    calls: Arc<AtomicU32>,
+}
+
+impl FakeAllocator {
+    fn slow(ready_gameservers: usize, delay: Duration) -> Self {
+        FakeAllocator {
+            delay,
+            ..Self::new(ready_gameservers)
+        }
+    }
+
+    fn new(ready_gameservers: usize) -> Self {
+        let gameservers = (0..ready_gameservers)
+            .map(|i| {
+                let sdk = FakeAgonesSdk::new(
+                    &format!("gameserver-{}", i),
+                    "127.0.0.1",
+                    7000 + i as i32,
+                    HashMap::new(),
+                );
+                sdk.set_state("Ready");
+                sdk
+            })
+            .collect();
+        FakeAllocator {
+            fleet: FakeFleetAllocationClient::new(gameservers, "region".to_string()),
+            api_errors: Mutex::new(0),
+            delay: Duration::from_millis(0),
+            calls: Arc::new(AtomicU32::new(0)),
+        }
+    }
+}
+
+#[async_trait]
+impl GameServerAllocationClient for FakeAllocator {
+    async fn allocate(&self, params: &AllocationParams) -> Result<Status, AllocationError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        delay_for(self.delay).await;
+        {
+            let mut api_errors = self.api_errors.lock().unwrap();
+            if *api_errors > 0 {
+                *api_errors -= 1;
+                return Err(AllocationError::Api(anyhow::anyhow!("connection reset")));
+            }
+        }
+        self.fleet.allocate(params).await
+    }
+}
+
+struct Pipeline {
+    om: FakeOpenMatch,
+    om_address: SocketAddr,
+    mmf_address: SocketAddr,
+    frontend: FrontendClient,
+    query: QueryClient,
+}
+
+impl Pipeline {
+    async fn start() -> Self {
+        Self::start_with(None).await
+    }
+
+    async fn start_with(fail_after: Option<usize>) -> Self {
+        Self::start_with_mmf(fail_after, false).await
+    }
+
+    async fn start_with_mmf(fail_after: Option<usize>, teams: bool) -> Self {
+        let om = FakeOpenMatch::new();
+        let om_address = free_address();
+        let mmf_address = free_address();
+        tokio::spawn(om.clone().serve(om_address));
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(om::match_function_server::MatchFunctionServer::new(
+                    PairMatchFunction {
+                        query_address: om_address,
+                        fail_after,
+                        teams,
+                    },
+                ))
+                .serve(mmf_address),
+        );
+        delay_for(Duration::from_millis(100)).await;
+        let url = format!("http://{}", om_address);
+        Pipeline {
+            om,
+            om_address,
+            mmf_address,
+            frontend: FrontendClient::connect(url.clone()).await.unwrap(),
+            query: QueryClient::connect(url).await.unwrap(),
+        }
+    }
+
+    /// The default profile, run by the pair match function.
+    fn profile(&self) -> ProfileConfig {
+        let mut profile = ProfileConfig::default_profile();
+        profile.match_function.host = Some(self.mmf_address.ip().to_string());
+        profile.match_function.port = Some(self.mmf_address.port() as i32);
+        profile
+    }
+
+    async fn director(
+        &self,
+        allocator: FakeAllocator,
+        config: DirectorConfig,
+    ) -> OpenMatchDirector<FakeAllocator> {
+        OpenMatchDirector::new(
+            allocator,
+            self.om_address.to_string(),
+            vec![self.profile()],
+            "default".to_string(),
+            config,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn create_tickets(&mut self, count: usize) -> HashSet<String> {
+        let mut ids = HashSet::new();
+        for _ in 0..count {
+            let ticket = self
+                .frontend
+                .create_ticket(tonic::Request::new(om::CreateTicketRequest {
+                    ticket: Some(om::Ticket::default()),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            ids.insert(ticket.id);
+        }
+        ids
+    }
+
+    async fn delete_ticket(&mut self, ticket_id: &str) {
+        self.frontend
+            .delete_ticket(tonic::Request::new(om::DeleteTicketRequest {
+                ticket_id: ticket_id.to_string(),
+            }))
+            .await
+            .unwrap();
+    }
+
+    fn assigned(&self) -> HashSet<String> {
+        self.om
+            .tickets()
+            .into_iter()
+            .filter(|t| t.assignment.is_some())
+            .map(|t| t.id)
+            .collect()
+    }
+
+    /// Tickets that are neither pending nor assigned.
+    async fn available(&mut self) -> HashSet<String> {
+        query(&mut self.query, om::Pool::default())
+            .await
+            .into_iter()
+            .map(|t| t.id)
+            .collect()
+    }
+}
+
+fn retry_policy(no_capacity_backoff: Duration) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+        multiplier: 2.0,
+        no_capacity_backoff,
+    }
+}
+
+#[tokio::test]
+async fn api_errors_are_retried() {
+    let mut pipeline = Pipeline::start().await;
+    pipeline.create_tickets(2).await;
+    let allocator = FakeAllocator::new(1);
+    *allocator.api_errors.lock().unwrap() = 2;
+    let calls = allocator.calls.clone();
+    let mut director = pipeline
+        .director(
+            allocator,
+            DirectorConfig {
+                retry_policy: retry_policy(Duration::from_millis(0)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    let summary = director.assign(&CancellationToken::new()).await.unwrap();
+    assert_eq!(summary.assigned, 1);
+    assert_eq!(summary.failed, 0);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert!(pipeline.available().await.is_empty());
+}
+
+#[tokio::test]
+async fn full_fleets_are_left_to_the_next_cycle() {
+    let mut pipeline = Pipeline::start().await;
+    let tickets = pipeline.create_tickets(2).await;
+    let allocator = FakeAllocator::new(0);
+    let calls = allocator.calls.clone();
+    let mut director = pipeline
+        .director(
+            allocator,
+            DirectorConfig {
+                retry_policy: retry_policy(Duration::from_millis(0)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    let summary = director.assign(&CancellationToken::new()).await.unwrap();
+    assert_eq!(summary.fetched, 1);
+    assert_eq!(summary.failed, 1);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    // the tickets of the unplaced match are back in the pool
+    assert_eq!(pipeline.available().await, tickets);
+}
+
+#[tokio::test]
+async fn full_fleets_are_retried_with_their_own_backoff() {
+    let mut pipeline = Pipeline::start().await;
+    let tickets = pipeline.create_tickets(2).await;
+    let allocator = FakeAllocator::new(0);
+    let calls = allocator.calls.clone();
+    let mut director = pipeline
+        .director(
+            allocator,
+            DirectorConfig {
+                retry_policy: retry_policy(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    let started = std::time::Instant::now();
+    let summary = director.assign(&CancellationToken::new()).await.unwrap();
+    assert_eq!(summary.failed, 1);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(pipeline.available().await, tickets);
+}
+
+#[tokio::test]
+async fn tickets_that_fail_to_assign_are_released() {
+    let mut pipeline = Pipeline::start().await;
+    let tickets = pipeline.create_tickets(4).await;
+    let allocator = FakeAllocator::slow(2, Duration::from_millis(300));
+    let mut director = pipeline
+        .director(allocator, DirectorConfig::default())
+        .await;
+
+    // a player gives up while their match is being placed
+    let gone = tickets.iter().next().unwrap().clone();
+    let cancel = CancellationToken::new();
+    let (summary, _) = tokio::join!(director.assign(&cancel), async {
+        delay_for(Duration::from_millis(100)).await;
+        pipeline.delete_ticket(&gone).await;
+    });
+    let summary = summary.unwrap();
+    assert_eq!(summary.fetched, 2);
+    assert_eq!(summary.assigned, 1);
+    assert_eq!(summary.failed, 1);
+    assert_eq!(pipeline.assigned().len(), 3);
+}
+
+#[tokio::test]
+async fn matches_the_gameserver_didnt_reserve_are_released() {
+    let mut pipeline = Pipeline::start_with_mmf(None, true).await;
+    let tickets = pipeline.create_tickets(2).await;
+    // nothing listens on the address of the allocated gameserver
+    let mut director = pipeline
+        .director(
+            FakeAllocator::new(1),
+            DirectorConfig {
+                retry_policy: retry_policy(Duration::from_millis(0)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    let summary = director.assign(&CancellationToken::new()).await.unwrap();
+    assert_eq!(summary.assigned, 0);
+    assert_eq!(summary.failed, 1);
+    assert!(pipeline.assigned().is_empty());
+    assert_eq!(pipeline.available().await, tickets);
+}
+
+#[tokio::test]
+async fn matches_being_placed_are_assigned_when_the_fetch_fails() {
+    let mut pipeline = Pipeline::start_with(Some(1)).await;
+    let tickets = pipeline.create_tickets(4).await;
+    let allocator = FakeAllocator::slow(2, Duration::from_millis(200));
+    let mut director = pipeline
+        .director(allocator, DirectorConfig::default())
+        .await;
+
+    assert!(director.assign(&CancellationToken::new()).await.is_err());
+    let assigned = pipeline.assigned();
+    assert_eq!(assigned.len(), 2);
+    // the tickets the mmf didn't get to are still in the pool
+    let available = pipeline.available().await;
+    assert_eq!(available.len(), 2);
+    assert!(available.union(&assigned).all(|id| tickets.contains(id)));
+}
+
+#[tokio::test]
+async fn cycles_past_their_deadline_release_the_tickets_they_didnt_place() {
+    let mut pipeline = Pipeline::start().await;
+    let tickets = pipeline.create_tickets(4).await;
+    let allocator = FakeAllocator::slow(2, Duration::from_millis(300));
+    let calls = allocator.calls.clone();
+    let director = pipeline
+        .director(
+            allocator,
+            DirectorConfig {
+                max_concurrent_assignments: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+    let mut worker = Worker::new(
+        director,
+        WorkerConfig {
+            interval: Duration::from_secs(60),
+            jitter: Duration::from_millis(0),
+            cycle_timeout: Duration::from_millis(100),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let cancel = CancellationToken::new();
+    let worker_cancel = cancel.clone();
+    let worker = tokio::spawn(async move { worker.run(worker_cancel).await });
+    delay_for(Duration::from_millis(600)).await;
+
+    // the allocation in flight at the deadline is finished, and the other match is released
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    let assigned = pipeline.assigned();
+    assert_eq!(assigned.len(), 2);
+    let available = pipeline.available().await;
+    assert_eq!(available.len(), 2);
+    assert!(available.union(&assigned).all(|id| tickets.contains(id)));
+
+    cancel.cancel();
+    worker.await.unwrap();
+}
+
+#[tokio::test]
+async fn cycles_that_dont_finish_after_their_deadline_are_dropped() {
+    let mut pipeline = Pipeline::start().await;
+    pipeline.create_tickets(2).await;
+    // the allocation hangs well past the deadline and the grace period
+    let allocator = FakeAllocator::slow(1, Duration::from_secs(10));
+    let calls = allocator.calls.clone();
+    let director = pipeline
+        .director(allocator, DirectorConfig::default())
+        .await;
+    let mut worker = Worker::new(
+        director,
+        WorkerConfig {
+            interval: Duration::from_secs(60),
+            jitter: Duration::from_millis(0),
+            cycle_timeout: Duration::from_millis(100),
+            cancel_grace: Duration::from_millis(100),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let cancel = CancellationToken::new();
+    let worker_cancel = cancel.clone();
+    let worker = tokio::spawn(async move { worker.run(worker_cancel).await });
+    delay_for(Duration::from_millis(400)).await;
+    cancel.cancel();
+
+    // the worker waits for the next cycle instead of the allocation
+    timeout(Duration::from_secs(1), worker)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn cancelled_workers_release_the_tickets_they_didnt_place() {
+    let mut pipeline = Pipeline::start().await;
+    let tickets = pipeline.create_tickets(6).await;
+    let allocator = FakeAllocator::slow(3, Duration::from_millis(300));
+    let calls = allocator.calls.clone();
+    let director = pipeline
+        .director(
+            allocator,
+            DirectorConfig {
+                max_concurrent_assignments: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+    let mut worker = Worker::new(director, WorkerConfig::default()).unwrap();
+
+    // the pod is told to stop while the first match is being placed
+    let cancel = CancellationToken::new();
+    let (_, _) = tokio::join!(worker.run(cancel.clone()), async {
+        delay_for(Duration::from_millis(100)).await;
+        cancel.cancel();
+    });
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    let assigned = pipeline.assigned();
+    assert_eq!(assigned.len(), 2);
+    let available = pipeline.available().await;
+    assert_eq!(available.len(), 4);
+    assert!(available.union(&assigned).all(|id| tickets.contains(id)));
+}
+
+#[tokio::test]
+async fn cancelled_workers_stop_within_their_grace_period() {
+    let mut pipeline = Pipeline::start().await;
+    pipeline.create_tickets(2).await;
+    let allocator = FakeAllocator::slow(1, Duration::from_secs(10));
+    let calls = allocator.calls.clone();
+    let director = pipeline
+        .director(allocator, DirectorConfig::default())
+        .await;
+    let mut worker = Worker::new(
+        director,
+        WorkerConfig {
+            cancel_grace: Duration::from_millis(100),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // the pod is told to stop while the allocation hangs
+    let cancel = CancellationToken::new();
+    let worker_cancel = cancel.clone();
+    let worker = tokio::spawn(async move { worker.run(worker_cancel).await });
+    delay_for(Duration::from_millis(200)).await;
+    cancel.cancel();
+
+    timeout(Duration::from_secs(1), worker)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn reloaded_profiles_are_used() {
+    let mut pipeline = Pipeline::start().await;
+    pipeline.create_tickets(4).await;
+    let director = pipeline
+        .director(
+            FakeAllocator::new(2),
+            DirectorConfig {
+                retry_policy: retry_policy(Duration::from_millis(0)),
+                ..Default::default()
+            },
+        )
+        .await;
+    let profiles = MatchProfiles::new(&[pipeline.profile()], "default").unwrap();
+    let (tx, rx) = watch::channel(profiles);
+    let mut director = director.with_profile_updates(rx);
+
+    let summary = director.assign(&CancellationToken::new()).await.unwrap();
+    assert_eq!(summary.assigned, 1);
+
+    // the profile now allocates from a fleet without gameservers
+    let mut profile = pipeline.profile();
+    profile.fleet = Some("ranked".to_string());
+    tx.broadcast(MatchProfiles::new(&[profile], "default").unwrap())
+        .unwrap();
+    let summary = director.assign(&CancellationToken::new()).await.unwrap();
+    assert_eq!(summary.fetched, 1);
+    assert_eq!(summary.failed, 1);
+}
@@ -0,0 +1,316 @@
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::{delay_for, timeout};
+
+use testing::{om, FakeOpenMatch};
+
+type FrontendClient = om::frontend_service_client::FrontendServiceClient<tonic::transport::Channel>;
+type BackendClient = om::backend_service_client::BackendServiceClient<tonic::transport::Channel>;
+type QueryClient = om::query_service_client::QueryServiceClient<tonic::transport::Channel>;
+
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Pairs the tickets of the first pool of the profile.
+struct PairMatchFunction {
+    query_address: SocketAddr,
+}
+
+#[tonic::async_trait]
+impl om::match_function_server::MatchFunction for PairMatchFunction {
+    type RunStream = mpsc::Receiver<Result<om::RunResponse, tonic::Status>>;
+
+    async fn run(
+        &self,
+        request: tonic::Request<om::RunRequest>,
+    ) -> Result<tonic::Response<Self::RunStream>, tonic::Status> {
+        let profile = request.into_inner().profile.unwrap();
+        let mut client = QueryClient::connect(format!("http://{}", self.query_address))
+            .await
+            .map_err(|err| tonic::Status::new(tonic::Code::Unavailable, err.to_string()))?;
+        let mut stream = client
+            .query_tickets(tonic::Request::new(om::QueryTicketsRequest {
+                pool: Some(profile.pools[0].clone()),
+            }))
+            .await?
+            .into_inner();
+        let mut tickets = Vec::new();
+        while let Some(res) = stream.message().await? {
+            tickets.extend(res.tickets);
+        }
+
+        let (mut tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            for (i, pair) in tickets.chunks_exact(2).enumerate() {
+                let proposal = om::Match {
+                    match_id: format!("{}-{}", profile.name, i),
+                    match_profile: profile.name.clone(),
+                    match_function: "pair".to_string(),
+                    tickets: pair.to_vec(),
+                    ..Default::default()
+                };
+                let res = om::RunResponse {
+                    proposal: Some(proposal),
+                };
+                if tx.send(Ok(res)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(tonic::Response::new(rx))
+    }
+}
+
+struct Pipeline {
+    frontend: FrontendClient,
+    backend: BackendClient,
+    query: QueryClient,
+    mmf_address: SocketAddr,
+}
+
+async fn start() -> Pipeline {
+    let om_address = free_address();
+    let mmf_address = free_address();
+    tokio::spawn(FakeOpenMatch::new().serve(om_address));
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(om::match_function_server::MatchFunctionServer::new(
+                PairMatchFunction {
+                    query_address: om_address,
+                },
+            ))
+            .serve(mmf_address),
+    );
+    delay_for(Duration::from_millis(100)).await;
+
+    let url = format!("http://{}", om_address);
+    Pipeline {
+        frontend: FrontendClient::connect(url.clone()).await.unwrap(),
+        backend: BackendClient::connect(url.clone()).await.unwrap(),
+        query: QueryClient::connect(url).await.unwrap(),
+        mmf_address,
+    }
+}
+
+fn mode_pool(mode: &str) -> om::Pool {
+    om::Pool {
+        name: mode.to_string(),
+        string_equals_filters: vec![om::StringEqualsFilter {
+            string_arg: "game_mode".to_string(),
+            value: mode.to_string(),
+        }],
+        ..Default::default()
+    }
+}
+
+async fn create_ticket(client: &mut FrontendClient, mode: &str, skill_rating: f64) -> om::Ticket {
+    let mut search_fields = om::SearchFields::default();
+    search_fields
+        .string_args
+        .insert("game_mode".to_string(), mode.to_string());
+    search_fields
+        .double_args
+        .insert("skill_rating".to_string(), skill_rating);
+    client
+        .create_ticket(tonic::Request::new(om::CreateTicketRequest {
+            ticket: Some(om::Ticket {
+                search_fields: Some(search_fields),
+                ..Default::default()
+            }),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+async fn query(client: &mut QueryClient, pool: om::Pool) -> Vec<om::Ticket> {
+    let mut stream = client
+        .query_tickets(tonic::Request::new(om::QueryTicketsRequest {
+            pool: Some(pool),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut tickets = Vec::new();
+    while let Some(res) = stream.message().await.unwrap() {
+        tickets.extend(res.tickets);
+    }
+    tickets
+}
+
+async fn fetch_matches(pipeline: &mut Pipeline, pool: om::Pool) -> Vec<om::Match> {
+    let req = om::FetchMatchesRequest {
+        config: Some(om::FunctionConfig {
+            host: pipeline.mmf_address.ip().to_string(),
+            port: pipeline.mmf_address.port() as i32,
+            r#type: om::function_config::Type::Grpc as i32,
+        }),
+        profile: Some(om::MatchProfile {
+            name: "test".to_string(),
+            pools: vec![pool],
+            ..Default::default()
+        }),
+    };
+    let mut stream = pipeline
+        .backend
+        .fetch_matches(tonic::Request::new(req))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut matches = Vec::new();
+    while let Some(res) = stream.message().await.unwrap() {
+        matches.extend(res.r#match);
+    }
+    matches
+}
+
+#[tokio::test]
+async fn query_filters_tickets_by_pool() {
+    let mut pipeline = start().await;
+    let expected = create_ticket(&mut pipeline.frontend, "ranked", 10.0).await;
+    create_ticket(&mut pipeline.frontend, "ranked", 50.0).await;
+    create_ticket(&mut pipeline.frontend, "casual", 10.0).await;
+
+    let mut pool = mode_pool("ranked");
+    pool.double_range_filters.push(om::DoubleRangeFilter {
+        double_arg: "skill_rating".to_string(),
+        min: 0.0,
+        max: 20.0,
+    });
+    let tickets = query(&mut pipeline.query, pool).await;
+
+    assert_eq!(tickets.len(), 1);
+    assert_eq!(tickets[0].id, expected.id);
+}
+
+#[tokio::test]
+async fn assignments_are_sent_to_watchers() {
+    let mut pipeline = start().await;
+    for _ in 0..3 {
+        create_ticket(&mut pipeline.frontend, "ranked", 10.0).await;
+    }
+
+    let matches = fetch_matches(&mut pipeline, mode_pool("ranked")).await;
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].tickets.len(), 2);
+
+    let mut watch = pipeline
+        .frontend
+        .watch_assignments(tonic::Request::new(om::WatchAssignmentsRequest {
+            ticket_id: matches[0].tickets[0].id.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let assignment = om::Assignment {
+        connection: "127.0.0.1:7777".to_string(),
+        ..Default::default()
+    };
+    let res = pipeline
+        .backend
+        .assign_tickets(tonic::Request::new(om::AssignTicketsRequest {
+            assignments: vec![om::AssignmentGroup {
+                ticket_ids: matches[0].tickets.iter().map(|t| t.id.clone()).collect(),
+                assignment: Some(assignment.clone()),
+            }],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(res.failures.is_empty());
+
+    let res = timeout(Duration::from_secs(1), watch.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.assignment, Some(assignment));
+    // assigned tickets leave the pools
+    assert_eq!(
+        query(&mut pipeline.query, mode_pool("ranked")).await.len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn assigning_unknown_ticket_fails() {
+    let mut pipeline = start().await;
+    let res = pipeline
+        .backend
+        .assign_tickets(tonic::Request::new(om::AssignTicketsRequest {
+            assignments: vec![om::AssignmentGroup {
+                ticket_ids: vec!["unknown".to_string()],
+                assignment: Some(om::Assignment::default()),
+            }],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(res.failures.len(), 1);
+    assert_eq!(res.failures[0].ticket_id, "unknown");
+}
+
+#[tokio::test]
+async fn released_tickets_return_to_pools() {
+    let mut pipeline = start().await;
+    for _ in 0..2 {
+        create_ticket(&mut pipeline.frontend, "ranked", 10.0).await;
+    }
+
+    let matches = fetch_matches(&mut pipeline, mode_pool("ranked")).await;
+    assert_eq!(matches.len(), 1);
+    // proposed tickets are pending
+    assert!(query(&mut pipeline.query, mode_pool("ranked"))
+        .await
+        .is_empty());
+    assert!(fetch_matches(&mut pipeline, mode_pool("ranked"))
+        .await
+        .is_empty());
+
+    pipeline
+        .backend
+        .release_tickets(tonic::Request::new(om::ReleaseTicketsRequest {
+            ticket_ids: matches[0].tickets.iter().map(|t| t.id.clone()).collect(),
+        }))
+        .await
+        .unwrap();
+    assert_eq!(
+        query(&mut pipeline.query, mode_pool("ranked")).await.len(),
+        2
+    );
+    assert_eq!(
+        fetch_matches(&mut pipeline, mode_pool("ranked"))
+            .await
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn deleted_tickets_are_not_found() {
+    let mut pipeline = start().await;
+    let ticket = create_ticket(&mut pipeline.frontend, "ranked", 10.0).await;
+    pipeline
+        .frontend
+        .delete_ticket(tonic::Request::new(om::DeleteTicketRequest {
+            ticket_id: ticket.id.clone(),
+        }))
+        .await
+        .unwrap();
+
+    let status = pipeline
+        .frontend
+        .get_ticket(tonic::Request::new(om::GetTicketRequest {
+            ticket_id: ticket.id,
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}